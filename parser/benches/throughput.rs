@@ -0,0 +1,83 @@
+#[macro_use]
+extern crate criterion;
+extern crate parser;
+
+use std::io::{BufReader, Cursor};
+
+use criterion::{Criterion, Throughput};
+
+use parser::bitreader::BitReader;
+use parser::nalunit::NalUnit;
+use parser::paramsets::ParameterSets;
+
+/* Baseline 320x240 SPS and PPS */
+const SPS: [u8; 8] = [0x67, 0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2];
+const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+/* IDR slice header, slice data follows */
+const IDR: [u8; 6] = [0x65, 0x88, 0x82, 0x00, 0x2d, 0x3f];
+
+/* Stream of IDR pictures with pseudo random slice data, about 16 MiB,
+ * with emulation prevention bytes where needed */
+fn synthetic_stream() -> Vec<u8> {
+    let mut stream = Vec::new();
+    let mut seed: u32 = 1;
+
+    stream.extend_from_slice(&[0, 0, 0, 1]);
+    stream.extend_from_slice(&SPS);
+    stream.extend_from_slice(&[0, 0, 0, 1]);
+    stream.extend_from_slice(&PPS);
+    while stream.len() < 16 << 20 {
+        stream.extend_from_slice(&[0, 0, 0, 1]);
+        stream.extend_from_slice(&IDR);
+        let mut zeroes = 0;
+        for _ in 0..20000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            /* Plenty of zero bytes to exercise emulation prevention */
+            let b = if seed >> 28 == 0 { 0 } else { (seed >> 16) as u8 };
+            if zeroes == 2 && b <= 3 {
+                stream.push(3);
+                zeroes = 0;
+            }
+            zeroes = if b == 0 { zeroes + 1 } else { 0 };
+            stream.push(b);
+        }
+        stream.push(0x80);
+    }
+
+    stream
+}
+
+fn parse_all<R: std::io::BufRead>(r: &mut BitReader<R>) -> usize {
+    let mut parameter_sets = ParameterSets::new();
+    let mut count = 0;
+
+    NalUnit::next(r).unwrap();
+    while !r.reached_end_of_data() {
+        let (mut nal, rbsp) = NalUnit::parse(r).unwrap();
+        let payload = nal.parse_payload(&rbsp, &parameter_sets).unwrap();
+        parameter_sets.update(&payload);
+        count += 1;
+    }
+
+    count
+}
+
+fn throughput(c: &mut Criterion) {
+    let stream = synthetic_stream();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.sample_size(10);
+
+    group.bench_function("slice", |b| b.iter(|| {
+        let mut reader = BitReader::new(&stream[..]);
+        parse_all(&mut reader)
+    }));
+    group.bench_function("bufreader", |b| b.iter(|| {
+        let mut reader = BitReader::new(BufReader::new(Cursor::new(&stream)));
+        parse_all(&mut reader)
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,329 @@
+use nalunit::{NalUnit, NalPayload};
+use slice::{SliceHeader, SliceType};
+use sei::SeiPayload;
+use paramsets::ParameterSets;
+use poc::{PicOrderCnt, PicOrderCntState};
+
+/// A NAL unit together with its payload, None when the payload could
+/// not be parsed.
+pub type AccessUnitNal = (NalUnit, Option<NalPayload>);
+
+/// The NAL units making up one access unit as described in 7.4.1.2.3,
+/// contains exactly one primary coded picture unless the stream is
+/// broken.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct AccessUnit {
+    pub nals: Vec<AccessUnitNal>,
+    /// From the access unit delimiter when present, otherwise the
+    /// lowest primary_pic_type of Table 7-5 allowing all slice types
+    /// of the primary coded picture.
+    pub primary_pic_type: u8,
+    /// The primary coded picture is an IDR picture.
+    pub idr: bool,
+    /// The access unit contains a recovery point SEI message.
+    pub recovery_point: bool,
+    /// Of the primary coded picture, None when the parameter sets it
+    /// refers to are missing.
+    pub pic_order_cnt: Option<PicOrderCnt>,
+}
+
+impl AccessUnit {
+    /// Decoding can start at this access unit.
+    pub fn random_access(&self) -> bool {
+        self.idr || self.recovery_point
+    }
+
+    /// Slice headers of the primary coded picture.
+    pub fn slices(&self) -> Vec<&SliceHeader> {
+        self.nals.iter()
+            .filter_map(|nal| match nal.1 {
+                Some(NalPayload::Slice(ref header))
+                    if header.redundant_pic_cnt == 0 => Some(header),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/* The parts of the first VCL NAL unit of a primary coded picture that
+ * are compared to detect the first VCL NAL unit of the next one */
+#[derive(Debug)]
+struct PictureId {
+    nal_ref_idc: u8,
+    idr_pic_flag: bool,
+    header: SliceHeader,
+}
+
+/// Groups NAL units into access units. NAL units are pushed in
+/// decoding order and an access unit is returned when the first NAL
+/// unit of the next access unit is pushed.
+#[derive(Debug, Default)]
+pub struct AccessUnitAggregator {
+    nals: Vec<AccessUnitNal>,
+    picture: Option<PictureId>,
+    slice_types: Vec<SliceType>,
+    pic_order_cnt: Option<PicOrderCnt>,
+    pic_order_cnt_state: PicOrderCntState,
+}
+
+/* Table 7-5, lowest primary_pic_type allowing the slice types */
+fn primary_pic_type(slice_types: &[SliceType]) -> u8 {
+    let has = |kind| slice_types.contains(&kind);
+    let intra = !has(SliceType::SI) && !has(SliceType::SP);
+    let switching = !has(SliceType::I) && !has(SliceType::P);
+
+    if has(SliceType::B) {
+        if intra { 2 } else { 7 }
+    }
+    else if intra {
+        if has(SliceType::P) { 1 } else { 0 }
+    }
+    else if switching {
+        if has(SliceType::SP) { 4 } else { 3 }
+    }
+    else if has(SliceType::P) || has(SliceType::SP) {
+        6
+    }
+    else {
+        5
+    }
+}
+
+/// Detection of the first VCL NAL unit of a primary coded picture as
+/// specified in 7.4.1.2.4. Fields that are not present in a slice
+/// header are zero so they can be compared regardless of the picture
+/// order count type.
+///
+/// Also treats a slice starting at macroblock 0 of the same colour
+/// plane as a new picture, this catches consecutive pictures with
+/// identical headers.
+fn is_new_picture(prev: &PictureId, nal: &NalUnit,
+                  header: &SliceHeader) -> bool {
+    let p = &prev.header;
+    let idr_pic_flag = nal.nal_unit_type == 5;
+
+    p.frame_num != header.frame_num ||
+        p.pic_parameter_set_id != header.pic_parameter_set_id ||
+        p.field_pic_flag != header.field_pic_flag ||
+        p.bottom_field_flag != header.bottom_field_flag ||
+        (prev.nal_ref_idc != nal.nal_ref_idc &&
+         (prev.nal_ref_idc == 0 || nal.nal_ref_idc == 0)) ||
+        p.pic_order_cnt_lsb != header.pic_order_cnt_lsb ||
+        p.delta_pic_order_cnt_bottom != header.delta_pic_order_cnt_bottom ||
+        p.delta_pic_order_cnt != header.delta_pic_order_cnt ||
+        prev.idr_pic_flag != idr_pic_flag ||
+        (idr_pic_flag && p.idr_pic_id != header.idr_pic_id) ||
+        (header.first_mb_in_slice == 0 &&
+         p.colour_plane_id == header.colour_plane_id)
+}
+
+impl AccessUnitAggregator {
+    pub fn new() -> AccessUnitAggregator {
+        AccessUnitAggregator {
+            nals: Vec::new(),
+            picture: None,
+            slice_types: Vec::new(),
+            pic_order_cnt: None,
+            pic_order_cnt_state: PicOrderCntState::new(),
+        }
+    }
+
+    /// Adds the next NAL unit, returns the previous access unit when
+    /// the NAL unit starts a new one. The parameter sets are used to
+    /// derive the picture order count of the pictures.
+    pub fn push(&mut self, nal: NalUnit, payload: Option<NalPayload>,
+                parameter_sets: &ParameterSets) -> Option<AccessUnit> {
+        let starts_new = match self.picture {
+            /* Everything before the first VCL NAL unit of the primary
+             * coded picture belongs to the access unit, an access unit
+             * delimiter is always first */
+            None => nal.nal_unit_type == 9 && !self.nals.is_empty(),
+            Some(ref picture) => match (nal.nal_unit_type, &payload) {
+                (6..=9, _) | (14..=18, _) => true,
+                (1, &Some(NalPayload::Slice(ref header))) |
+                (5, &Some(NalPayload::Slice(ref header))) =>
+                    header.redundant_pic_cnt == 0 &&
+                    is_new_picture(picture, &nal, header),
+                _ => false,
+            },
+        };
+
+        let access_unit = if starts_new { self.flush() } else { None };
+
+        if let Some(NalPayload::Slice(ref header)) = payload {
+            if header.redundant_pic_cnt == 0 {
+                if self.picture.is_none() {
+                    let sps = parameter_sets
+                        .pps(header.pic_parameter_set_id)
+                        .and_then(|pps| {
+                            parameter_sets.sps(pps.seq_parameter_set_id)
+                        });
+                    self.pic_order_cnt = sps.map(|sps| {
+                        self.pic_order_cnt_state.decode(&nal, header, sps)
+                    });
+                    self.picture = Some(PictureId {
+                        nal_ref_idc: nal.nal_ref_idc,
+                        idr_pic_flag: nal.nal_unit_type == 5,
+                        header: header.clone(),
+                    });
+                }
+                self.slice_types.push(header.slice_type());
+            }
+        }
+        self.nals.push((nal, payload));
+
+        access_unit
+    }
+
+    /// Returns the access unit being collected, call at end of stream.
+    pub fn flush(&mut self) -> Option<AccessUnit> {
+        if self.nals.is_empty() {
+            return None;
+        }
+
+        let nals: Vec<AccessUnitNal> = self.nals.drain(..).collect();
+        let picture = self.picture.take();
+        let slice_types: Vec<SliceType> = self.slice_types.drain(..).collect();
+
+        let mut delimiter_pic_type = None;
+        let mut recovery_point = false;
+        for nal in &nals {
+            match nal.1 {
+                Some(NalPayload::AccessUnitDelimiter(ref aud)) =>
+                    delimiter_pic_type = Some(aud.primary_pic_type),
+                Some(NalPayload::Sei(ref messages)) => {
+                    recovery_point |= messages.iter().any(|m| {
+                        matches!(m.payload, SeiPayload::RecoveryPoint(_))
+                    });
+                },
+                _ => (),
+            }
+        }
+
+        Some(AccessUnit {
+            nals,
+            primary_pic_type: delimiter_pic_type
+                .unwrap_or_else(|| primary_pic_type(&slice_types)),
+            idr: picture.is_some_and(|p| p.idr_pic_flag),
+            recovery_point,
+            pic_order_cnt: self.pic_order_cnt.take(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use bitreader::BitReader;
+    use paramsets::ParameterSets;
+    use super::*;
+
+    /* Baseline 320x240, 4 bit frame_num and 6 bit pic_order_cnt_lsb */
+    const SPS: [u8; 8] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2,
+    ];
+
+    const PPS: [u8; 4] = [
+        0x68, 0xce, 0x3c, 0x80,
+    ];
+
+    const AUD: [u8; 2] = [
+        0x09, 0x30,
+    ];
+
+    /* idr_pic_id 1 */
+    const IDR: [u8; 7] = [
+        0x65, 0x88, 0x82, 0x00, 0x2d, 0x3f, 0xff,
+    ];
+
+    /* frame_num 1, pic_order_cnt_lsb 2, first_mb_in_slice 0 */
+    const P1_FIRST: [u8; 5] = [
+        0x41, 0x9a, 0x21, 0x0a, 0x80,
+    ];
+
+    /* frame_num 1, pic_order_cnt_lsb 2, first_mb_in_slice 10 */
+    const P1_SECOND: [u8; 5] = [
+        0x41, 0x16, 0x68, 0x84, 0x2a,
+    ];
+
+    /* frame_num 2, pic_order_cnt_lsb 4, first_mb_in_slice 0 */
+    const P2: [u8; 5] = [
+        0x41, 0x9a, 0x42, 0x0a, 0x80,
+    ];
+
+    fn nal(buf: &[u8], parameter_sets: &mut ParameterSets) ->
+           (NalUnit, Option<NalPayload>) {
+        let mut reader = BitReader::new(Cursor::new(buf.to_vec()));
+        let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+        let payload = nal.parse_payload(&rbsp, parameter_sets).unwrap();
+        parameter_sets.update(&payload);
+
+        (nal, Some(payload))
+    }
+
+    fn push_all(nals: &[&[u8]]) -> Vec<AccessUnit> {
+        let mut parameter_sets = ParameterSets::new();
+        let mut aggregator = AccessUnitAggregator::new();
+        let mut access_units = Vec::new();
+
+        for buf in nals {
+            let (nal, payload) = nal(buf, &mut parameter_sets);
+            if let Some(access_unit) = aggregator.push(nal, payload,
+                                                       &parameter_sets) {
+                access_units.push(access_unit);
+            }
+        }
+        if let Some(access_unit) = aggregator.flush() {
+            access_units.push(access_unit);
+        }
+
+        access_units
+    }
+
+    #[test]
+    fn split_on_new_picture() {
+        let access_units = push_all(&[
+            &SPS, &PPS, &IDR, &P1_FIRST, &P1_SECOND, &P2,
+        ]);
+
+        assert_eq!(access_units.len(), 3);
+        assert_eq!(access_units[0].nals.len(), 3);
+        assert!(access_units[0].idr);
+        assert!(access_units[0].random_access());
+        assert_eq!(access_units[0].primary_pic_type, 0);
+        assert_eq!(access_units[1].nals.len(), 2);
+        assert_eq!(access_units[1].slices().len(), 2);
+        assert!(!access_units[1].idr);
+        assert_eq!(access_units[1].primary_pic_type, 1);
+        assert_eq!(access_units[2].nals.len(), 1);
+        let pic_order_cnts: Vec<i64> = access_units.iter()
+            .map(|au| au.pic_order_cnt.unwrap().pic_order_cnt())
+            .collect();
+        assert_eq!(pic_order_cnts, [0, 2, 4]);
+    }
+
+    #[test]
+    fn split_on_non_vcl() {
+        let access_units = push_all(&[
+            &SPS, &PPS, &IDR, &AUD, &P1_FIRST, &PPS, &P2,
+        ]);
+
+        assert_eq!(access_units.len(), 3);
+        assert_eq!(access_units[1].nals.len(), 2);
+        assert_eq!(access_units[1].primary_pic_type, 1);
+        assert_eq!(access_units[2].nals.len(), 2);
+    }
+
+    #[test]
+    fn primary_pic_type_from_slice_types() {
+        assert_eq!(primary_pic_type(&[SliceType::I]), 0);
+        assert_eq!(primary_pic_type(&[SliceType::I, SliceType::B]), 2);
+        assert_eq!(primary_pic_type(&[SliceType::SI]), 3);
+        assert_eq!(primary_pic_type(&[SliceType::SP, SliceType::SI]), 4);
+        assert_eq!(primary_pic_type(&[SliceType::I, SliceType::SI]), 5);
+        assert_eq!(primary_pic_type(&[SliceType::P, SliceType::SP]), 6);
+        assert_eq!(primary_pic_type(&[SliceType::SP, SliceType::B]), 7);
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::prelude::*;
+
+use bitreader::BitReader;
+use bitwriter::BitWriter;
+use slice::SliceType;
+use super::*;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct AccessUnitDelimiter {
+    pub primary_pic_type: u8,
+}
+
+impl AccessUnitDelimiter {
+    /// Parses access_unit_delimiter_rbsp() as specified in 7.3.2.4.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) ->
+                             Result<AccessUnitDelimiter> {
+        let primary_pic_type = r.name("primary_pic_type").u8(3)?;
+        r.rbsp_trailing_bits()?;
+
+        Ok(AccessUnitDelimiter { primary_pic_type })
+    }
+
+    /// Writes access_unit_delimiter_rbsp(), the inverse of `parse`.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        w.u8(3, self.primary_pic_type)?;
+        w.rbsp_trailing_bits()
+    }
+
+    /// Slice types that may be present in the primary coded picture,
+    /// Table 7-5.
+    pub fn slice_types(&self) -> &'static [SliceType] {
+        match self.primary_pic_type {
+            0 => &[SliceType::I],
+            1 => &[SliceType::I, SliceType::P],
+            2 => &[SliceType::I, SliceType::P, SliceType::B],
+            3 => &[SliceType::SI],
+            4 => &[SliceType::SI, SliceType::SP],
+            5 => &[SliceType::I, SliceType::SI],
+            6 => &[SliceType::I, SliceType::SI, SliceType::P, SliceType::SP],
+            _ => &[SliceType::I, SliceType::SI, SliceType::P, SliceType::SP,
+                   SliceType::B],
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let buf: [u8; 1] = [0b01010000];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let aud = AccessUnitDelimiter::parse(&mut reader).unwrap();
+
+        assert_eq!(aud.primary_pic_type, 2);
+        assert_eq!(aud.slice_types(),
+                   [SliceType::I, SliceType::P, SliceType::B]);
+    }
+
+    #[test]
+    fn parse_no_stop_bit() {
+        let buf: [u8; 1] = [0b01000000];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let res = AccessUnitDelimiter::parse(&mut reader);
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,478 @@
+use bitreader::BitReader;
+use bitwriter::BitWriter;
+use nalunit::{NalUnit, NalPayload};
+use paramsets::ParameterSets;
+use super::*;
+
+/// AVCDecoderConfigurationRecord as specified in ISO/IEC 14496-15
+/// 5.3.3.1, the avcC box of MP4 and the codec private data of
+/// Matroska and FLV.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct AvcConfiguration {
+    pub configuration_version: u8,
+    pub avc_profile_indication: u8,
+    pub profile_compatibility: u8,
+    pub avc_level_indication: u8,
+    pub length_size_minus_one: u8,
+    /// SPS NAL units, header included
+    pub sequence_parameter_sets: Vec<Vec<u8>>,
+    /// PPS NAL units, header included
+    pub picture_parameter_sets: Vec<Vec<u8>>,
+    /// Present for the High profiles, when not left out by the writer
+    pub extension: Option<AvcConfigurationExtension>,
+}
+
+/// Fields of the configuration record for profile_idc 100, 110, 122
+/// and 144.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct AvcConfigurationExtension {
+    pub chroma_format: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    /// SPS extension NAL units, header included
+    pub sequence_parameter_set_ext: Vec<Vec<u8>>,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Avcc();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn check_length_size(length_size: usize) -> Result<()> {
+    match length_size {
+        1 | 2 | 4 => Ok(()),
+        _ => Err(err("NAL unit length size is not 1, 2 or 4")),
+    }
+}
+
+/* Parameter set NAL units, each preceded by a 16 bit length */
+fn parse_nal_units(r: &mut BitReader<&[u8]>, count: usize,
+                   name: &'static str) -> Result<Vec<Vec<u8>>> {
+    let mut nal_units = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let length = r.name(name).u32(16)?;
+        let mut nal_unit = Vec::with_capacity(length as usize);
+        for _ in 0..length {
+            nal_unit.push(r.b()?);
+        }
+        nal_units.push(nal_unit);
+    }
+
+    Ok(nal_units)
+}
+
+fn write_nal_units(w: &mut BitWriter, nal_units: &[Vec<u8>]) -> Result<()> {
+    for nal_unit in nal_units {
+        if nal_unit.len() > 0xffff {
+            return Err(err("Parameter set longer than 65535 bytes"));
+        }
+        w.u32(16, nal_unit.len() as u32)?;
+        for byte in nal_unit {
+            w.b(*byte)?;
+        }
+    }
+
+    Ok(())
+}
+
+/* NAL units of an Annex B byte stream, data before the first start
+ * code is dropped */
+fn annex_b_nal_units(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut r = BitReader::new(data);
+    let mut nal_units = Vec::new();
+
+    let mut found = r.skip_to_start_code()?;
+    while found {
+        let mut nal_unit = Vec::new();
+        found = r.read_to_start_code(&mut nal_unit)?;
+        if !nal_unit.is_empty() {
+            nal_units.push(nal_unit);
+        }
+    }
+
+    Ok(nal_units)
+}
+
+impl AvcConfiguration {
+    pub fn parse(data: &[u8]) -> Result<AvcConfiguration> {
+        let mut r = BitReader::new_rbsp(data);
+
+        let configuration_version = r.name("configurationVersion").b()?;
+        if configuration_version != 1 {
+            return Err(err("configurationVersion is not 1"));
+        }
+        let avc_profile_indication = r.name("AVCProfileIndication").b()?;
+        let profile_compatibility = r.name("profile_compatibility").b()?;
+        let avc_level_indication = r.name("AVCLevelIndication").b()?;
+        r.name("reserved").u8(6)?;
+        let length_size_minus_one = r.name("lengthSizeMinusOne").u8(2)?;
+        if length_size_minus_one == 2 {
+            return Err(err("lengthSizeMinusOne is 2"));
+        }
+        r.name("reserved").u8(3)?;
+
+        let count = r.name("numOfSequenceParameterSets").u8(5)? as usize;
+        let sequence_parameter_sets =
+            parse_nal_units(&mut r, count, "sequenceParameterSetLength")?;
+        let count = r.name("numOfPictureParameterSets").b()? as usize;
+        let picture_parameter_sets =
+            parse_nal_units(&mut r, count, "pictureParameterSetLength")?;
+
+        /* Older writers leave the extension out */
+        let mut extension = None;
+        let high_profile =
+            matches!(avc_profile_indication, 100 | 110 | 122 | 144);
+        if high_profile && r.bit_pos() < data.len() * 8 {
+            r.name("reserved").u8(6)?;
+            let chroma_format = r.name("chroma_format").u8(2)?;
+            r.name("reserved").u8(5)?;
+            let bit_depth_luma_minus8 =
+                r.name("bit_depth_luma_minus8").u8(3)?;
+            r.name("reserved").u8(5)?;
+            let bit_depth_chroma_minus8 =
+                r.name("bit_depth_chroma_minus8").u8(3)?;
+            let count = r.name("numOfSequenceParameterSetExt").b()? as usize;
+            let sequence_parameter_set_ext = parse_nal_units(
+                &mut r, count, "sequenceParameterSetExtLength")?;
+            extension = Some(AvcConfigurationExtension {
+                chroma_format,
+                bit_depth_luma_minus8,
+                bit_depth_chroma_minus8,
+                sequence_parameter_set_ext,
+            });
+        }
+
+        Ok(AvcConfiguration {
+            configuration_version,
+            avc_profile_indication,
+            profile_compatibility,
+            avc_level_indication,
+            length_size_minus_one,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+            extension,
+        })
+    }
+
+    /// Builds the record from the SPS, SPS extension and PPS NAL units
+    /// of an Annex B byte stream. Profile and level are those of the
+    /// first SPS, repeated parameter sets are included once.
+    pub fn from_annex_b(data: &[u8], length_size: usize)
+                        -> Result<AvcConfiguration> {
+        check_length_size(length_size)?;
+        let mut sequence_parameter_sets = Vec::new();
+        let mut picture_parameter_sets = Vec::new();
+        let mut sequence_parameter_set_ext = Vec::new();
+
+        for nal_unit in annex_b_nal_units(data)? {
+            let nal_units = match nal_unit[0] & 0x1f {
+                7 => &mut sequence_parameter_sets,
+                8 => &mut picture_parameter_sets,
+                13 => &mut sequence_parameter_set_ext,
+                _ => continue,
+            };
+            if !nal_units.contains(&nal_unit) {
+                nal_units.push(nal_unit);
+            }
+        }
+
+        let sps = match sequence_parameter_sets.first() {
+            Some(nal_unit) => {
+                let mut r = BitReader::new(&nal_unit[..]);
+                let (mut nal, rbsp) = NalUnit::parse(&mut r)?;
+                match nal.parse_payload(&rbsp, &ParameterSets::new())? {
+                    NalPayload::SequenceParameterSet(sps) => sps,
+                    _ => return Err(err("Not a parameter set")),
+                }
+            },
+            None => return Err(err("No SPS in stream")),
+        };
+        let first = &sequence_parameter_sets[0];
+        if first.len() < 4 {
+            return Err(err("SPS too short"));
+        }
+        let extension =
+            if matches!(sps.profile_idc, 100 | 110 | 122 | 144) {
+                Some(AvcConfigurationExtension {
+                    chroma_format: sps.chroma_format_idc,
+                    bit_depth_luma_minus8: sps.bit_depth_luma_minus8,
+                    bit_depth_chroma_minus8: sps.bit_depth_chroma_minus8,
+                    sequence_parameter_set_ext,
+                })
+            }
+            else {
+                None
+            };
+
+        Ok(AvcConfiguration {
+            configuration_version: 1,
+            avc_profile_indication: sps.profile_idc,
+            /* Constraint flags and reserved_zero_2bits */
+            profile_compatibility: first[2],
+            avc_level_indication: sps.level_idc,
+            length_size_minus_one: length_size as u8 - 1,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+            extension,
+        })
+    }
+
+    /// Writes the record, the contents of an avcC box.
+    pub fn write(&self) -> Result<Vec<u8>> {
+        let mut w = BitWriter::new_rbsp();
+
+        w.b(self.configuration_version)?;
+        w.b(self.avc_profile_indication)?;
+        w.b(self.profile_compatibility)?;
+        w.b(self.avc_level_indication)?;
+        /* reserved */
+        w.u8(6, 0x3f)?;
+        w.u8(2, self.length_size_minus_one)?;
+        w.u8(3, 0x07)?;
+        if self.sequence_parameter_sets.len() > 31 ||
+           self.picture_parameter_sets.len() > 255 {
+            return Err(err("Too many parameter sets"));
+        }
+        w.u8(5, self.sequence_parameter_sets.len() as u8)?;
+        write_nal_units(&mut w, &self.sequence_parameter_sets)?;
+        w.b(self.picture_parameter_sets.len() as u8)?;
+        write_nal_units(&mut w, &self.picture_parameter_sets)?;
+
+        if let Some(ref extension) = self.extension {
+            if extension.sequence_parameter_set_ext.len() > 255 {
+                return Err(err("Too many parameter sets"));
+            }
+            w.u8(6, 0x3f)?;
+            w.u8(2, extension.chroma_format)?;
+            w.u8(5, 0x1f)?;
+            w.u8(3, extension.bit_depth_luma_minus8)?;
+            w.u8(5, 0x1f)?;
+            w.u8(3, extension.bit_depth_chroma_minus8)?;
+            w.b(extension.sequence_parameter_set_ext.len() as u8)?;
+            write_nal_units(&mut w, &extension.sequence_parameter_set_ext)?;
+        }
+
+        Ok(w.into_bytes())
+    }
+
+    /// Number of bytes of the length preceding each NAL unit in
+    /// samples.
+    pub fn length_size(&self) -> usize {
+        self.length_size_minus_one as usize + 1
+    }
+
+    /// Parses the SPS and PPS of the record.
+    pub fn parameter_sets(&self) -> Result<ParameterSets> {
+        let mut parameter_sets = ParameterSets::new();
+
+        for nal_unit in self.sequence_parameter_sets.iter()
+            .chain(self.picture_parameter_sets.iter()) {
+            let mut r = BitReader::new(&nal_unit[..]);
+            let (mut nal, rbsp) = NalUnit::parse(&mut r)?;
+            match nal.parse_payload(&rbsp, &parameter_sets)? {
+                payload @ NalPayload::SequenceParameterSet(_) |
+                payload @ NalPayload::PictureParameterSet(_) => {
+                    parameter_sets.update(&payload);
+                },
+                _ => return Err(err("Not a parameter set")),
+            }
+        }
+
+        Ok(parameter_sets)
+    }
+}
+
+/// Splits `data` into NAL units each preceded by its length in
+/// `length_size` bytes, as in MP4 samples.
+pub fn nal_units(data: &[u8], length_size: usize) -> Result<Vec<&[u8]>> {
+    let mut nal_units = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        if pos + length_size > data.len() {
+            return Err(err("NAL unit length past end of data"));
+        }
+        let length = data[pos..pos + length_size].iter()
+            .fold(0, |length, b| length << 8 | *b as usize);
+        pos += length_size;
+        if pos + length > data.len() {
+            return Err(err("NAL unit past end of data"));
+        }
+        nal_units.push(&data[pos..pos + length]);
+        pos += length;
+    }
+
+    Ok(nal_units)
+}
+
+/// Converts an Annex B byte stream to NAL units each preceded by its
+/// length in `length_size` bytes. Data before the first start code and
+/// zero bytes after NAL units are dropped.
+pub fn annex_b_to_avcc(data: &[u8], length_size: usize) -> Result<Vec<u8>> {
+    check_length_size(length_size)?;
+    let mut avcc = Vec::with_capacity(data.len());
+
+    for nal_unit in annex_b_nal_units(data)? {
+        if length_size < 4 && nal_unit.len() >> (8 * length_size) != 0 {
+            return Err(err("NAL unit too long for the length size"));
+        }
+        for i in (0..length_size).rev() {
+            avcc.push((nal_unit.len() >> (8 * i)) as u8);
+        }
+        avcc.extend_from_slice(&nal_unit);
+    }
+
+    Ok(avcc)
+}
+
+/// Converts NAL units each preceded by its length in `length_size`
+/// bytes to an Annex B byte stream with four byte start codes.
+pub fn avcc_to_annex_b(data: &[u8], length_size: usize) -> Result<Vec<u8>> {
+    check_length_size(length_size)?;
+    let mut annex_b = Vec::with_capacity(data.len() + data.len() / 8);
+
+    for nal_unit in nal_units(data, length_size)? {
+        annex_b.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        annex_b.extend_from_slice(nal_unit);
+    }
+
+    Ok(annex_b)
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use bitreader::Framing;
+    use nalunit::NalUnit;
+    use super::*;
+
+    /// Baseline 320x240 SPS and PPS.
+    pub const AVCC: [u8; 23] = [
+        0x01, 0x42, 0xc0, 0x1e, 0xff, 0xe1, 0x00, 0x08,
+        0x67, 0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2,
+        0x01, 0x00, 0x04, 0x68, 0xce, 0x3c, 0x80,
+    ];
+
+    /// NAL unit types of `data`, NAL units with a length prefix of
+    /// `length_size` bytes.
+    pub fn nal_types(data: &[u8], length_size: u8) -> Vec<u8> {
+        let framing = Framing::Length(length_size);
+        let mut bitreader = BitReader::with_framing(data, framing).unwrap();
+        let mut types = Vec::new();
+
+        NalUnit::next(&mut bitreader).unwrap();
+        while !bitreader.reached_end_of_data() {
+            let (nal, _) = NalUnit::parse(&mut bitreader).unwrap();
+            types.push(nal.nal_unit_type);
+        }
+
+        types
+    }
+
+    #[test]
+    fn parse() {
+        let config = AvcConfiguration::parse(&AVCC).unwrap();
+
+        assert_eq!(config.avc_profile_indication, 66);
+        assert_eq!(config.avc_level_indication, 30);
+        assert_eq!(config.length_size(), 4);
+        assert_eq!(config.sequence_parameter_sets.len(), 1);
+        assert_eq!(config.picture_parameter_sets,
+                   [vec![0x68, 0xce, 0x3c, 0x80]]);
+        assert!(config.extension.is_none());
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let res = AvcConfiguration::parse(&AVCC[..12]);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn parameter_sets() {
+        let config = AvcConfiguration::parse(&AVCC).unwrap();
+
+        let parameter_sets = config.parameter_sets().unwrap();
+
+        let sps = parameter_sets.sps(0).unwrap();
+        assert_eq!((sps.width(), sps.height()), (320, 240));
+        assert_eq!(parameter_sets.pps(0).unwrap().seq_parameter_set_id, 0);
+    }
+
+    #[test]
+    fn write() {
+        let config = AvcConfiguration::parse(&AVCC).unwrap();
+
+        assert_eq!(config.write().unwrap(), AVCC);
+    }
+
+    #[test]
+    fn from_annex_b() {
+        let mut stream = vec![0x00, 0x00, 0x00, 0x01, 0x09, 0xf0];
+        for _ in 0..2 {
+            stream.extend_from_slice(&[0x00, 0x00, 0x01]);
+            stream.extend_from_slice(&AVCC[8..16]);
+            stream.extend_from_slice(&[0x00, 0x00, 0x01]);
+            stream.extend_from_slice(&AVCC[19..]);
+            stream.extend_from_slice(&[0x00, 0x00, 0x01, 0x65, 0x88, 0x84]);
+        }
+
+        let config = AvcConfiguration::from_annex_b(&stream, 4).unwrap();
+
+        assert_eq!(config, AvcConfiguration::parse(&AVCC).unwrap());
+        assert!(AvcConfiguration::from_annex_b(&stream[..6], 4).is_err());
+        assert!(AvcConfiguration::from_annex_b(&stream, 3).is_err());
+    }
+
+    #[test]
+    fn from_annex_b_high_profile() {
+        /* High 1920x1080 */
+        let stream = [
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x28,
+            0xac, 0x2d, 0x94, 0x07, 0x80, 0x22, 0x7e, 0x54,
+        ];
+
+        let config = AvcConfiguration::from_annex_b(&stream, 2).unwrap();
+        let bytes = config.write().unwrap();
+
+        assert_eq!(config.extension, Some(AvcConfigurationExtension {
+            chroma_format: 1,
+            bit_depth_luma_minus8: 0,
+            bit_depth_chroma_minus8: 0,
+            sequence_parameter_set_ext: Vec::new(),
+        }));
+        assert_eq!(AvcConfiguration::parse(&bytes).unwrap(), config);
+        assert_eq!(bytes[4], 0xfd);
+    }
+
+    #[test]
+    fn convert_framing() {
+        let annex_b = [
+            0x00, 0x00, 0x00, 0x01, 0x09, 0xf0, 0x00,
+            0x00, 0x00, 0x01, 0x68, 0xce, 0x3c, 0x80,
+        ];
+        let avcc = [0x02, 0x09, 0xf0, 0x04, 0x68, 0xce, 0x3c, 0x80];
+
+        assert_eq!(annex_b_to_avcc(&annex_b, 1).unwrap(), avcc);
+        assert_eq!(avcc_to_annex_b(&avcc, 1).unwrap(), annex_b);
+        assert!(annex_b_to_avcc(&[0x00, 0x00, 0x01, 0xff].repeat(2), 3)
+                .is_err());
+    }
+
+    #[test]
+    fn split_nal_units() {
+        let data = [0x00, 0x02, 0x09, 0xf0, 0x00, 0x01, 0x0a];
+
+        let nal_units = nal_units(&data, 2).unwrap();
+
+        assert_eq!(nal_units, [&[0x09, 0xf0][..], &[0x0a][..]]);
+        assert!(super::nal_units(&data[..6], 2).is_err());
+    }
+}
@@ -1,10 +1,32 @@
 use std::io::prelude::*;
-use std::io::SeekFrom;
-use std::error::Error;
+use std::io::BufReader;
+use std::convert::TryInto;
 use std;
+use std::mem;
 
+use memchr::memchr;
+
+use trace::{Descriptor, TraceElement};
 use super::*;
 
+/// How NAL units are delimited in the data read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// Byte stream format of Annex B, NAL units follow start code
+    /// prefixes
+    AnnexB,
+    /// NAL units are preceded by their length in 1, 2 or 4 bytes, as
+    /// in MP4, Matroska and FLV
+    Length(u8),
+}
+
+/// Reads bits from a buffered byte source. Reading from a slice, or a
+/// `Cursor` over one, does not copy the data, other sources are
+/// wrapped in a `BufReader` by `buffered`.
+///
+/// Fixed length reads of 8 bits or more at a byte boundary load the
+/// bytes at once, shorter reads and Exp-Golomb codes are read bit by
+/// bit from the buffered byte.
 pub struct BitReader<R> {
     bits: u8,
     valid_bits: u8,
@@ -12,55 +34,110 @@ pub struct BitReader<R> {
     reader: R,
     pub pos: usize,
     end_of_data: bool,
+    /* Removes emulation_prevention_three_byte when set */
+    emulation_prevention: bool,
+    /* Syntax element being read, or last read, and its bit position */
+    element: Option<&'static str>,
+    element_pos: usize,
+    /* Syntax elements read, when tracing */
+    trace: Option<Vec<TraceElement>>,
+    /* Last start code skipped was preceded by a zero_byte */
+    long_start_code: bool,
+    framing: Framing,
+    /* Position after the current NAL unit, with length framing */
+    nal_end: usize,
+    /* Bit position of the rbsp_stop_one_bit, when known */
+    rbsp_length: Option<usize>,
 }
 
 fn err(text: &str) -> ParserError {
     let description = String::from(text);
-    let error = BitReaderError { description };
+    let error = BitReaderError { description, location: None };
 
     ParserError::BitReaderError(error)
 }
 
+fn io_err(e: std::io::Error) -> ParserError {
+    err(&e.to_string())
+}
+
 /* Ensures that bits exists */
-fn ensure<R: Read>(reader: &mut BitReader<R>) -> Result<()> {
-    if reader.valid_bits > 0 {
-        return Ok(())
-    }
+fn ensure<R: BufRead>(reader: &mut BitReader<R>) -> Result<()> {
+    while reader.valid_bits == 0 {
+        let byte = match reader.reader.fill_buf() {
+            Ok(buf) => buf.first().cloned(),
+            Err(e) => return Err(io_err(e)),
+        };
+        let byte = match byte {
+            Some(byte) => byte,
+            /* Zero bytes read, no more data */
+            None => {
+                reader.end_of_data = true;
+                return Err(ParserError::BitReaderEndOfStream());
+            },
+        };
+        reader.reader.consume(1);
+        reader.pos += 1;
+
+        if reader.emulation_prevention && reader.num_zeroes == 2 &&
+           byte == 0x03 {
+            reader.num_zeroes = 0;
+            continue;
+        }
+        else if byte == 0x00 {
+            reader.num_zeroes += 1;
+        }
+        else {
+            reader.num_zeroes = 0;
+        }
 
-    let mut buf: [u8; 1] = [0];
-    match reader.reader.read(&mut buf) {
-        Ok(1) => {
-            reader.bits = buf[0];
-            reader.valid_bits = 8;
-            reader.pos += 1;
+        reader.bits = byte;
+        reader.valid_bits = 8;
+    }
 
-            if reader.num_zeroes == 2 &&
-                reader.bits == 0x03 {
+    Ok(())
+}
 
-                reader.num_zeroes = 0;
-                reader.bits = 0;
-                return ensure(reader)
-            }
-            else if reader.bits == 0x00 {
-                reader.num_zeroes += 1;
-            }
-            else {
-                reader.num_zeroes = 0;
-            }
+/* Reads n bits, at least 8, with a single 64 bit load when at a byte
+ * boundary and the bytes are available in the buffer. Returns None
+ * when the bytes might contain an emulation_prevention_three_byte or
+ * are not buffered. */
+fn read_word<R: BufRead>(reader: &mut BitReader<R>, n: u8) ->
+                         Result<Option<u64>> {
+    let num_bytes = (n as usize).div_ceil(8);
+    let (word, last) = {
+        let buf = reader.reader.fill_buf().map_err(io_err)?;
+        if buf.len() < 8 {
+            return Ok(None);
+        }
+        let bytes = &buf[..num_bytes];
+        if reader.emulation_prevention && bytes.iter().any(|b| *b <= 0x03) {
+            return Ok(None);
+        }
+        let word = u64::from_be_bytes(buf[..8].try_into().unwrap());
+        (word, bytes[num_bytes - 1])
+    };
+    reader.reader.consume(num_bytes);
+    reader.pos += num_bytes;
+    reader.num_zeroes = 0;
+
+    /* Keep the unread bits of the last byte */
+    let rest = (num_bytes * 8 - n as usize) as u8;
+    if rest > 0 {
+        reader.bits = last << (8 - rest);
+        reader.valid_bits = rest;
+    }
 
-            return Ok(());
-        },
-        /* Zero bytes read, no more data */
-        Ok(_) => {
-            reader.end_of_data = true;
-            return Err(ParserError::BitReaderEndOfStream());
-        },
-        Err(e) => Err(err(e.description())),
+    if n == 64 {
+        Ok(Some(word))
+    }
+    else {
+        Ok(Some(word >> (64 - n)))
     }
 }
 
 /* Reads n number of bits into unsigned */
-fn read<R: Read>(reader: &mut BitReader<R>, n: u8) -> Result<u64> {
+fn read<R: BufRead>(reader: &mut BitReader<R>, n: u8) -> Result<u64> {
     let mut requested_bits = n;
     let mut u: u64 = 0;
 
@@ -68,6 +145,12 @@ fn read<R: Read>(reader: &mut BitReader<R>, n: u8) -> Result<u64> {
         return Err(err("too many bits, > 64"));
     }
 
+    if reader.valid_bits == 0 && n >= 8 {
+        if let Some(u) = read_word(reader, n)? {
+            return Ok(u);
+        }
+    }
+
     while requested_bits > 0 {
         ensure(reader)?;
         if requested_bits >= reader.valid_bits {
@@ -90,7 +173,54 @@ fn read<R: Read>(reader: &mut BitReader<R>, n: u8) -> Result<u64> {
     Ok(u)
 }
 
-impl<R: Read> BitReader<R> {
+/* Reads an Exp-Golomb code */
+fn read_ue<R: BufRead>(reader: &mut BitReader<R>) -> Result<u64> {
+    let mut leading_zeroes: i32 = -1;
+    let mut bit = 0;
+
+    while bit == 0 {
+        bit = read(reader, 1)?;
+        leading_zeroes += 1;
+    }
+
+    let bits = read(reader, leading_zeroes as u8)?;
+
+    Ok(2u64.pow(leading_zeroes as u32) - 1 + bits)
+}
+
+/* Number of zero bytes at the end of buf */
+fn trailing_zeroes(buf: &[u8]) -> usize {
+    buf.iter().rev().take_while(|b| **b == 0).count()
+}
+
+/// Length in bits of the RBSP data in `rbsp`, the position of the
+/// last set bit which is the rbsp_stop_one_bit. 0 when no bit is set.
+pub fn rbsp_length(rbsp: &[u8]) -> usize {
+    match rbsp.iter().rposition(|b| *b != 0) {
+        Some(i) => i * 8 + 7 - rbsp[i].trailing_zeros() as usize,
+        None => 0,
+    }
+}
+
+impl<R: Read> BitReader<BufReader<R>> {
+    /// Reader of a byte stream from a source that is not buffered, see
+    /// `new`.
+    pub fn buffered(r: R) -> BitReader<BufReader<R>> {
+        BitReader::new(BufReader::new(r))
+    }
+}
+
+impl<'a> BitReader<&'a [u8]> {
+    /// Reader of the RBSP data in `rbsp`, see `with_rbsp_length`. The
+    /// length is found from the rbsp_stop_one_bit.
+    pub fn from_rbsp(rbsp: &'a [u8]) -> BitReader<&'a [u8]> {
+        BitReader::with_rbsp_length(rbsp, rbsp_length(rbsp))
+    }
+}
+
+impl<R: BufRead> BitReader<R> {
+    /// Reader of a byte stream or of NAL units as coded in the stream,
+    /// emulation prevention bytes are removed.
     pub fn new(r: R) -> BitReader<R> {
         BitReader {
             bits: 0,
@@ -98,13 +228,275 @@ impl<R: Read> BitReader<R> {
             num_zeroes: 0,
             reader: r,
             pos: 0,
-            end_of_data: false
+            end_of_data: false,
+            emulation_prevention: true,
+            element: None,
+            element_pos: 0,
+            trace: None,
+            long_start_code: false,
+            framing: Framing::AnnexB,
+            nal_end: 0,
+            rbsp_length: None,
+        }
+    }
+
+    /// Reader of NAL units delimited by `framing`, emulation prevention
+    /// bytes are removed. Fails for a length size other than 1, 2 or 4.
+    pub fn with_framing(r: R, framing: Framing) -> Result<BitReader<R>> {
+        match framing {
+            Framing::Length(1) | Framing::Length(2) | Framing::Length(4) |
+            Framing::AnnexB => (),
+            Framing::Length(_) => {
+                return Err(err("NAL unit length size is not 1, 2 or 4"));
+            },
+        }
+
+        Ok(BitReader {
+            framing,
+            ..BitReader::new(r)
+        })
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Reader of RBSP data where emulation prevention bytes already
+    /// have been removed.
+    pub fn new_rbsp(r: R) -> BitReader<R> {
+        BitReader {
+            emulation_prevention: false,
+            ..BitReader::new(r)
+        }
+    }
+
+    /// Reader of RBSP data of `length` bits, as returned by
+    /// `rbsp_length`, where emulation prevention bytes already have
+    /// been removed. `more_rbsp_data` is answered from the length.
+    pub fn with_rbsp_length(r: R, length: usize) -> BitReader<R> {
+        BitReader {
+            rbsp_length: Some(length),
+            ..BitReader::new_rbsp(r)
+        }
+    }
+
+    /* Scans the bytes for the next start code prefix 0x000001, bytes
+     * before it are passed to `f` and the start code is skipped.
+     * Returns false when reaching end of data. */
+    fn scan_start_code<F: FnMut(&[u8])>(&mut self, mut f: F) ->
+                                        Result<bool> {
+        /* Zero bytes right before the buffer */
+        let mut zeroes = 0;
+
+        self.valid_bits = 0;
+        self.num_zeroes = 0;
+        loop {
+            let (found, used) = {
+                let buf = self.reader.fill_buf().map_err(io_err)?;
+                if buf.is_empty() {
+                    self.end_of_data = true;
+                    return Ok(false);
+                }
+                match memchr(0x01, buf) {
+                    Some(i) => {
+                        let before = &buf[..i];
+                        let mut num_zeroes = trailing_zeroes(before);
+                        if num_zeroes == i {
+                            num_zeroes += zeroes;
+                        }
+                        f(before);
+                        if num_zeroes < 2 {
+                            f(&buf[i..i + 1]);
+                        }
+                        zeroes = 0;
+                        self.long_start_code = num_zeroes >= 3;
+                        (num_zeroes >= 2, i + 1)
+                    },
+                    None => {
+                        let num_zeroes = trailing_zeroes(buf);
+                        zeroes = if num_zeroes == buf.len() {
+                            zeroes + num_zeroes
+                        }
+                        else {
+                            num_zeroes
+                        };
+                        f(buf);
+                        (false, buf.len())
+                    },
+                }
+            };
+            self.reader.consume(used);
+            self.pos += used;
+            if found {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Skips bytes up to and including the next start code prefix,
+    /// drops any bits left of the current byte.
+    /// Returns false when reaching end of data.
+    pub fn skip_to_start_code(&mut self) -> Result<bool> {
+        self.scan_start_code(|_| ())
+    }
+
+    /// Appends the bytes up to the next start code prefix, or end of
+    /// data, to `bytes` and skips the start code. Trailing zero bytes,
+    /// trailing_zero_8bits or the zero_byte of the next start code, are
+    /// not included. The bytes are copied as is, including emulation
+    /// prevention bytes, and any bits left of the current byte are
+    /// dropped.
+    /// Returns false when reaching end of data.
+    pub fn read_to_start_code(&mut self, bytes: &mut Vec<u8>) ->
+                              Result<bool> {
+        let found = self.scan_start_code(|buf| bytes.extend_from_slice(buf))?;
+        let len = bytes.len() - trailing_zeroes(bytes);
+        bytes.truncate(len);
+
+        Ok(found)
+    }
+
+    /* Reads the length preceding the next NAL unit, skipping empty
+     * ones. Returns false when reaching end of data. */
+    fn read_nal_length(&mut self, length_size: u8) -> Result<bool> {
+        self.valid_bits = 0;
+        self.num_zeroes = 0;
+        loop {
+            let mut length = 0;
+            for i in 0..length_size {
+                let byte = self.reader.fill_buf().map_err(io_err)?
+                    .first().cloned();
+                let byte = match byte {
+                    Some(byte) => byte,
+                    None => {
+                        self.end_of_data = true;
+                        if i == 0 {
+                            return Ok(false);
+                        }
+                        return Err(err("NAL unit length past end of data"));
+                    },
+                };
+                self.reader.consume(1);
+                self.pos += 1;
+                length = length << 8 | byte as usize;
+            }
+            if length > 0 {
+                self.nal_end = self.pos + length;
+                return Ok(true);
+            }
+        }
+    }
+
+    /* Passes the bytes up to the end of the current NAL unit to `f` */
+    fn scan_nal_end<F: FnMut(&[u8])>(&mut self, mut f: F) -> Result<()> {
+        self.valid_bits = 0;
+        self.num_zeroes = 0;
+        while self.pos < self.nal_end {
+            let used = {
+                let buf = self.reader.fill_buf().map_err(io_err)?;
+                if buf.is_empty() {
+                    self.end_of_data = true;
+                    return Err(err("NAL unit past end of data"));
+                }
+                let used = buf.len().min(self.nal_end - self.pos);
+                f(&buf[..used]);
+                used
+            };
+            self.reader.consume(used);
+            self.pos += used;
+        }
+
+        Ok(())
+    }
+
+    /// Skips bytes up to the start of the next NAL unit, the next start
+    /// code prefix or the next length, and drops any bits left of the
+    /// current byte.
+    /// Returns false when reaching end of data.
+    pub fn skip_to_nal_unit(&mut self) -> Result<bool> {
+        match self.framing {
+            Framing::AnnexB => self.skip_to_start_code(),
+            Framing::Length(length_size) => {
+                self.scan_nal_end(|_| ())?;
+                self.read_nal_length(length_size)
+            },
+        }
+    }
+
+    /// Appends the rest of the current NAL unit to `bytes`, as is, and
+    /// positions at the start of the next one, see `read_to_start_code`.
+    /// Returns false when reaching end of data.
+    pub fn read_nal_unit(&mut self, bytes: &mut Vec<u8>) -> Result<bool> {
+        match self.framing {
+            Framing::AnnexB => self.read_to_start_code(bytes),
+            Framing::Length(length_size) => {
+                self.scan_nal_end(|buf| bytes.extend_from_slice(buf))?;
+                self.read_nal_length(length_size)
+            },
+        }
+    }
+
+    /// Names the syntax element read next, for error locations.
+    pub fn name(&mut self, element: &'static str) -> &mut BitReader<R> {
+        self.element = Some(element);
+        self.element_pos = self.bit_pos();
+        self
+    }
+
+    /// Syntax element being read, or the last one read.
+    pub fn element(&self) -> Option<&'static str> {
+        self.element
+    }
+
+    /// Bit position where the named syntax element starts.
+    pub fn element_pos(&self) -> usize {
+        self.element_pos
+    }
+
+    /// Number of bits read, including emulation prevention bytes.
+    pub fn bit_pos(&self) -> usize {
+        self.pos * 8 - self.valid_bits as usize
+    }
+
+    /// Whether the last start code prefix skipped was preceded by a
+    /// zero_byte, making it a four byte start code.
+    pub fn long_start_code(&self) -> bool {
+        self.long_start_code
+    }
+
+    /// Starts recording the syntax elements read.
+    pub fn trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// Returns the syntax elements recorded since tracing started or
+    /// since the last call.
+    pub fn take_trace(&mut self) -> Vec<TraceElement> {
+        match self.trace {
+            Some(ref mut trace) => mem::take(trace),
+            None => Vec::new(),
+        }
+    }
+
+    fn record(&mut self, bit_pos: usize, descriptor: Descriptor,
+              value: i64) {
+        if let Some(ref mut trace) = self.trace {
+            trace.push(TraceElement {
+                name: self.element,
+                bit_pos,
+                descriptor,
+                value,
+            });
         }
     }
 
     /* Reads n number of bits into unsigned */
     pub fn u64(&mut self, n: u8) -> Result<u64> {
-        read(self, n)
+        let bit_pos = self.bit_pos();
+        let u = read(self, n)?;
+        self.record(bit_pos, Descriptor::U(n), u as i64);
+
+        Ok(u)
     }
 
     pub fn u32(&mut self, n: u8) -> Result<u32> {
@@ -138,17 +530,11 @@ impl<R: Read> BitReader<R> {
     }
 
     pub fn ue64(&mut self) -> Result<u64> {
-        let mut leading_zeroes: i32 = -1;
-        let mut bit = 0;
-
-        while bit == 0 {
-            bit = self.u64(1)?;
-            leading_zeroes += 1;
-        }
+        let bit_pos = self.bit_pos();
+        let ue = read_ue(self)?;
+        self.record(bit_pos, Descriptor::Ue, ue as i64);
 
-        let bits = self.u64(leading_zeroes as u8)?;
-
-        Ok(2u64.pow(leading_zeroes as u32) - 1 + bits)
+        Ok(ue)
     }
 
     pub fn ue32(&mut self) -> Result<u32> {
@@ -172,14 +558,18 @@ impl<R: Read> BitReader<R> {
     }
 
     pub fn se64(&mut self) -> Result<i64> {
-        let code_num = self.ue64()?;
-        let half = (code_num as f64 / 2.0).ceil() as i64;
-        match (code_num & 1) == 1 {
+        let bit_pos = self.bit_pos();
+        let code_num = read_ue(self)?;
+        let half = (code_num / 2 + (code_num & 1)) as i64;
+        let se = match (code_num & 1) == 1 {
             /* Odd */
-            true => Ok(half),
+            true => half,
             /* Even */
-            false => Ok(-half),
-        }
+            false => -half,
+        };
+        self.record(bit_pos, Descriptor::Se, se);
+
+        Ok(se)
     }
 
     pub fn se8(&mut self) -> Result<i8> {
@@ -220,14 +610,17 @@ impl<R: Read> BitReader<R> {
         }
         */
 
-        let rbsp_stop_one_bit = self.u8(1)?;
+        /* Not traced, like in the JM reference decoder */
+        self.name("rbsp_stop_one_bit");
+        let rbsp_stop_one_bit = read(self, 1)?;
 
         if rbsp_stop_one_bit != 1 {
             return Err(err("rbsp_stop_one_bit is not 1"));
         }
 
         while !self.is_byte_aligned() {
-            let rbsp_alignment_zero_bit = self.u8(1)?;
+            self.name("rbsp_alignment_zero_bit");
+            let rbsp_alignment_zero_bit = read(self, 1)?;
             if rbsp_alignment_zero_bit != 0 {
                 return Err(err("rbsp_alignment_zero_bit is not 0"));
             }
@@ -235,61 +628,15 @@ impl<R: Read> BitReader<R> {
 
         Ok(())
     }
-}
-
 
-impl<R: Read+Seek> BitReader<R> {
+    /// Whether there is more data before the rbsp_trailing_bits, as
+    /// specified in 7.2. Fails unless the RBSP length was given with
+    /// `with_rbsp_length`.
     pub fn more_rbsp_data(&mut self) -> Result<bool> {
-        /* Keep track of initial position in stream */
-        let initial_pos = self.reader.seek(SeekFrom::Current(0)).unwrap();
-        /* Keep state of self */
-        let bits = self.bits;
-        let valid_bits = self.valid_bits;
-        let num_zeroes = self.num_zeroes;
-        let end_of_data = self.end_of_data;
-        let pos = self.pos;
-
-        /* If next bit is 1 and the rest of the bits are zero than
-         * we have stumbled upon the rbsp_stop_bit and therefore
-         * we have no more rbsp data. */
-
-        let mut next = read(self, 1);
-        if next.is_err() {
-            /* No more data, no need to restore state */
-            return Ok(false);
-        }
-
-        let mut more_data = false;
-
-        if next.unwrap() == 0 {
-            more_data = true;
-        }
-        else {
-            /* Next bit is 1, this might have been the rbsp_stop_bit. */
-            loop {
-                next = read(self, 1);
-                if next.is_err() {
-                    break;
-                }
-                /* A later 1 bit found, we didn't find the rbsp_stop_bit. */
-                if next.unwrap() == 1 {
-                    more_data = true;
-                    break;
-                }
-            }
+        match self.rbsp_length {
+            Some(length) => Ok(self.bit_pos() < length),
+            None => Err(err("more_rbsp_data without RBSP length")),
         }
-
-        /* Restore */
-        self.bits = bits;
-        self.valid_bits = valid_bits;
-        self.num_zeroes = num_zeroes;
-        self.end_of_data = end_of_data;
-        self.pos = pos;
-        if self.reader.seek(SeekFrom::Start(initial_pos)).is_err() {
-            return Err(err("Failed to restore reader"));
-        }
-
-        Ok(more_data)
     }
 }
 
@@ -510,6 +857,127 @@ mod tests {
         assert_eq!(n3, 0x00);
     }
 
+    #[test]
+    fn emulation_prevention_0x000001() {
+        let buf: [u8; 4] = [0x00, 0x00, 0x03, 0x01];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new(cursor);
+
+        let n1 = reader.b().unwrap();
+        let n2 = reader.b().unwrap();
+        let n3 = reader.b().unwrap();
+
+        assert_eq!(n1, 0x00);
+        assert_eq!(n2, 0x00);
+        assert_eq!(n3, 0x01);
+    }
+
+    #[test]
+    fn emulation_prevention_kept_in_rbsp() {
+        let buf: [u8; 4] = [0x00, 0x00, 0x03, 0x01];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new_rbsp(cursor);
+
+        let n = reader.u64(32).unwrap();
+
+        assert_eq!(n, 0x00000301);
+    }
+
+    #[test]
+    fn named_element_position() {
+        let buf = [0b1010_1000];
+        let mut reader = BitReader::new(&buf[..]);
+
+        assert_eq!(reader.element(), None);
+        reader.name("first").u8(3).unwrap();
+        reader.name("second").ue8().unwrap();
+        assert_eq!(reader.element(), Some("second"));
+        assert_eq!(reader.element_pos(), 3);
+        assert_eq!(reader.bit_pos(), 6);
+    }
+
+    #[test]
+    fn buffered() {
+        let buf = [0x00, 0x00, 0x01, 0x67, 0x42];
+        let source: Box<dyn Read> = Box::new(&buf[..]);
+        let mut reader = BitReader::buffered(source);
+
+        assert!(reader.skip_to_start_code().unwrap());
+        assert_eq!(reader.u64(16).unwrap(), 0x6742);
+    }
+
+    #[test]
+    fn word_reads() {
+        let buf: [u8; 10] = [
+            0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x11, 0x22,
+        ];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new(cursor);
+
+        let n1 = reader.u64(12).unwrap();
+        let n2 = reader.u64(4).unwrap();
+        let n3 = reader.u64(64).unwrap();
+
+        assert_eq!(n1, 0x123);
+        assert_eq!(n2, 0x4);
+        assert_eq!(n3, 0x56789abcdef01122);
+        assert_eq!(reader.pos, 10);
+    }
+
+    #[test]
+    fn word_reads_with_emulation_prevention() {
+        let buf: [u8; 10] = [
+            0x11, 0x00, 0x00, 0x03, 0x01, 0x22, 0x33, 0x44, 0x55, 0x66,
+        ];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new(cursor);
+
+        let n = reader.u64(40).unwrap();
+
+        assert_eq!(n, 0x1100000122);
+    }
+
+    #[test]
+    fn skip_to_start_code_across_buffers() {
+        let buf = vec![0x12, 0x00, 0x00, 0x00, 0x01, 0x67];
+        let cursor = BufReader::with_capacity(2, Cursor::new(buf));
+        let mut reader = BitReader::new(cursor);
+
+        let found = reader.skip_to_start_code().unwrap();
+
+        assert!(found);
+        assert_eq!(reader.b().unwrap(), 0x67);
+    }
+
+    #[test]
+    fn read_to_start_code_keeps_emulation_prevention() {
+        let buf = vec![
+            0x67, 0x42, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x01, 0x68,
+        ];
+        let cursor = BufReader::with_capacity(3, Cursor::new(buf));
+        let mut reader = BitReader::new(cursor);
+        let mut bytes = Vec::new();
+
+        let found = reader.read_to_start_code(&mut bytes).unwrap();
+
+        assert!(found);
+        assert_eq!(bytes, [0x67, 0x42, 0x00, 0x00, 0x03, 0x01]);
+        assert_eq!(reader.b().unwrap(), 0x68);
+    }
+
+    #[test]
+    fn read_to_start_code_at_end_of_data() {
+        let buf = vec![0x67, 0x42, 0x00];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        let mut bytes = Vec::new();
+
+        let found = reader.read_to_start_code(&mut bytes).unwrap();
+
+        assert!(!found);
+        assert!(reader.reached_end_of_data());
+        assert_eq!(bytes, [0x67, 0x42]);
+    }
+
     #[test]
     fn pos_is_increased_at_read() {
         let buf: [u8; 4] = [0x00, 0x00, 0x03, 0x00];
@@ -566,8 +1034,7 @@ mod tests {
     #[test]
     fn more_rbsp_data_at_end() {
         let buf: [u8; 1] = [1];
-        let cursor = Cursor::new(buf);
-        let mut reader = BitReader::new(cursor);
+        let mut reader = BitReader::from_rbsp(&buf);
 
         /* Read the one and only byte */
         reader.b().unwrap();
@@ -579,9 +1046,8 @@ mod tests {
 
     #[test]
     fn more_rbsp_data_at_a_zero() {
-        let buf: [u8; 1] = [0];
-        let cursor = Cursor::new(buf);
-        let mut reader = BitReader::new(cursor);
+        let buf: [u8; 2] = [0, 0b10000000];
+        let mut reader = BitReader::from_rbsp(&buf);
 
         /* Read all but one bit, last one should be zero */
         reader.u64(7).unwrap();
@@ -599,8 +1065,7 @@ mod tests {
     #[test]
     fn more_rbsp_data_with_rbsp_stop_bit() {
         let buf: [u8; 1] = [0b10000000];
-        let cursor = Cursor::new(buf);
-        let mut reader = BitReader::new(cursor);
+        let mut reader = BitReader::from_rbsp(&buf);
 
         let more_rbsp_data = reader.more_rbsp_data().unwrap();
 
@@ -615,8 +1080,7 @@ mod tests {
     #[test]
     fn more_rbsp_data_with_rbsp_stop_bit_but_more_data() {
         let buf: [u8; 1] = [0b10000001];
-        let cursor = Cursor::new(buf);
-        let mut reader = BitReader::new(cursor);
+        let mut reader = BitReader::from_rbsp(&buf);
 
         let more_rbsp_data = reader.more_rbsp_data().unwrap();
 
@@ -626,4 +1090,51 @@ mod tests {
         assert!(more_rbsp_data);
         assert!(first == 1);
     }
+
+    #[test]
+    fn more_rbsp_data_without_rbsp_length() {
+        let buf: [u8; 1] = [0b10000000];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        assert!(reader.more_rbsp_data().is_err());
+    }
+
+    #[test]
+    fn rbsp_length_of_stop_bit() {
+        assert_eq!(rbsp_length(&[0xce, 0x3c, 0x80]), 16);
+        assert_eq!(rbsp_length(&[0x01, 0x00, 0x00]), 7);
+        assert_eq!(rbsp_length(&[0x42, 0x04]), 13);
+        assert_eq!(rbsp_length(&[0x00]), 0);
+        assert_eq!(rbsp_length(&[]), 0);
+    }
+
+    #[test]
+    fn more_rbsp_data_with_rbsp_length() {
+        let buf = [0b10000001, 0b10000000];
+        let bytes = BufReader::with_capacity(1, &buf[..]);
+        let mut reader = BitReader::with_rbsp_length(bytes, 8);
+
+        let more_first = reader.more_rbsp_data().unwrap();
+        reader.u64(7).unwrap();
+        let more_last = reader.more_rbsp_data().unwrap();
+        reader.u64(1).unwrap();
+        let more_stop = reader.more_rbsp_data().unwrap();
+
+        assert!(more_first && more_last && !more_stop);
+        assert!(reader.rbsp_trailing_bits().is_ok());
+    }
+
+    #[test]
+    fn framing_length_size() {
+        let buf = [0x00, 0x00, 0x00];
+
+        assert!(BitReader::with_framing(&buf[..], Framing::Length(0))
+                .is_err());
+        assert!(BitReader::with_framing(&buf[..], Framing::Length(3))
+                .is_err());
+        assert!(BitReader::with_framing(&buf[..], Framing::Length(9))
+                .is_err());
+        assert!(BitReader::with_framing(&buf[..], Framing::Length(1))
+                .is_ok());
+    }
 }
@@ -0,0 +1,305 @@
+use super::*;
+
+/// Writes bits into a byte vector, the inverse of `BitReader`.
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    /* Bits of the byte being written, right aligned */
+    bits: u8,
+    num_bits: u8,
+    num_zeroes: u8,
+    /* Inserts emulation_prevention_three_byte when set */
+    emulation_prevention: bool,
+}
+
+fn err(text: &str) -> ParserError {
+    let description = String::from(text);
+    let error = BitWriterError { description };
+
+    ParserError::BitWriterError(error)
+}
+
+/* Writes the n lowest bits of u, n may be larger than 64 for the
+ * leading zero bits of long Exp-Golomb codes */
+fn write(writer: &mut BitWriter, n: u8, u: u128) {
+    for i in (0..n).rev() {
+        writer.bits = writer.bits << 1 | (u >> i) as u8 & 1;
+        writer.num_bits += 1;
+        if writer.num_bits == 8 {
+            let byte = writer.bits;
+            push(writer, byte);
+            writer.bits = 0;
+            writer.num_bits = 0;
+        }
+    }
+}
+
+fn push(writer: &mut BitWriter, byte: u8) {
+    if writer.emulation_prevention && writer.num_zeroes == 2 && byte <= 0x03 {
+        writer.bytes.push(0x03);
+        writer.num_zeroes = 0;
+    }
+    writer.bytes.push(byte);
+    if byte == 0x00 {
+        writer.num_zeroes += 1;
+    }
+    else {
+        writer.num_zeroes = 0;
+    }
+}
+
+impl BitWriter {
+    /// Writer of NAL units as coded in the byte stream, emulation
+    /// prevention bytes are inserted.
+    pub fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            bits: 0,
+            num_bits: 0,
+            num_zeroes: 0,
+            emulation_prevention: true,
+        }
+    }
+
+    /// Writer of RBSP data, without emulation prevention.
+    pub fn new_rbsp() -> BitWriter {
+        BitWriter {
+            emulation_prevention: false,
+            ..BitWriter::new()
+        }
+    }
+
+    /* Writes n number of bits of unsigned */
+    pub fn u64(&mut self, n: u8, u: u64) -> Result<()> {
+        if n > 64 {
+            return Err(err("too many bits, > 64"));
+        }
+        if n < 64 && u >> n != 0 {
+            return Err(err("value does not fit in bits"));
+        }
+
+        write(self, n, u as u128);
+
+        Ok(())
+    }
+
+    pub fn u32(&mut self, n: u8, u: u32) -> Result<()> {
+        if n > 32 {
+            return Err(err("too many bits, > 32"));
+        }
+
+        self.u64(n, u as u64)
+    }
+
+    pub fn u8(&mut self, n: u8, u: u8) -> Result<()> {
+        if n > 8 {
+            return Err(err("too many bits, > 8"));
+        }
+
+        self.u64(n, u as u64)
+    }
+
+    pub fn b(&mut self, u: u8) -> Result<()> {
+        self.u8(8, u)
+    }
+
+    pub fn ue64(&mut self, u: u64) -> Result<()> {
+        let code = u as u128 + 1;
+        let leading_zeroes = (127 - code.leading_zeros()) as u8;
+
+        write(self, leading_zeroes, 0);
+        write(self, leading_zeroes + 1, code);
+
+        Ok(())
+    }
+
+    pub fn ue32(&mut self, u: u32) -> Result<()> {
+        self.ue64(u as u64)
+    }
+
+    pub fn ue8(&mut self, u: u8) -> Result<()> {
+        self.ue64(u as u64)
+    }
+
+    pub fn se64(&mut self, s: i64) -> Result<()> {
+        /* Table 9-3 */
+        let code_num = if s > 0 {
+            s as u128 * 2 - 1
+        }
+        else {
+            s.unsigned_abs() as u128 * 2
+        };
+        let code = code_num + 1;
+        let leading_zeroes = (127 - code.leading_zeros()) as u8;
+
+        write(self, leading_zeroes, 0);
+        write(self, leading_zeroes + 1, code);
+
+        Ok(())
+    }
+
+    pub fn se8(&mut self, s: i8) -> Result<()> {
+        self.se64(s as i64)
+    }
+
+    pub fn flag(&mut self, flag: bool) -> Result<()> {
+        self.u64(1, flag as u64)
+    }
+
+    pub fn is_byte_aligned(&self) -> bool {
+        self.num_bits == 0
+    }
+
+    /* Writes rbsp trailing bits */
+    pub fn rbsp_trailing_bits(&mut self) -> Result<()> {
+        /* rbsp_stop_one_bit */
+        write(self, 1, 1);
+        while !self.is_byte_aligned() {
+            /* rbsp_alignment_zero_bit */
+            write(self, 1, 0);
+        }
+
+        Ok(())
+    }
+
+    /// Number of bits written.
+    pub fn bit_pos(&self) -> usize {
+        self.bytes.len() * 8 + self.num_bits as usize
+    }
+
+    /// Returns the bytes written, the last byte is padded with zero
+    /// bits when not byte aligned. A final 0x03 byte is appended when
+    /// emulation prevention is done and the data ends with a zero byte,
+    /// as specified in 7.4.1.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        if !self.is_byte_aligned() {
+            let n = 8 - self.num_bits;
+            write(&mut self, n, 0);
+        }
+        if self.emulation_prevention && self.bytes.last() == Some(&0x00) {
+            self.bytes.push(0x03);
+        }
+
+        self.bytes
+    }
+}
+
+impl Default for BitWriter {
+    fn default() -> BitWriter {
+        BitWriter::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitreader::BitReader;
+
+    #[test]
+    fn write_bits() {
+        let mut writer = BitWriter::new_rbsp();
+        writer.u8(3, 0b101).unwrap();
+        writer.flag(false).unwrap();
+        writer.u32(12, 0xabc).unwrap();
+
+        assert_eq!(writer.bit_pos(), 16);
+        assert_eq!(writer.into_bytes(), [0b1010_1010, 0xbc]);
+    }
+
+    #[test]
+    fn value_too_large() {
+        let mut writer = BitWriter::new_rbsp();
+
+        assert!(writer.u8(3, 8).is_err());
+        assert!(writer.u32(33, 0).is_err());
+        assert!(writer.u64(65, 0).is_err());
+    }
+
+    #[test]
+    fn write_exp_golomb() {
+        let mut writer = BitWriter::new_rbsp();
+        /* 1, 010, 011, 00100 */
+        writer.ue8(0).unwrap();
+        writer.ue8(1).unwrap();
+        writer.se8(-1).unwrap();
+        writer.ue32(3).unwrap();
+        writer.rbsp_trailing_bits().unwrap();
+
+        assert_eq!(writer.into_bytes(), [0b1010_0110, 0b0100_1000]);
+    }
+
+    #[test]
+    fn write_extreme_exp_golomb() {
+        let mut writer = BitWriter::new_rbsp();
+        writer.ue64(u64::MAX - 1).unwrap();
+        writer.se64(i64::MIN + 1).unwrap();
+        writer.rbsp_trailing_bits().unwrap();
+        let bytes = writer.into_bytes();
+        let mut reader = BitReader::new_rbsp(&bytes[..]);
+
+        assert_eq!(reader.ue64().unwrap(), u64::MAX - 1);
+        assert_eq!(reader.se64().unwrap(), i64::MIN + 1);
+    }
+
+    #[test]
+    fn insert_emulation_prevention() {
+        let mut writer = BitWriter::new();
+        for byte in &[0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04] {
+            writer.b(*byte).unwrap();
+        }
+
+        assert_eq!(writer.into_bytes(), [0x00, 0x00, 0x03, 0x01, 0x00, 0x00,
+                                         0x03, 0x00, 0x00, 0x04]);
+    }
+
+    #[test]
+    fn final_zero_byte() {
+        let mut writer = BitWriter::new();
+        writer.b(0x80).unwrap();
+        writer.b(0x00).unwrap();
+
+        assert_eq!(writer.into_bytes(), [0x80, 0x00, 0x03]);
+    }
+
+    proptest! {
+        #[test]
+        fn read_back(values in proptest::collection::vec(
+                (0u8..4, 0u8..65, proptest::num::u64::ANY), 0..64)) {
+            let mut writer = BitWriter::new();
+            for &(kind, n, u) in &values {
+                match kind {
+                    0 => writer.u64(n, u & mask(n)).unwrap(),
+                    1 => writer.ue64(ue(u)).unwrap(),
+                    2 => writer.se64(se(u)).unwrap(),
+                    _ => writer.flag(u & 1 == 1).unwrap(),
+                }
+            }
+            writer.rbsp_trailing_bits().unwrap();
+            let bytes = writer.into_bytes();
+            let mut reader = BitReader::new(&bytes[..]);
+
+            for &(kind, n, u) in &values {
+                match kind {
+                    0 => prop_assert_eq!(reader.u64(n).unwrap(), u & mask(n)),
+                    1 => prop_assert_eq!(reader.ue64().unwrap(), ue(u)),
+                    2 => prop_assert_eq!(reader.se64().unwrap(), se(u)),
+                    _ => prop_assert_eq!(reader.flag().unwrap(), u & 1 == 1),
+                }
+            }
+            reader.rbsp_trailing_bits().unwrap();
+        }
+    }
+
+    fn mask(n: u8) -> u64 {
+        if n == 64 { u64::MAX } else { (1 << n) - 1 }
+    }
+
+    /* Largest values the reader supports, 63 leading zero bits */
+    fn ue(u: u64) -> u64 {
+        u.min(u64::MAX - 1)
+    }
+
+    fn se(u: u64) -> i64 {
+        (u as i64).max(i64::MIN + 1)
+    }
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::io::prelude::*;
+
+use super::*;
+
+/// Container demuxer of an H.264 stream.
+pub trait Demuxer {
+    /// Reads the NAL units of the next sample, frame or packet as
+    /// Annex B bytes, None at end of data.
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>>;
+}
+
+/// Reads the H.264 stream of a `Demuxer` as an Annex B byte stream.
+pub struct AnnexBReader<D> {
+    demuxer: D,
+    /* Annex B bytes of the last sample and position */
+    buf: Vec<u8>,
+    buf_pos: usize,
+    end_of_data: bool,
+}
+
+impl<D: Demuxer> AnnexBReader<D> {
+    pub fn new(demuxer: D) -> AnnexBReader<D> {
+        AnnexBReader {
+            demuxer,
+            buf: Vec::new(),
+            buf_pos: 0,
+            end_of_data: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &D {
+        &self.demuxer
+    }
+
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.demuxer
+    }
+}
+
+impl<D: Demuxer> Read for AnnexBReader<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl<D: Demuxer> BufRead for AnnexBReader<D> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.buf_pos == self.buf.len() && !self.end_of_data {
+            let annex_b = self.demuxer.next_annex_b().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            })?;
+            match annex_b {
+                Some(annex_b) => self.buf = annex_b,
+                None => {
+                    self.buf = Vec::new();
+                    self.end_of_data = true;
+                },
+            }
+            self.buf_pos = 0;
+        }
+
+        Ok(&self.buf[self.buf_pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf_pos = (self.buf_pos + amt).min(self.buf.len());
+    }
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use bitreader::BitReader;
+    use nalunit::NalUnit;
+    use super::*;
+
+    /// NAL unit types of the Annex B stream of `demuxer`.
+    pub fn nal_types<D: Demuxer>(demuxer: D) -> Vec<u8> {
+        let mut bitreader = BitReader::new(AnnexBReader::new(demuxer));
+        let mut types = Vec::new();
+
+        NalUnit::next(&mut bitreader).unwrap();
+        while !bitreader.reached_end_of_data() {
+            let (nal, _) = NalUnit::parse(&mut bitreader).unwrap();
+            types.push(nal.nal_unit_type);
+        }
+
+        types
+    }
+
+    /* Demuxer returning the samples in order */
+    struct Samples(Vec<Vec<u8>>);
+
+    impl Demuxer for Samples {
+        fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>> {
+            if self.0.is_empty() {
+                return Ok(None);
+            }
+
+            Ok(Some(self.0.remove(0)))
+        }
+    }
+
+    #[test]
+    fn read_samples() {
+        let aud = vec![0x00, 0x00, 0x00, 0x01, 0x09, 0xf0];
+        let samples = Samples(vec![aud.clone(), Vec::new(), aud.clone()]);
+        let mut reader = AnnexBReader::new(samples);
+        let mut data = Vec::new();
+
+        reader.read_to_end(&mut data).unwrap();
+
+        assert_eq!(data, [aud.clone(), aud].concat());
+        assert!(reader.get_ref().0.is_empty());
+    }
+
+    #[test]
+    fn nal_types_of_samples() {
+        let samples = Samples(vec![vec![0x00, 0x00, 0x01, 0x09, 0xf0,
+                                        0x00, 0x00, 0x01, 0x09, 0x10]]);
+
+        assert_eq!(nal_types(samples), [9, 9]);
+    }
+}
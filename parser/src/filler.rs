@@ -0,0 +1,84 @@
+use std::io::prelude::*;
+
+use bitreader::BitReader;
+use bitwriter::BitWriter;
+use super::*;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct FillerData {
+    /// Number of ff_byte.
+    pub length: usize,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Filler();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+impl FillerData {
+    /// Parses filler_data_rbsp() as specified in 7.3.2.7.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) -> Result<FillerData> {
+        let mut length = 0;
+
+        /* Byte aligned, first byte that isn't ff_byte should be the
+         * rbsp trailing bits */
+        loop {
+            match r.name("ff_byte").b()? {
+                0xff => length += 1,
+                0x80 => break,
+                _ => return Err(err("Filler data byte is not 0xff")),
+            }
+        }
+
+        Ok(FillerData { length })
+    }
+
+    /// Writes filler_data_rbsp(), the inverse of `parse`.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        for _ in 0..self.length {
+            w.b(0xff)?;
+        }
+        w.rbsp_trailing_bits()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let buf: [u8; 4] = [0xff, 0xff, 0xff, 0x80];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let filler = FillerData::parse(&mut reader).unwrap();
+
+        assert_eq!(filler.length, 3);
+    }
+
+    #[test]
+    fn parse_not_ff() {
+        let buf: [u8; 3] = [0xff, 0xfe, 0x80];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let res = FillerData::parse(&mut reader);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn parse_no_trailing_bits() {
+        let buf: [u8; 2] = [0xff, 0xff];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let res = FillerData::parse(&mut reader);
+
+        assert!(res.is_err());
+    }
+}
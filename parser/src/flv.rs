@@ -0,0 +1,341 @@
+use std::io;
+use std::io::prelude::*;
+
+use avcc::{self, AvcConfiguration};
+use demux::Demuxer;
+use super::*;
+
+const TAG_VIDEO: u8 = 9;
+const CODEC_AVC: u8 = 7;
+
+/* Size of the file header of version 1 */
+const HEADER_SIZE: usize = 9;
+
+/// A frame of AVC video, times are in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Frame {
+    /// Byte offset of the tag in the file
+    pub offset: u64,
+    /// Decoding time, the tag timestamp
+    pub dts: u32,
+    /// Presentation time, DTS plus the composition time offset
+    pub pts: i64,
+    pub keyframe: bool,
+    /// NAL units each preceded by its length, see
+    /// `AvcConfiguration::length_size`
+    pub data: Vec<u8>,
+}
+
+/// AVC video packet of an FLV video tag.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum AvcPacket {
+    /// AVCPacketType 0, the configuration of the following frames
+    SequenceHeader(AvcConfiguration),
+    /// AVCPacketType 1
+    Frame(Frame),
+    /// AVCPacketType 2
+    EndOfSequence(),
+}
+
+/// Reads the AVC video tags of an FLV file, like recordings of RTMP
+/// streams.
+///
+/// Also reads as an Annex B byte stream with `AnnexBReader`, the
+/// parameter sets of each sequence header followed by the NAL units of
+/// the frames, so the file can be parsed like a raw H.264 stream.
+pub struct FlvReader<R> {
+    reader: R,
+    /* Byte offset of the next tag */
+    offset: u64,
+    configuration: Option<AvcConfiguration>,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Flv();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn not_impl(text: &str) -> ParserError {
+    let unit = ParserUnit::Flv();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::NotImplemented(error)
+}
+
+fn be24(data: &[u8]) -> u32 {
+    (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32
+}
+
+impl<R: Read> FlvReader<R> {
+    /// Reads the file header.
+    pub fn new(mut reader: R) -> Result<FlvReader<R>> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)
+            .map_err(|e| io_err(ParserUnit::Flv(), e))?;
+        if header[..3] != *b"FLV" {
+            return Err(err("Not an FLV file"));
+        }
+        let data_offset = u32::from_be_bytes([header[5], header[6],
+                                              header[7], header[8]]) as u64;
+        if data_offset < HEADER_SIZE as u64 {
+            return Err(err("DataOffset smaller than the header"));
+        }
+
+        let mut flv = FlvReader {
+            reader,
+            offset: HEADER_SIZE as u64,
+            configuration: None,
+        };
+        /* Rest of the header and PreviousTagSize0 */
+        flv.skip(data_offset - HEADER_SIZE as u64 + 4)?;
+
+        Ok(flv)
+    }
+
+    /// Configuration of the last sequence header read.
+    pub fn configuration(&self) -> Option<&AvcConfiguration> {
+        self.configuration.as_ref()
+    }
+
+    fn skip(&mut self, size: u64) -> Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(size),
+                               &mut io::sink())
+            .map_err(|e| io_err(ParserUnit::Flv(), e))?;
+        self.offset += skipped;
+        if skipped < size {
+            return Err(err("Tag past end of data"));
+        }
+
+        Ok(())
+    }
+
+    /* Reads the next tag header, None at end of data */
+    fn tag_header(&mut self) -> Result<Option<[u8; 11]>> {
+        let mut header = [0; 11];
+        match read_all(&mut self.reader, &mut header) {
+            Ok(0) => return Ok(None),
+            Ok(n) if n < header.len() => {
+                return Err(err("Tag header past end of data"));
+            },
+            Ok(_) => {},
+            Err(e) => return Err(io_err(ParserUnit::Flv(), e)),
+        }
+        self.offset += header.len() as u64;
+
+        Ok(Some(header))
+    }
+
+    /// Reads the next AVC video packet, None at end of data. Other tags
+    /// are skipped.
+    pub fn next_packet(&mut self) -> Result<Option<AvcPacket>> {
+        loop {
+            let offset = self.offset;
+            let header = match self.tag_header()? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            let tag_type = header[0] & 0x1f;
+            let size = be24(&header[1..]) as usize;
+            /* TimestampExtended holds the upper 8 bits */
+            let dts = be24(&header[4..]) | (header[7] as u32) << 24;
+
+            if tag_type != TAG_VIDEO || size < 5 {
+                /* With PreviousTagSize */
+                self.skip(size as u64 + 4)?;
+                continue;
+            }
+            let mut data = vec![0; size + 4];
+            self.reader.read_exact(&mut data)
+                .map_err(|e| io_err(ParserUnit::Flv(), e))?;
+            self.offset += data.len() as u64;
+            data.truncate(size);
+            if header[0] & 0x20 != 0 {
+                return Err(not_impl("Encrypted tag"));
+            }
+            /* Enhanced RTMP video tags carry other codecs */
+            if data[0] & 0x80 != 0 || data[0] & 0x0f != CODEC_AVC {
+                continue;
+            }
+            let frame_type = data[0] >> 4;
+            /* Video info/command frame */
+            if frame_type == 5 {
+                continue;
+            }
+            /* Signed 24 bit CompositionTime */
+            let composition_time = (be24(&data[2..]) << 8) as i32 >> 8;
+
+            match data[1] {
+                0 => {
+                    let configuration = AvcConfiguration::parse(&data[5..])?;
+                    self.configuration = Some(configuration.clone());
+                    return Ok(Some(AvcPacket::SequenceHeader(configuration)));
+                },
+                1 => {
+                    return Ok(Some(AvcPacket::Frame(Frame {
+                        offset,
+                        dts,
+                        pts: dts as i64 + composition_time as i64,
+                        keyframe: frame_type == 1,
+                        data: data.split_off(5),
+                    })));
+                },
+                2 => return Ok(Some(AvcPacket::EndOfSequence())),
+                _ => return Err(err("Invalid AVCPacketType")),
+            }
+        }
+    }
+}
+
+impl<R: Read> Demuxer for FlvReader<R> {
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut annex_b = Vec::new();
+        match self.next_packet()? {
+            Some(AvcPacket::SequenceHeader(configuration)) => {
+                for nal_unit in configuration.sequence_parameter_sets.iter()
+                    .chain(configuration.picture_parameter_sets.iter()) {
+                    annex_b.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+                    annex_b.extend_from_slice(nal_unit);
+                }
+            },
+            Some(AvcPacket::Frame(frame)) => {
+                let length_size = match self.configuration {
+                    Some(ref configuration) => configuration.length_size(),
+                    None => return Err(err("Frame before sequence header")),
+                };
+                annex_b = avcc::avcc_to_annex_b(&frame.data, length_size)?;
+            },
+            Some(AvcPacket::EndOfSequence()) => {},
+            None => return Ok(None),
+        }
+
+        Ok(Some(annex_b))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use avcc::tests::AVCC;
+    use demux::AnnexBReader;
+    use demux::tests::nal_types;
+    use super::*;
+
+    /* AUD and IDR slice, length size 4 */
+    const IDR: [u8; 14] = [0x00, 0x00, 0x00, 0x02, 0x09, 0x10,
+                           0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00];
+    const P: [u8; 7] = [0x00, 0x00, 0x00, 0x03, 0x41, 0x9a, 0x02];
+
+    fn tag(tag_type: u8, timestamp: u32, data: &[u8]) -> Vec<u8> {
+        let mut tag = vec![tag_type];
+        tag.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        tag.push((timestamp >> 24) as u8);
+        tag.extend_from_slice(&[0x00; 3]);
+        tag.extend_from_slice(data);
+        tag.extend_from_slice(&(11 + data.len() as u32).to_be_bytes());
+
+        tag
+    }
+
+    fn video(frame_type: u8, packet_type: u8, composition_time: i32,
+             data: &[u8]) -> Vec<u8> {
+        let mut video = vec![frame_type << 4 | CODEC_AVC, packet_type];
+        video.extend_from_slice(&composition_time.to_be_bytes()[1..]);
+        video.extend_from_slice(data);
+
+        video
+    }
+
+    fn file() -> Vec<u8> {
+        let mut file = vec![b'F', b'L', b'V', 0x01, 0x05,
+                            0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00];
+        /* onMetaData script tag and an AAC audio tag */
+        file.extend(tag(18, 0, &[0x02, 0x00, 0x00]));
+        file.extend(tag(8, 0, &[0xaf, 0x01, 0x21]));
+        file.extend(tag(9, 0, &video(1, 0, 0, &AVCC)));
+        file.extend(tag(9, 0, &video(1, 1, 80, &IDR)));
+        file.extend(tag(9, 0x01000028, &video(2, 1, -40, &P)));
+        file.extend(tag(9, 0x01000050, &video(1, 2, 0, &[])));
+
+        file
+    }
+
+    #[test]
+    fn read_packets() {
+        let mut reader = FlvReader::new(Cursor::new(file())).unwrap();
+
+        let header = reader.next_packet().unwrap().unwrap();
+        let idr = reader.next_packet().unwrap().unwrap();
+        let p = reader.next_packet().unwrap().unwrap();
+        let end = reader.next_packet().unwrap().unwrap();
+
+        assert_eq!(header, AvcPacket::SequenceHeader(
+            AvcConfiguration::parse(&AVCC).unwrap()));
+        assert_eq!(idr, AvcPacket::Frame(Frame {
+            offset: 13 + 18 + 18 + 43,
+            dts: 0,
+            pts: 80,
+            keyframe: true,
+            data: IDR.to_vec(),
+        }));
+        match p {
+            AvcPacket::Frame(frame) => {
+                assert_eq!((frame.dts, frame.pts), (0x01000028, 0x01000000));
+                assert!(!frame.keyframe);
+            },
+            _ => panic!("Not a frame"),
+        }
+        assert_eq!(end, AvcPacket::EndOfSequence());
+        assert!(reader.next_packet().unwrap().is_none());
+        assert!(reader.configuration().is_some());
+    }
+
+    #[test]
+    fn parse_length_prefixed() {
+        let mut reader = FlvReader::new(Cursor::new(file())).unwrap();
+        reader.next_packet().unwrap();
+        let frame = match reader.next_packet().unwrap() {
+            Some(AvcPacket::Frame(frame)) => frame,
+            _ => panic!("Not a frame"),
+        };
+
+        let types = avcc::tests::nal_types(&frame.data, 4);
+
+        assert_eq!(types, [9, 5]);
+    }
+
+    #[test]
+    fn read_annex_b() {
+        let reader = FlvReader::new(Cursor::new(file())).unwrap();
+
+        let types = nal_types(reader);
+
+        assert_eq!(types, [7, 8, 9, 5, 1]);
+    }
+
+    #[test]
+    fn frame_before_sequence_header() {
+        let mut file = file();
+        /* Drop the sequence header tag */
+        file.drain(49..49 + 43);
+        let reader = FlvReader::new(Cursor::new(file)).unwrap();
+        let mut reader = AnnexBReader::new(reader);
+        let mut buf = Vec::new();
+
+        assert!(reader.read_to_end(&mut buf).is_err());
+    }
+
+    #[test]
+    fn not_flv() {
+        let res = FlvReader::new(Cursor::new(vec![0x00; 13]));
+
+        assert!(res.is_err());
+    }
+}
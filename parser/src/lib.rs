@@ -1,40 +1,181 @@
 use std::result;
 use std::error::Error;
 use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+extern crate memchr;
+
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(all(test, feature = "serialize"))]
+extern crate serde_json;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 
 pub mod bitreader;
+pub mod bitwriter;
 pub mod nalunit;
 pub mod sps;
 pub mod pps;
+pub mod scaling;
+pub mod paramsets;
+pub mod slice;
+pub mod sei;
+pub mod aud;
+pub mod filler;
+pub mod accessunit;
+pub mod poc;
+pub mod trace;
+pub mod avcc;
+pub mod demux;
+pub mod mp4;
+pub mod ts;
+pub mod pcap;
+pub mod rtp;
+pub mod mkv;
+pub mod flv;
+pub mod probe;
 
 type Result<T> = result::Result<T, ParserError>;
 
+/* Error of `unit` for an I/O error of the input */
+fn io_err(unit: ParserUnit, e: io::Error) -> ParserError {
+    let description = e.to_string();
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+/* Reads up to `buf.len()` bytes, fewer only at end of data */
+fn read_all<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match reader.read(&mut buf[pos..]) {
+            Ok(0) => break,
+            Ok(n) => pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(pos)
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub enum ParserUnit {
     Nal(),
     Pps(),
     Sps(),
+    Slice(),
+    Sei(),
+    Filler(),
+    Avcc(),
+    Mp4(),
+    Ts(),
+    Pcap(),
+    Rtp(),
+    Mkv(),
+    Flv(),
+}
+
+/// Where in the input an error occurred.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ErrorLocation {
+    /// Byte offset of the NAL unit header in the input
+    pub nal_offset: usize,
+    /// Bit offset within the RBSP, of the NAL unit header for NAL
+    /// errors. The start of the syntax element when named.
+    pub bit_offset: usize,
+    /// Syntax element being read, or the last one read
+    pub element: Option<&'static str>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct ParserUnitError {
     unit: ParserUnit,
     description: String,
+    location: Option<ErrorLocation>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct BitReaderError {
     description: String,
+    location: Option<ErrorLocation>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct BitWriterError {
+    description: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub enum ParserError {
     BitReaderError(BitReaderError),
     BitReaderEndOfStream(),
+    BitWriterError(BitWriterError),
     InvalidStream(ParserUnitError),
     NotImplemented(ParserUnitError),
 }
 
+impl ParserError {
+    /// Attaches the location to the error. Running out of data is
+    /// turned into a bit reader error as it happened within a unit.
+    pub fn at(self, location: ErrorLocation) -> ParserError {
+        match self {
+            ParserError::BitReaderError(mut e) => {
+                e.location = Some(location);
+                ParserError::BitReaderError(e)
+            },
+            ParserError::BitReaderEndOfStream() => {
+                let description = String::from("unexpected end of data");
+                let location = Some(location);
+                let error = BitReaderError { description, location };
+                ParserError::BitReaderError(error)
+            },
+            ParserError::BitWriterError(e) => ParserError::BitWriterError(e),
+            ParserError::InvalidStream(mut e) => {
+                e.location = Some(location);
+                ParserError::InvalidStream(e)
+            },
+            ParserError::NotImplemented(mut e) => {
+                e.location = Some(location);
+                ParserError::NotImplemented(e)
+            },
+        }
+    }
+
+    /// Location of the error, if known.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match *self {
+            ParserError::BitReaderError(ref e) => e.location.as_ref(),
+            ParserError::BitReaderEndOfStream() => None,
+            ParserError::BitWriterError(_) => None,
+            ParserError::InvalidStream(ref e) |
+            ParserError::NotImplemented(ref e) => e.location.as_ref(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NAL at byte {}, RBSP bit {}", self.nal_offset,
+               self.bit_offset)?;
+        if let Some(element) = self.element {
+            write!(f, ", {}", element)?;
+        }
+        Ok(())
+    }
+}
+
 impl Error for BitReaderError {
     fn description(&self) -> &str {
         &self.description
@@ -43,7 +184,23 @@ impl Error for BitReaderError {
 
 impl fmt::Display for BitReaderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Bitreader error: {}", self.description)
+        write!(f, "Bitreader error: {}", self.description)?;
+        if let Some(ref location) = self.location {
+            write!(f, " ({})", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BitWriterError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl fmt::Display for BitWriterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bitwriter error: {}", self.description)
     }
 }
 
@@ -60,6 +217,45 @@ impl fmt::Display for ParserUnitError {
                 ParserUnit::Nal() => "NAL",
                 ParserUnit::Sps() => "SPS",
                 ParserUnit::Pps() => "PPS",
-            })
+                ParserUnit::Slice() => "Slice",
+                ParserUnit::Sei() => "SEI",
+                ParserUnit::Filler() => "Filler data",
+                ParserUnit::Avcc() => "avcC",
+                ParserUnit::Mp4() => "MP4",
+                ParserUnit::Ts() => "MPEG-TS",
+                ParserUnit::Pcap() => "pcap",
+                ParserUnit::Rtp() => "RTP",
+                ParserUnit::Mkv() => "Matroska",
+                ParserUnit::Flv() => "FLV",
+            })?;
+        if let Some(ref location) = self.location {
+            write!(f, " ({})", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParserError {
+    fn description(&self) -> &str {
+        match *self {
+            ParserError::BitReaderError(ref e) => &e.description,
+            ParserError::BitReaderEndOfStream() => "end of data",
+            ParserError::BitWriterError(ref e) => &e.description,
+            ParserError::InvalidStream(ref e) |
+            ParserError::NotImplemented(ref e) => &e.description,
+        }
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParserError::BitReaderError(ref e) => write!(f, "{}", e),
+            ParserError::BitReaderEndOfStream() => write!(f, "End of data"),
+            ParserError::BitWriterError(ref e) => write!(f, "{}", e),
+            ParserError::InvalidStream(ref e) => write!(f, "{}", e),
+            ParserError::NotImplemented(ref e) =>
+                write!(f, "Not implemented: {}", e),
+        }
     }
 }
@@ -0,0 +1,692 @@
+use std::io;
+use std::io::prelude::*;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use avcc::{self, AvcConfiguration};
+use demux::Demuxer;
+use super::*;
+
+/* Element IDs, marker bits included */
+const EBML: u32 = 0x1a45dfa3;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549a966;
+const TIMECODE_SCALE: u32 = 0x2ad7b1;
+const TRACKS: u32 = 0x1654ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const DEFAULT_DURATION: u32 = 0x23e383;
+const CONTENT_ENCODINGS: u32 = 0x6d80;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const CLUSTER: u32 = 0x1f43b675;
+const TIMECODE: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const REFERENCE_BLOCK: u32 = 0xfb;
+
+const CODEC_ID_H264: &str = "V_MPEG4/ISO/AVC";
+
+/* Larger elements read into memory are taken as corrupt */
+const MAX_ELEMENT_SIZE: u64 = 1 << 28;
+
+/// The first H.264 track of the file.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Track {
+    pub track_number: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Duration of a frame in nanoseconds, when constant
+    pub default_duration: Option<u64>,
+    /// Parsed CodecPrivate
+    pub configuration: AvcConfiguration,
+}
+
+/// A frame of the H.264 track.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Frame {
+    /// Byte offset of the block in the file
+    pub offset: u64,
+    /// Presentation time in nanoseconds
+    pub timecode: i64,
+    pub keyframe: bool,
+    /// NAL units each preceded by its length, see
+    /// `AvcConfiguration::length_size`
+    pub data: Vec<u8>,
+}
+
+/// Reads the H.264 track of a Matroska or WebM file.
+///
+/// Also reads as an Annex B byte stream with `AnnexBReader`, the
+/// parameter sets of CodecPrivate followed by the NAL units of the
+/// frames, so the file can be parsed like a raw H.264 stream. Elements
+/// are read in order without seeking, Segments and Clusters of unknown
+/// size, as written by live recordings, are supported.
+pub struct MkvReader<R> {
+    elements: Elements<R>,
+    track: Track,
+    /* Nanoseconds per timecode unit */
+    timecode_scale: u64,
+    cluster_timecode: u64,
+    /* Frames of the last block not returned yet */
+    frames: VecDeque<Frame>,
+    started: bool,
+}
+
+/* Reads elements from a stream */
+struct Elements<R> {
+    reader: R,
+    /* Byte offset of the next element */
+    offset: u64,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Mkv();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn not_impl(text: &str) -> ParserError {
+    let unit = ParserUnit::Mkv();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::NotImplemented(error)
+}
+
+/* Variable size integer of EBML, the value and its size. The marker
+ * bit is kept for element IDs. */
+fn vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let size = first.leading_zeros() as usize + 1;
+    if size > 8 || data.len() < size {
+        return None;
+    }
+    let first = if keep_marker {
+        first as u64
+    }
+    else {
+        first as u64 & (0xff >> size)
+    };
+    let value = data[1..size].iter()
+        .fold(first, |value, b| value << 8 | *b as u64);
+
+    Some((value, size))
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |value, b| value << 8 | *b as u64)
+}
+
+/* Child elements of a master element in memory, unknown sizes are
+ * not allowed */
+fn children(mut data: &[u8]) -> Result<Vec<(u32, &[u8])>> {
+    let mut elements = Vec::new();
+
+    while !data.is_empty() {
+        let (id, id_size) = vint(data, true)
+            .ok_or_else(|| err("Invalid element ID"))?;
+        let (size, size_size) = vint(&data[id_size..], false)
+            .ok_or_else(|| err("Invalid element size"))?;
+        let start = id_size + size_size;
+        if size > (data.len() - start) as u64 {
+            return Err(err("Element past end of parent"));
+        }
+        let end = start + size as usize;
+        elements.push((id as u32, &data[start..end]));
+        data = &data[end..];
+    }
+
+    Ok(elements)
+}
+
+fn child(elements: &[(u32, &[u8])], id: u32) -> Option<u64> {
+    elements.iter()
+        .find(|(element, _)| *element == id)
+        .map(|(_, data)| uint(data))
+}
+
+/* The first H.264 track of a Tracks element */
+fn parse_tracks(data: &[u8]) -> Result<Option<Track>> {
+    for (id, entry) in children(data)? {
+        if id != TRACK_ENTRY {
+            continue;
+        }
+        let entry = children(entry)?;
+        let h264 = entry.iter().any(|(id, codec_id)| {
+            *id == CODEC_ID && *codec_id == CODEC_ID_H264.as_bytes()
+        });
+        if !h264 {
+            continue;
+        }
+        if entry.iter().any(|(id, _)| *id == CONTENT_ENCODINGS) {
+            return Err(not_impl("ContentEncodings of H.264 track"));
+        }
+
+        let track_number = child(&entry, TRACK_NUMBER)
+            .ok_or_else(|| err("TrackNumber missing"))?;
+        let codec_private = entry.iter()
+            .find(|(id, _)| *id == CODEC_PRIVATE)
+            .ok_or_else(|| err("CodecPrivate missing"))?;
+        let configuration = AvcConfiguration::parse(codec_private.1)?;
+        let video = entry.iter().find(|(id, _)| *id == VIDEO);
+        let (width, height) = match video {
+            Some((_, video)) => {
+                let video = children(video)?;
+                (child(&video, PIXEL_WIDTH).unwrap_or(0) as u32,
+                 child(&video, PIXEL_HEIGHT).unwrap_or(0) as u32)
+            },
+            None => (0, 0),
+        };
+
+        return Ok(Some(Track {
+            track_number,
+            width,
+            height,
+            default_duration: child(&entry, DEFAULT_DURATION),
+            configuration,
+        }));
+    }
+
+    Ok(None)
+}
+
+/* Sizes of the frames of a laced block, `data` starts at the number
+ * of frames */
+fn lace_sizes(lacing: u8, data: &[u8]) -> Result<(Vec<usize>, usize)> {
+    let count = match data.first() {
+        Some(count) => *count as usize + 1,
+        None => return Err(err("Lacing past end of block")),
+    };
+    let mut pos = 1;
+    let mut sizes = Vec::with_capacity(count);
+
+    match lacing {
+        /* Xiph */
+        1 => for _ in 1..count {
+            let mut size = 0;
+            loop {
+                let byte = *data.get(pos)
+                    .ok_or_else(|| err("Lacing past end of block"))?;
+                pos += 1;
+                size += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+            sizes.push(size);
+        },
+        /* EBML, sizes after the first are signed differences */
+        3 => {
+            let mut size: i64 = 0;
+            for i in 1..count {
+                let (value, length) = vint(&data[pos..], false)
+                    .ok_or_else(|| err("Lacing past end of block"))?;
+                pos += length;
+                let next = if i == 1 {
+                    Some(value as i64)
+                }
+                else {
+                    let bias = (1 << (7 * length - 1)) - 1;
+                    size.checked_add(value as i64)
+                        .and_then(|size| size.checked_sub(bias))
+                };
+                size = next.ok_or_else(|| err("Lace size overflow"))?;
+                if size < 0 {
+                    return Err(err("Negative lace size"));
+                }
+                sizes.push(size as usize);
+            }
+        },
+        /* Fixed size */
+        _ => {
+            let rest = data.len() - pos;
+            if !rest.is_multiple_of(count) {
+                return Err(err("Fixed size lacing not evenly divided"));
+            }
+            sizes.resize(count - 1, rest / count);
+        },
+    }
+
+    let used = sizes.iter()
+        .try_fold(pos, |used, size| used.checked_add(*size))
+        .filter(|used| *used <= data.len())
+        .ok_or_else(|| err("Laced frame past end of block"))?;
+    sizes.push(data.len() - used);
+
+    Ok((sizes, pos))
+}
+
+impl<R: Read> Elements<R> {
+    /* Reads exactly `buf.len()` bytes, false at end of data before the
+     * first byte */
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<bool> {
+        let n = read_all(&mut self.reader, buf)
+            .map_err(|e| io_err(ParserUnit::Mkv(), e))?;
+        self.offset += n as u64;
+        if n < buf.len() {
+            if n == 0 {
+                return Ok(false);
+            }
+            return Err(err("Element past end of data"));
+        }
+
+        Ok(true)
+    }
+
+    fn read_vint(&mut self, keep_marker: bool)
+                 -> Result<Option<(u64, usize)>> {
+        let mut bytes = [0; 8];
+        if !self.read_bytes(&mut bytes[..1])? {
+            return Ok(None);
+        }
+        let size = bytes[0].leading_zeros() as usize + 1;
+        if size > 8 {
+            return Err(err("Invalid variable size integer"));
+        }
+        if !self.read_bytes(&mut bytes[1..size])? && size > 1 {
+            return Err(err("Element past end of data"));
+        }
+
+        Ok(vint(&bytes[..size], keep_marker))
+    }
+
+    /* ID and size of the next element, the size is None when unknown.
+     * None at end of data. */
+    fn header(&mut self) -> Result<Option<(u32, Option<u64>)>> {
+        let id = match self.read_vint(true)? {
+            Some((id, _)) => id as u32,
+            None => return Ok(None),
+        };
+        let (size, length) = match self.read_vint(false)? {
+            Some(size) => size,
+            None => return Err(err("Element size past end of data")),
+        };
+        let unknown = size == (1 << (7 * length)) - 1;
+
+        Ok(Some((id, if unknown { None } else { Some(size) })))
+    }
+
+    fn read_data(&mut self, size: Option<u64>) -> Result<Vec<u8>> {
+        let size = match size {
+            Some(size) if size <= MAX_ELEMENT_SIZE => size as usize,
+            Some(_) => return Err(err("Element too large")),
+            None => return Err(not_impl("Unknown size of element")),
+        };
+        let mut data = vec![0; size];
+        if size > 0 && !self.read_bytes(&mut data)? {
+            return Err(err("Element past end of data"));
+        }
+
+        Ok(data)
+    }
+
+    fn skip(&mut self, size: Option<u64>) -> Result<()> {
+        let size = match size {
+            Some(size) => size,
+            None => return Err(not_impl("Unknown size of element")),
+        };
+        let skipped = io::copy(&mut (&mut self.reader).take(size),
+                               &mut io::sink())
+            .map_err(|e| io_err(ParserUnit::Mkv(), e))?;
+        self.offset += skipped;
+        if skipped < size {
+            return Err(err("Element past end of data"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> MkvReader<R> {
+    /// Reads the elements up to and including Tracks.
+    pub fn new(reader: R) -> Result<MkvReader<R>> {
+        let mut elements = Elements { reader, offset: 0 };
+        let mut timecode_scale = 1_000_000;
+
+        let mut first = true;
+        let track = loop {
+            let (id, size) = match elements.header()? {
+                Some(header) => header,
+                None => return Err(err("No Tracks element")),
+            };
+            if first && id != EBML {
+                return Err(err("Not an EBML file"));
+            }
+            first = false;
+            match id {
+                SEGMENT => {},
+                INFO => {
+                    let info = elements.read_data(size)?;
+                    if let Some(scale) = child(&children(&info)?,
+                                               TIMECODE_SCALE) {
+                        timecode_scale = scale;
+                    }
+                },
+                TRACKS => match parse_tracks(&elements.read_data(size)?)? {
+                    Some(track) => break track,
+                    None => return Err(not_impl("No H.264 track")),
+                },
+                CLUSTER => return Err(err("Cluster before Tracks")),
+                _ => elements.skip(size)?,
+            }
+        };
+
+        Ok(MkvReader {
+            elements,
+            track,
+            timecode_scale,
+            cluster_timecode: 0,
+            frames: VecDeque::new(),
+            started: false,
+        })
+    }
+
+    pub fn track(&self) -> &Track {
+        &self.track
+    }
+
+    /* Queues the frames of a Block or SimpleBlock of the track,
+     * `keyframe` is None for SimpleBlock where it is a flag */
+    fn block(&mut self, offset: u64, data: &[u8], keyframe: Option<bool>)
+             -> Result<()> {
+        let (track_number, length) = vint(data, false)
+            .ok_or_else(|| err("Invalid block track number"))?;
+        if track_number != self.track.track_number {
+            return Ok(());
+        }
+        if data.len() < length + 3 {
+            return Err(err("Block header past end of block"));
+        }
+        let relative = i16::from_be_bytes([data[length], data[length + 1]]);
+        let flags = data[length + 2];
+        let keyframe = keyframe.unwrap_or(flags & 0x80 != 0);
+        let data = &data[length + 3..];
+
+        let lacing = flags >> 1 & 0x03;
+        let (sizes, mut pos) = if lacing == 0 {
+            (vec![data.len()], 0)
+        }
+        else {
+            lace_sizes(lacing, data)?
+        };
+
+        let scale = i64::try_from(self.timecode_scale).ok();
+        let timecode = i64::try_from(self.cluster_timecode).ok()
+            .and_then(|timecode| timecode.checked_add(relative as i64))
+            .and_then(|timecode| timecode.checked_mul(scale?))
+            .ok_or_else(|| err("Block timecode overflow"))?;
+        let duration =
+            i64::try_from(self.track.default_duration.unwrap_or(0)).ok();
+        for (i, size) in sizes.into_iter().enumerate() {
+            let timecode = duration
+                .and_then(|duration| duration.checked_mul(i as i64))
+                .and_then(|offset| timecode.checked_add(offset))
+                .ok_or_else(|| err("Block timecode overflow"))?;
+            self.frames.push_back(Frame {
+                offset,
+                timecode,
+                keyframe,
+                data: data[pos..pos + size].to_vec(),
+            });
+            pos += size;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next frame of the H.264 track, None at end of data.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            if let Some(frame) = self.frames.pop_front() {
+                return Ok(Some(frame));
+            }
+
+            let offset = self.elements.offset;
+            let (id, size) = match self.elements.header()? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            match id {
+                /* Children are read in order, also for unknown sizes */
+                SEGMENT | CLUSTER => {},
+                TIMECODE => {
+                    let timecode = self.elements.read_data(size)?;
+                    self.cluster_timecode = uint(&timecode);
+                },
+                SIMPLE_BLOCK => {
+                    let data = self.elements.read_data(size)?;
+                    self.block(offset, &data, None)?;
+                },
+                BLOCK_GROUP => {
+                    let data = self.elements.read_data(size)?;
+                    let group = children(&data)?;
+                    /* Frames referencing no other frame are keyframes */
+                    let keyframe = !group.iter()
+                        .any(|(id, _)| *id == REFERENCE_BLOCK);
+                    if let Some((_, block)) = group.iter()
+                        .find(|(id, _)| *id == BLOCK) {
+                        self.block(offset, block, Some(keyframe))?;
+                    }
+                },
+                _ => self.elements.skip(size)?,
+            }
+        }
+    }
+}
+
+impl<R: Read> Demuxer for MkvReader<R> {
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut annex_b = Vec::new();
+        if !self.started {
+            let configuration = &self.track.configuration;
+            for nal_unit in configuration.sequence_parameter_sets.iter()
+                .chain(configuration.picture_parameter_sets.iter()) {
+                annex_b.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+                annex_b.extend_from_slice(nal_unit);
+            }
+            self.started = true;
+        }
+        match self.next_frame()? {
+            Some(frame) => {
+                let length_size = self.track.configuration.length_size();
+                annex_b.append(&mut avcc::avcc_to_annex_b(&frame.data,
+                                                          length_size)?);
+            },
+            None if annex_b.is_empty() => return Ok(None),
+            None => {},
+        }
+
+        Ok(Some(annex_b))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use avcc::tests::AVCC;
+    use demux::tests::nal_types;
+    use super::*;
+
+    /* Element with an eight byte size */
+    fn element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut element: Vec<u8> = id.to_be_bytes().iter()
+            .skip_while(|b| **b == 0)
+            .cloned()
+            .collect();
+        element.push(0x01);
+        element.extend_from_slice(&(data.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(data);
+
+        element
+    }
+
+    /* Element of unknown size */
+    fn open_element(id: u32) -> Vec<u8> {
+        let mut element = id.to_be_bytes().to_vec();
+        element.push(0xff);
+
+        element
+    }
+
+    fn tracks(codec_id: &str) -> Vec<u8> {
+        /* An audio track before the video track */
+        let audio = [element(TRACK_NUMBER, &[1]),
+                     element(CODEC_ID, b"A_OPUS")].concat();
+        let video = [
+            element(TRACK_NUMBER, &[2]),
+            element(CODEC_ID, codec_id.as_bytes()),
+            element(DEFAULT_DURATION, &40_000_000u32.to_be_bytes()),
+            element(CODEC_PRIVATE, &AVCC),
+            element(VIDEO, &[element(PIXEL_WIDTH, &[0x01, 0x40]),
+                             element(PIXEL_HEIGHT, &[0xf0])].concat()),
+        ].concat();
+
+        element(TRACKS, &[element(TRACK_ENTRY, &audio),
+                          element(TRACK_ENTRY, &video)].concat())
+    }
+
+    fn block(track: u8, relative: i16, flags: u8, data: &[u8]) -> Vec<u8> {
+        let mut block = vec![0x80 | track];
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(flags);
+        block.extend_from_slice(data);
+
+        block
+    }
+
+    /* AUD and IDR slice, and AUD and P slice, length size 4 */
+    const IDR: [u8; 14] = [0x00, 0x00, 0x00, 0x02, 0x09, 0x10,
+                           0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00];
+    const P: [u8; 13] = [0x00, 0x00, 0x00, 0x02, 0x09, 0x30,
+                         0x00, 0x00, 0x00, 0x03, 0x41, 0x9a, 0x02];
+
+    fn file(codec_id: &str) -> Vec<u8> {
+        let mut file = element(EBML, &element(0x4282, b"matroska"));
+        /* Segment and Cluster of unknown size */
+        file.extend(open_element(SEGMENT));
+        file.extend(element(INFO, &element(TIMECODE_SCALE, &[0x0f, 0x42,
+                                                              0x40])));
+        file.extend(tracks(codec_id));
+        file.extend(open_element(CLUSTER));
+        file.extend(element(TIMECODE, &[0x03, 0xe8]));
+        file.extend(element(SIMPLE_BLOCK, &block(1, 0, 0x80, &[0xfc])));
+        file.extend(element(SIMPLE_BLOCK, &block(2, 0, 0x80, &IDR)));
+        file.extend(element(BLOCK_GROUP, &[
+            element(BLOCK, &block(2, 80, 0x00, &P)),
+            element(REFERENCE_BLOCK, &[0xb0]),
+        ].concat()));
+        /* Xiph lacing of two P frames, then EBML lacing of three */
+        let xiph = [&[0x01, P.len() as u8][..], &P, &P].concat();
+        file.extend(element(SIMPLE_BLOCK, &block(2, 120, 0x02, &xiph)));
+        let ebml = [&[0x02, 0x80 | P.len() as u8, 0xbf][..], &P, &P, &P]
+            .concat();
+        file.extend(element(SIMPLE_BLOCK, &block(2, 200, 0x06, &ebml)));
+
+        file
+    }
+
+    #[test]
+    fn read_frames() {
+        let mut reader = MkvReader::new(Cursor::new(file(CODEC_ID_H264)))
+            .unwrap();
+        let mut frames = Vec::new();
+
+        while let Some(frame) = reader.next_frame().unwrap() {
+            frames.push(frame);
+        }
+
+        let track = reader.track();
+        assert_eq!((track.track_number, track.width, track.height),
+                   (2, 320, 240));
+        let timecodes: Vec<i64> = frames.iter()
+            .map(|f| f.timecode / 1_000_000)
+            .collect();
+        assert_eq!(timecodes, [1000, 1080, 1120, 1160, 1200, 1240, 1280]);
+        assert_eq!(frames[0].data, IDR);
+        assert!(frames[0].keyframe && !frames[1].keyframe);
+        assert!(frames[1..].iter().all(|f| f.data == P));
+    }
+
+    #[test]
+    fn parameter_sets() {
+        let reader = MkvReader::new(Cursor::new(file(CODEC_ID_H264)))
+            .unwrap();
+
+        let parameter_sets = reader.track().configuration.parameter_sets()
+            .unwrap();
+
+        let sps = parameter_sets.sps(0).unwrap();
+        assert_eq!((sps.width(), sps.height()), (320, 240));
+        assert!(parameter_sets.pps(0).is_some());
+    }
+
+    #[test]
+    fn parse_length_prefixed() {
+        let mut reader = MkvReader::new(Cursor::new(file(CODEC_ID_H264)))
+            .unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+
+        let types = avcc::tests::nal_types(&frame.data, 4);
+
+        assert_eq!(types, [9, 5]);
+    }
+
+    #[test]
+    fn read_annex_b() {
+        let reader = MkvReader::new(Cursor::new(file(CODEC_ID_H264)))
+            .unwrap();
+
+        let types = nal_types(reader);
+
+        assert_eq!(types.len(), 2 + 2 * 7);
+        assert_eq!(types[..6], [7, 8, 9, 5, 9, 1]);
+    }
+
+    #[test]
+    fn no_h264_track() {
+        let res = MkvReader::new(Cursor::new(file("V_VP9")));
+
+        assert!(res.is_err());
+    }
+
+    /* File with one cluster at `timecode` holding `block` */
+    fn cluster(timecode: &[u8], block: &[u8]) -> Vec<u8> {
+        let mut file = element(EBML, &element(0x4282, b"matroska"));
+        file.extend(open_element(SEGMENT));
+        file.extend(tracks(CODEC_ID_H264));
+        file.extend(open_element(CLUSTER));
+        file.extend(element(TIMECODE, timecode));
+        file.extend(element(SIMPLE_BLOCK, block));
+
+        file
+    }
+
+    #[test]
+    fn untrusted_sizes() {
+        let timecode = cluster(&[0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+                               &block(2, 0, 0x80, &IDR));
+        /* 256 EBML laced frames with growing eight byte sizes */
+        let mut ebml = vec![0xff, 0x01, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff,
+                            0xff];
+        for _ in 2..256 {
+            ebml.extend_from_slice(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                     0xff, 0xfe]);
+        }
+        let lacing = cluster(&[0x00], &block(2, 0, 0x06, &ebml));
+
+        for file in [timecode, lacing].iter() {
+            let mut reader = MkvReader::new(Cursor::new(file)).unwrap();
+            assert!(reader.next_frame().is_err());
+        }
+    }
+}
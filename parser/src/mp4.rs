@@ -0,0 +1,760 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use avcc::{self, AvcConfiguration};
+use demux::Demuxer;
+use super::*;
+
+/* Largest box or sample read into memory, sizes are not trusted */
+const MAX_BOX_SIZE: u64 = 1 << 28;
+/* Largest number of samples of a track */
+const MAX_SAMPLES: usize = 1 << 24;
+
+/// A sample of the H.264 track, times are in the track timescale.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Sample {
+    /// Byte offset in the file
+    pub offset: u64,
+    pub size: u32,
+    /// Decoding time, DTS
+    pub decode_time: u64,
+    /// Composition time minus decoding time
+    pub composition_offset: i64,
+    pub sync: bool,
+}
+
+impl Sample {
+    /// Composition time, PTS.
+    pub fn composition_time(&self) -> i64 {
+        (self.decode_time as i64).saturating_add(self.composition_offset)
+    }
+}
+
+/// The first H.264 track of the file, with the samples of the movie
+/// and of all movie fragments.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Track {
+    pub track_id: u32,
+    /// Units per second of the sample times
+    pub timescale: u32,
+    /// Sample entry type, avc1 or avc3
+    pub sample_entry: String,
+    pub width: u16,
+    pub height: u16,
+    pub configuration: AvcConfiguration,
+    pub samples: Vec<Sample>,
+}
+
+/// Reads the H.264 track of an ISO base media file, like .mp4 and
+/// .mov, as specified in ISO/IEC 14496-12 and 14496-15.
+///
+/// Also reads as an Annex B byte stream with `AnnexBReader`, the
+/// parameter sets of the configuration record followed by the NAL units
+/// of the samples, so the file can be parsed like a raw H.264 stream.
+pub struct Mp4Reader<R> {
+    reader: R,
+    track: Track,
+    /* Index of the next sample */
+    next: usize,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Mp4();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn not_impl(text: &str) -> ParserError {
+    let unit = ParserUnit::Mp4();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::NotImplemented(error)
+}
+
+/* Big endian integer of `n` bytes at `pos` */
+fn be(data: &[u8], pos: usize, n: usize) -> Result<u64> {
+    match data.get(pos..pos + n) {
+        Some(bytes) => Ok(bytes.iter().fold(0, |v, b| v << 8 | *b as u64)),
+        None => Err(err("box too small")),
+    }
+}
+
+/* Reads `size` bytes, allocating no more than there is in the file */
+fn read_data<R: Read>(r: &mut R, size: u64) -> Result<Vec<u8>> {
+    if size > MAX_BOX_SIZE {
+        return Err(err("box or sample too large"));
+    }
+    let mut data = Vec::new();
+    r.take(size).read_to_end(&mut data)
+        .map_err(|e| io_err(ParserUnit::Mp4(), e))?;
+    if (data.len() as u64) < size {
+        return Err(err("box or sample past end of file"));
+    }
+
+    Ok(data)
+}
+
+fn overflow() -> ParserError {
+    err("sample offset or time out of range")
+}
+
+/* Children of a box, as type and content. `data` is the content of
+ * the parent after any fields preceding the children. */
+fn children(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+
+    while pos + 8 <= data.len() {
+        let mut size = be(data, pos, 4)? as usize;
+        let mut header = 8;
+        if size == 1 {
+            size = be(data, pos + 8, 8)? as usize;
+            header = 16;
+        }
+        else if size == 0 {
+            size = data.len() - pos;
+        }
+        let end = match pos.checked_add(size).filter(|e| *e <= data.len()) {
+            Some(end) if size >= header => end,
+            _ => return Err(err("box size out of bounds")),
+        };
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&data[pos + 4..pos + 8]);
+        boxes.push((kind, &data[pos + header..end]));
+        pos = end;
+    }
+
+    Ok(boxes)
+}
+
+/* Content of the first child box of type `kind` */
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<Option<&'a [u8]>> {
+    Ok(children(data)?.into_iter().find(|b| b.0 == *kind).map(|b| b.1))
+}
+
+fn required<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<&'a [u8]> {
+    match child(data, kind)? {
+        Some(content) => Ok(content),
+        None => {
+            let name = String::from_utf8_lossy(kind);
+            Err(err(&format!("{} box missing", name)))
+        },
+    }
+}
+
+/* Entries of a full box table with the entry count after version and
+ * flags, `size` bytes each */
+fn table(data: &[u8], size: usize) -> Result<Vec<&[u8]>> {
+    let count = be(data, 4, 4)? as usize;
+    let entries = &data[8..];
+    if entries.len() / size < count {
+        return Err(err("table past end of box"));
+    }
+
+    Ok(entries.chunks(size).take(count).collect())
+}
+
+/* Number of bytes from the current position to the end of file */
+fn remaining<R: Seek>(r: &mut R) -> io::Result<u64> {
+    let pos = r.stream_position()?;
+    let end = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(pos))?;
+
+    Ok(end - pos)
+}
+
+/* Header of the top level box at the current position: type, size of
+ * the header and size of the whole box, None at end of file */
+fn box_header<R: Read + Seek>(r: &mut R)
+                              -> Result<Option<([u8; 4], u64, u64)>> {
+    let mut header = [0; 8];
+    let n = read_all(r, &mut header)
+        .map_err(|e| io_err(ParserUnit::Mp4(), e))?;
+    if n < header.len() {
+        return Ok(None);
+    }
+    let mut kind = [0; 4];
+    kind.copy_from_slice(&header[4..]);
+    let mut size = be(&header, 0, 4)?;
+    let mut header_size = 8;
+    if size == 1 {
+        let mut large = [0; 8];
+        r.read_exact(&mut large)
+            .map_err(|e| io_err(ParserUnit::Mp4(), e))?;
+        size = be(&large, 0, 8)?;
+        header_size = 16;
+    }
+    else if size == 0 {
+        /* Box extends to end of file */
+        size = remaining(r).map_err(|e| io_err(ParserUnit::Mp4(), e))? +
+            header_size;
+    }
+    if size < header_size {
+        return Err(err("box size smaller than header"));
+    }
+
+    Ok(Some((kind, header_size, size)))
+}
+
+/* Defaults of the track for movie fragments, from trex */
+#[derive(Default)]
+struct TrackDefaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/* sample_is_non_sync_sample of the sample flags */
+fn is_sync(flags: u32) -> bool {
+    flags & 0x0001_0000 == 0
+}
+
+/* Sample table of the stbl box */
+fn sample_table(stbl: &[u8]) -> Result<Vec<Sample>> {
+    let stsz = required(stbl, b"stsz")?;
+    let sample_size = be(stsz, 4, 4)? as u32;
+    let sample_count = be(stsz, 8, 4)? as usize;
+    if sample_count > MAX_SAMPLES {
+        return Err(err("too many samples"));
+    }
+    let sizes: Vec<u32> = if sample_size != 0 {
+        vec![sample_size; sample_count]
+    }
+    else {
+        match stsz.get(12..12 + sample_count * 4) {
+            Some(sizes) => sizes.chunks(4)
+                .map(|s| be(s, 0, 4).unwrap() as u32).collect(),
+            None => return Err(err("stsz past end of box")),
+        }
+    };
+
+    let chunk_offsets: Vec<u64> = match child(stbl, b"stco")? {
+        Some(stco) => table(stco, 4)?.iter()
+            .map(|e| be(e, 0, 4).unwrap()).collect(),
+        None => match child(stbl, b"co64")? {
+            Some(co64) => table(co64, 8)?.iter()
+                .map(|e| be(e, 0, 8).unwrap()).collect(),
+            None => return Err(err("stco box missing")),
+        },
+    };
+
+    /* first_chunk and samples_per_chunk */
+    let stsc: Vec<(u64, u64)> = table(required(stbl, b"stsc")?, 12)?.iter()
+        .map(|e| (be(e, 0, 4).unwrap(), be(e, 4, 4).unwrap())).collect();
+    let mut samples = Vec::with_capacity(sample_count);
+    for (i, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk = i as u64 + 1;
+        let samples_per_chunk = stsc.iter().rev()
+            .find(|entry| entry.0 <= chunk)
+            .map_or(0, |entry| entry.1);
+        let mut offset = *chunk_offset;
+        for _ in 0..samples_per_chunk {
+            let size = match sizes.get(samples.len()) {
+                Some(size) => *size,
+                None => break,
+            };
+            samples.push(Sample {
+                offset,
+                size,
+                decode_time: 0,
+                composition_offset: 0,
+                sync: true,
+            });
+            offset = offset.checked_add(size as u64).ok_or_else(overflow)?;
+        }
+    }
+    if samples.len() != sample_count {
+        return Err(err("chunks do not contain all samples"));
+    }
+
+    /* Decoding times, sample_count and sample_delta. Counts past the
+     * last sample are ignored. */
+    let mut decode_time = 0;
+    let mut deltas = Vec::with_capacity(sample_count);
+    for entry in table(required(stbl, b"stts")?, 8)? {
+        let delta = be(entry, 4, 4)?;
+        let count = (be(entry, 0, 4)? as usize)
+            .min(sample_count - deltas.len());
+        deltas.extend((0..count).map(|_| delta));
+    }
+    for (sample, delta) in samples.iter_mut().zip(deltas) {
+        sample.decode_time = decode_time;
+        decode_time += delta;
+    }
+
+    /* Composition offsets, signed in version 1 */
+    if let Some(ctts) = child(stbl, b"ctts")? {
+        let signed = be(ctts, 0, 1)? == 1;
+        let mut i = 0;
+        for entry in table(ctts, 8)? {
+            let offset = be(entry, 4, 4)?;
+            let offset = if signed { offset as u32 as i32 as i64 }
+                         else { offset as i64 };
+            let count = be(entry, 0, 4)? as usize;
+            for sample in samples.iter_mut().skip(i).take(count) {
+                sample.composition_offset = offset;
+            }
+            i = i.saturating_add(count);
+        }
+    }
+
+    /* All samples are sync samples without stss */
+    if let Some(stss) = child(stbl, b"stss")? {
+        for sample in &mut samples {
+            sample.sync = false;
+        }
+        for entry in table(stss, 4)? {
+            let i = be(entry, 0, 4)? as usize;
+            if let Some(sample) = samples.get_mut(i.wrapping_sub(1)) {
+                sample.sync = true;
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/* Parses the trak box, None when it is not an H.264 video track */
+fn parse_track(trak: &[u8]) -> Result<Option<Track>> {
+    let tkhd = required(trak, b"tkhd")?;
+    /* Times are 64 bit in version 1 */
+    let version = be(tkhd, 0, 1)?;
+    let track_id = be(tkhd, if version == 1 { 20 } else { 12 }, 4)? as u32;
+    let mdia = required(trak, b"mdia")?;
+    let mdhd = required(mdia, b"mdhd")?;
+    let version = be(mdhd, 0, 1)?;
+    let timescale = be(mdhd, if version == 1 { 20 } else { 12 }, 4)? as u32;
+    let stbl = required(required(mdia, b"minf")?, b"stbl")?;
+    let stsd = required(stbl, b"stsd")?;
+
+    /* Sample entries follow version, flags and entry_count */
+    let entries = match stsd.get(8..) {
+        Some(entries) => children(entries)?,
+        None => return Err(err("stsd box too small")),
+    };
+    let (kind, entry) = match entries.into_iter()
+        .find(|e| &e.0 == b"avc1" || &e.0 == b"avc3") {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    /* SampleEntry and VisualSampleEntry fields before the boxes */
+    let width = be(entry, 24, 2)? as u16;
+    let height = be(entry, 26, 2)? as u16;
+    let avcc = required(&entry[78.min(entry.len())..], b"avcC")?;
+    let configuration = AvcConfiguration::parse(avcc)?;
+
+    Ok(Some(Track {
+        track_id,
+        timescale,
+        sample_entry: String::from_utf8_lossy(&kind).into_owned(),
+        width,
+        height,
+        configuration,
+        samples: sample_table(stbl)?,
+    }))
+}
+
+/* Appends the samples of the track fragments of `moof`, at byte
+ * `offset`, for track `track_id` */
+fn parse_fragment(moof: &[u8], offset: u64, track: &mut Track,
+                  defaults: &TrackDefaults) -> Result<()> {
+    for (kind, traf) in children(moof)? {
+        if &kind != b"traf" {
+            continue;
+        }
+        let tfhd = required(traf, b"tfhd")?;
+        let flags = be(tfhd, 0, 4)? & 0xff_ffff;
+        if be(tfhd, 4, 4)? as u32 != track.track_id {
+            continue;
+        }
+        let mut pos = 8;
+        let mut field = |present: bool, n: usize| -> Result<Option<u64>> {
+            if !present {
+                return Ok(None);
+            }
+            pos += n;
+            be(tfhd, pos - n, n).map(Some)
+        };
+        let base_data_offset = field(flags & 0x01 != 0, 8)?;
+        field(flags & 0x02 != 0, 4)?;
+        let duration = field(flags & 0x08 != 0, 4)?
+            .map_or(defaults.duration, |v| v as u32);
+        let size = field(flags & 0x10 != 0, 4)?
+            .map_or(defaults.size, |v| v as u32);
+        let sample_flags = field(flags & 0x20 != 0, 4)?
+            .map_or(defaults.flags, |v| v as u32);
+
+        let mut decode_time = match child(traf, b"tfdt")? {
+            Some(tfdt) if be(tfdt, 0, 1)? == 1 => be(tfdt, 4, 8)?,
+            Some(tfdt) => be(tfdt, 4, 4)?,
+            None => track.samples.last()
+                .map_or(0, |s| s.decode_time.saturating_add(duration as u64)),
+        };
+        /* Without base_data_offset, relative to the start of moof */
+        let mut data_offset = base_data_offset.unwrap_or(offset);
+
+        for (kind, trun) in children(traf)? {
+            if &kind != b"trun" {
+                continue;
+            }
+            let version = be(trun, 0, 1)?;
+            let trun_flags = be(trun, 0, 4)? & 0xff_ffff;
+            let count = be(trun, 4, 4)?;
+            if track.samples.len() as u64 + count > MAX_SAMPLES as u64 {
+                return Err(err("too many samples"));
+            }
+            let mut pos = 8;
+            if trun_flags & 0x01 != 0 {
+                let relative = be(trun, pos, 4)? as u32 as i32 as i64;
+                data_offset = (base_data_offset.unwrap_or(offset) as i64)
+                    .checked_add(relative).ok_or_else(overflow)? as u64;
+                pos += 4;
+            }
+            let mut first_sample_flags = None;
+            if trun_flags & 0x04 != 0 {
+                first_sample_flags = Some(be(trun, pos, 4)? as u32);
+                pos += 4;
+            }
+            for i in 0..count {
+                let mut sample_duration = duration;
+                let mut sample_size = size;
+                let mut flags = sample_flags;
+                let mut composition_offset = 0;
+                if trun_flags & 0x100 != 0 {
+                    sample_duration = be(trun, pos, 4)? as u32;
+                    pos += 4;
+                }
+                if trun_flags & 0x200 != 0 {
+                    sample_size = be(trun, pos, 4)? as u32;
+                    pos += 4;
+                }
+                if trun_flags & 0x400 != 0 {
+                    flags = be(trun, pos, 4)? as u32;
+                    pos += 4;
+                }
+                if trun_flags & 0x800 != 0 {
+                    let value = be(trun, pos, 4)?;
+                    composition_offset = if version == 1 {
+                        value as u32 as i32 as i64
+                    }
+                    else {
+                        value as i64
+                    };
+                    pos += 4;
+                }
+                if i == 0 {
+                    flags = first_sample_flags.unwrap_or(flags);
+                }
+                track.samples.push(Sample {
+                    offset: data_offset,
+                    size: sample_size,
+                    decode_time,
+                    composition_offset,
+                    sync: is_sync(flags),
+                });
+                data_offset = data_offset.checked_add(sample_size as u64)
+                    .ok_or_else(overflow)?;
+                decode_time = decode_time.checked_add(sample_duration as u64)
+                    .ok_or_else(overflow)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/* The H.264 track of the moov box and the fragment defaults */
+fn movie(moov: &[u8], defaults: &mut TrackDefaults)
+         -> Result<Option<Track>> {
+    let mut track = None;
+
+    for (kind, trak) in children(moov)? {
+        if &kind == b"trak" {
+            track = parse_track(trak)?;
+            if track.is_some() {
+                break;
+            }
+        }
+    }
+    let track_id = match track {
+        Some(ref track) => track.track_id,
+        None => return Ok(None),
+    };
+
+    if let Some(mvex) = child(moov, b"mvex")? {
+        for (kind, trex) in children(mvex)? {
+            if &kind == b"trex" && be(trex, 4, 4)? as u32 == track_id {
+                defaults.duration = be(trex, 12, 4)? as u32;
+                defaults.size = be(trex, 16, 4)? as u32;
+                defaults.flags = be(trex, 20, 4)? as u32;
+            }
+        }
+    }
+
+    Ok(track)
+}
+
+impl<R: Read + Seek> Mp4Reader<R> {
+    /// Reads the boxes of the file, except for the media data, and
+    /// selects the first avc1 or avc3 track.
+    pub fn new(mut reader: R) -> Result<Mp4Reader<R>> {
+        let mut track = None;
+        let mut defaults = TrackDefaults::default();
+        let mut offset = 0;
+
+        reader.seek(SeekFrom::Start(0))
+            .map_err(|e| io_err(ParserUnit::Mp4(), e))?;
+        while let Some((kind, header_size, size)) = box_header(&mut reader)? {
+            match &kind {
+                b"moov" | b"moof" => {
+                    let content = read_data(&mut reader, size - header_size)?;
+                    if &kind == b"moov" {
+                        track = movie(&content, &mut defaults)?;
+                    }
+                    else if let Some(ref mut track) = track {
+                        parse_fragment(&content, offset, track, &defaults)?;
+                    }
+                },
+                _ => {
+                    let next = offset.checked_add(size)
+                        .ok_or_else(|| err("box size out of range"))?;
+                    reader.seek(SeekFrom::Start(next))
+                        .map_err(|e| io_err(ParserUnit::Mp4(), e))?;
+                },
+            }
+            offset += size;
+        }
+
+        match track {
+            Some(track) => Ok(Mp4Reader {
+                reader,
+                track,
+                next: 0,
+            }),
+            None => Err(not_impl("No H.264 track")),
+        }
+    }
+
+    pub fn track(&self) -> &Track {
+        &self.track
+    }
+
+    /// Reads the next sample, None after the last sample.
+    pub fn next_sample(&mut self) -> Result<Option<(Sample, Vec<u8>)>> {
+        let sample = match self.track.samples.get(self.next) {
+            Some(sample) => sample.clone(),
+            None => return Ok(None),
+        };
+        self.next += 1;
+
+        self.reader.seek(SeekFrom::Start(sample.offset))
+            .map_err(|e| io_err(ParserUnit::Mp4(), e))?;
+        let data = read_data(&mut self.reader, sample.size as u64)?;
+
+        Ok(Some((sample, data)))
+    }
+}
+
+impl<R: Read + Seek> Demuxer for Mp4Reader<R> {
+    /* The first sample is preceded by the parameter sets */
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut annex_b = Vec::new();
+        if self.next == 0 && !self.track.samples.is_empty() {
+            let configuration = &self.track.configuration;
+            for nal_unit in configuration.sequence_parameter_sets.iter()
+                .chain(configuration.picture_parameter_sets.iter()) {
+                annex_b.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+                annex_b.extend_from_slice(nal_unit);
+            }
+        }
+        let data = match self.next_sample()? {
+            Some((_, data)) => data,
+            None => return Ok(None),
+        };
+        let length_size = self.track.configuration.length_size();
+        annex_b.append(&mut avcc::avcc_to_annex_b(&data, length_size)?);
+
+        Ok(Some(annex_b))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use avcc::tests::AVCC;
+    use demux::tests::nal_types;
+    use super::*;
+
+    /* Box of type `kind` around `content` */
+    fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(content.len() as u32 + 8).to_be_bytes());
+        data.extend_from_slice(kind);
+        data.extend_from_slice(content);
+
+        data
+    }
+
+    /* Full box content with version 0 and flags */
+    fn full(flags: u32, fields: &[u32]) -> Vec<u8> {
+        let mut data = flags.to_be_bytes().to_vec();
+        for field in fields {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+
+        data
+    }
+
+    /* Track 1 with two samples in one chunk at `chunk_offset` */
+    fn moov(chunk_offset: u32, fragmented: bool) -> Vec<u8> {
+        let mut avc1 = vec![0; 78];
+        avc1[24..28].copy_from_slice(&[0x01, 0x40, 0x00, 0xf0]);
+        avc1.extend(mp4_box(b"avcC", &AVCC));
+        let mut stsd = full(0, &[1]);
+        stsd.extend(mp4_box(b"avc1", &avc1));
+
+        let mut stbl = mp4_box(b"stsd", &stsd);
+        let count = if fragmented { 0 } else { 2 };
+        stbl.extend(mp4_box(b"stts", &full(0, &[1, count, 512])));
+        stbl.extend(mp4_box(b"stss", &full(0, &[1, 1])));
+        stbl.extend(mp4_box(b"stsz", &full(0, &[0, count, 8, 6])));
+        stbl.extend(mp4_box(b"stsc", &full(0, &[1, 1, count, 1])));
+        stbl.extend(mp4_box(b"stco", &full(0, &[1, chunk_offset])));
+        let minf = mp4_box(b"stbl", &stbl);
+        let mut mdia = mp4_box(b"mdhd", &full(0, &[0, 0, 12800, 0, 0]));
+        mdia.extend(mp4_box(b"minf", &minf));
+        let mut trak = mp4_box(b"tkhd", &full(0, &[0, 0, 1, 0, 0]));
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        let mut moov = mp4_box(b"trak", &trak);
+        if fragmented {
+            let trex = full(0, &[1, 1, 512, 0, 0x0001_0000]);
+            moov.extend(mp4_box(b"mvex", &mp4_box(b"trex", &trex)));
+        }
+
+        mp4_box(b"moov", &moov)
+    }
+
+    /* IDR slice header of a 320x240 picture and a P slice header */
+    const SAMPLES: [u8; 14] = [
+        0x00, 0x00, 0x00, 0x04, 0x65, 0x88, 0x84, 0x00,
+        0x00, 0x00, 0x00, 0x02, 0x41, 0x9a,
+    ];
+
+    /* Fragment of track 1 with the two samples */
+    fn moof(data_offset: u32) -> Vec<u8> {
+        let mut traf = mp4_box(b"tfhd", &full(0x02_0000, &[1]));
+        traf.extend(mp4_box(b"tfdt", &full(0, &[1024])));
+        let trun = full(0x0205, &[2, data_offset, 0x0200_0000, 8, 6]);
+        traf.extend(mp4_box(b"trun", &trun));
+        let mut moof = mp4_box(b"mfhd", &full(0, &[1]));
+        moof.extend(mp4_box(b"traf", &traf));
+
+        mp4_box(b"moof", &moof)
+    }
+
+    fn mp4(fragmented: bool) -> Vec<u8> {
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let moov_size = moov(0, fragmented).len();
+        let mdat_offset = (file.len() + moov_size + 8) as u32;
+        file.extend(moov(mdat_offset, fragmented));
+        if !fragmented {
+            file.extend(mp4_box(b"mdat", &SAMPLES));
+            return file;
+        }
+
+        /* Data offset relative to moof, samples right after it */
+        let moof_size = moof(0).len() as u32;
+        let moof = moof(moof_size + 8);
+        file.extend(moof);
+        file.extend(mp4_box(b"mdat", &SAMPLES));
+
+        file
+    }
+
+    #[test]
+    fn read_samples() {
+        let mut reader = Mp4Reader::new(Cursor::new(mp4(false))).unwrap();
+
+        {
+            let track = reader.track();
+            assert_eq!(track.track_id, 1);
+            assert_eq!(track.timescale, 12800);
+            assert_eq!(track.sample_entry, "avc1");
+            assert_eq!((track.width, track.height), (320, 240));
+            assert_eq!(track.configuration.avc_level_indication, 30);
+        }
+        let (first, data) = reader.next_sample().unwrap().unwrap();
+        let (second, _) = reader.next_sample().unwrap().unwrap();
+
+        assert_eq!(data, &SAMPLES[..8]);
+        assert!(first.sync);
+        assert!(!second.sync);
+        assert_eq!(second.decode_time, 512);
+        assert!(reader.next_sample().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_fragmented() {
+        let mut reader = Mp4Reader::new(Cursor::new(mp4(true))).unwrap();
+
+        let (first, data) = reader.next_sample().unwrap().unwrap();
+        let (second, data2) = reader.next_sample().unwrap().unwrap();
+
+        assert_eq!(data, &SAMPLES[..8]);
+        assert_eq!(data2, &SAMPLES[8..]);
+        assert_eq!(first.decode_time, 1024);
+        assert!(first.sync);
+        assert_eq!(second.decode_time, 1536);
+        assert!(!second.sync);
+    }
+
+    #[test]
+    fn untrusted_sizes() {
+        /* moov with a largesize far past the end of the file */
+        let large = [
+            0x00, 0x00, 0x00, 0x01, b'm', b'o', b'o', b'v',
+            0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ];
+        /* Fragment with 2^32 - 1 samples without per sample fields */
+        let mut traf = mp4_box(b"tfhd", &full(0x02_0000, &[1]));
+        traf.extend(mp4_box(b"trun", &full(0, &[u32::MAX])));
+        let mut fragmented = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        fragmented.extend(moov(0, true));
+        fragmented.extend(mp4_box(b"moof", &mp4_box(b"traf", &traf)));
+        /* Child box with a largesize wrapping around past its offset */
+        let mut wrapping = mp4_box(b"free", &[]);
+        wrapping.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        wrapping.extend_from_slice(b"trak");
+        wrapping.extend_from_slice(&0xffff_ffff_ffff_fffcu64.to_be_bytes());
+        let mut child = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        child.extend(mp4_box(b"moov", &wrapping));
+
+        assert!(Mp4Reader::new(Cursor::new(&large[..])).is_err());
+        assert!(Mp4Reader::new(Cursor::new(fragmented)).is_err());
+        assert!(Mp4Reader::new(Cursor::new(child)).is_err());
+    }
+
+    #[test]
+    fn read_annex_b() {
+        let reader = Mp4Reader::new(Cursor::new(mp4(false))).unwrap();
+
+        let types = nal_types(reader);
+
+        assert_eq!(types, [7, 8, 5, 1]);
+    }
+
+    #[test]
+    fn no_h264_track() {
+        let file = mp4_box(b"ftyp", b"isom\0\0\0\0");
+
+        assert!(Mp4Reader::new(Cursor::new(file)).is_err());
+    }
+}
@@ -1,24 +1,42 @@
 use std::io::prelude::*;
 use std::vec::Vec;
-use std::io::Cursor;
+
+use memchr::memchr_iter;
 
 use bitreader::BitReader;
+use bitwriter::BitWriter;
 use sps::SequenceParameterSet;
 use pps::PictureParameterSet;
+use paramsets::ParameterSets;
+use slice::SliceHeader;
+use sei::SeiMessage;
+use aud::AccessUnitDelimiter;
+use filler::FillerData;
+use trace::TraceElement;
 use super::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct NalUnit {
+    /// Byte offset of the NAL unit header in the input
+    pub offset: usize,
     pub nal_ref_idc: u8,
     pub nal_unit_type: u8,
     pub svc_extension_flag: bool,
     pub avc_3d_extension_flag: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub enum NalPayload {
     SequenceParameterSet(SequenceParameterSet),
     PictureParameterSet(PictureParameterSet),
+    Slice(SliceHeader),
+    Sei(Vec<SeiMessage>),
+    AccessUnitDelimiter(AccessUnitDelimiter),
+    EndOfSequence(),
+    EndOfStream(),
+    FillerData(FillerData),
 }
 
 impl fmt::Display for NalPayload {
@@ -28,6 +46,26 @@ impl fmt::Display for NalPayload {
                 write!(f, "Sequence parameter set"),
             NalPayload::PictureParameterSet(_) =>
                 write!(f, "Picture parameter set"),
+            NalPayload::Slice(ref header) =>
+                write!(f, "Slice header, {:?} slice",
+                       header.slice_type()),
+            NalPayload::Sei(ref messages) => {
+                write!(f, "SEI")?;
+                for (i, message) in messages.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { ", " };
+                    write!(f, "{}{}", separator, message.payload)?;
+                }
+                Ok(())
+            },
+            NalPayload::AccessUnitDelimiter(ref aud) =>
+                write!(f, "Access unit delimiter, primary_pic_type {}",
+                       aud.primary_pic_type),
+            NalPayload::EndOfSequence() =>
+                write!(f, "End of sequence"),
+            NalPayload::EndOfStream() =>
+                write!(f, "End of stream"),
+            NalPayload::FillerData(ref filler) =>
+                write!(f, "Filler data, {} bytes", filler.length),
         }
     }
 }
@@ -35,7 +73,7 @@ impl fmt::Display for NalPayload {
 fn err(text: &str) -> ParserError {
     let unit = ParserUnit::Nal();
     let description = String::from(text);
-    let error = ParserUnitError { unit, description };
+    let error = ParserUnitError { unit, description, location: None };
 
     ParserError::InvalidStream(error)
 }
@@ -43,30 +81,74 @@ fn err(text: &str) -> ParserError {
 fn not_impl(text: &str) -> ParserError {
     let unit = ParserUnit::Nal();
     let description = String::from(text);
-    let error = ParserUnitError { unit, description };
+    let error = ParserUnitError { unit, description, location: None };
 
     ParserError::NotImplemented(error)
 }
 
+/// Removes emulation_prevention_three_byte from the NAL unit bytes,
+/// see 7.4.1.
+pub fn remove_emulation_prevention(bytes: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(bytes.len());
+    let mut start = 0;
+
+    for i in memchr_iter(0x03, bytes) {
+        /* Zero bytes before a removed byte do not count */
+        if i >= start + 2 && bytes[i - 1] == 0x00 && bytes[i - 2] == 0x00 {
+            rbsp.extend_from_slice(&bytes[start..i]);
+            start = i + 1;
+        }
+    }
+    rbsp.extend_from_slice(&bytes[start..]);
+
+    rbsp
+}
+
+/* Location of an error at the current position of `r`, the syntax
+ * element is used if it was named after bit position `start`. */
+fn location<R: BufRead>(r: &BitReader<R>, nal_offset: usize,
+                        start: usize) -> ErrorLocation {
+    match r.element() {
+        Some(element) if r.element_pos() >= start => ErrorLocation {
+            nal_offset,
+            bit_offset: r.element_pos() - start,
+            element: Some(element),
+        },
+        _ => ErrorLocation {
+            nal_offset,
+            bit_offset: r.bit_pos() - start,
+            element: None,
+        },
+    }
+}
+
 impl NalUnit {
     /// Starts parsing of NAL unit at the current position of the
     /// bitreader. Caller should make sure that position is after
-    /// the start code (0x00000001/0x000001) and on an even byte
-    /// boundary.
-    pub fn parse<R: Read>(r: &mut BitReader<R>) ->
-                          Result<(NalUnit, Vec<u8>)> {
+    /// the start code (0x00000001/0x000001), or the length with length
+    /// framing, and on an even byte boundary.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) ->
+                             Result<(NalUnit, Vec<u8>)> {
+        let start = r.bit_pos();
+        let offset = start / 8;
+
+        NalUnit::parse_unit(r, offset)
+            .map_err(|e| e.at(location(r, offset, start)))
+    }
 
+    fn parse_unit<R: BufRead>(r: &mut BitReader<R>, offset: usize) ->
+                              Result<(NalUnit, Vec<u8>)> {
         if !r.is_byte_aligned() {
             return Err(err("Should be byte aligned at start of nal"));
         }
 
-        let forbidden_zero_bit = r.u64(1)?;
+        let forbidden_zero_bit = r.name("forbidden_zero_bit").u64(1)?;
         if forbidden_zero_bit != 0 {
             return Err(err("Forbidden_zero_bit is not 0"));
         }
 
-        let nal_ref_idc = r.u8(2)?;
-        let nal_unit_type = r.u8(5)?;
+        let nal_ref_idc = r.name("nal_ref_idc").u8(2)?;
+        let nal_unit_type = r.name("nal_unit_type").u8(5)?;
 
         let mut svc_extension_flag = false;
         let mut avc_3d_extension_flag = false;
@@ -75,21 +157,22 @@ impl NalUnit {
            nal_unit_type == 21 {
 
             if nal_unit_type != 21 {
-                svc_extension_flag = r.flag()?;
+                svc_extension_flag = r.name("svc_extension_flag").flag()?;
             }
             else {
-                avc_3d_extension_flag = r.flag()?;
+                avc_3d_extension_flag =
+                    r.name("avc_3d_extension_flag").flag()?;
             }
 
             if svc_extension_flag {
                 /* 3 bytes, svc extension */
-                return Err(err("svc extension not implemented"));
+                return Err(not_impl("svc extension not implemented"));
             }
             else if avc_3d_extension_flag {
-                return Err(err("avc 3d extension not implemented"));
+                return Err(not_impl("avc 3d extension not implemented"));
             }
             else {
-                return Err(err("mvc extension not implemented"));
+                return Err(not_impl("mvc extension not implemented"));
             }
         }
 
@@ -97,35 +180,13 @@ impl NalUnit {
             return Err(err("Should be byte aligned at start of nal rbsp"));
         }
 
-        /* Read RBSP bytes until next nal or end of data */
-        let mut rbsp = Vec::new();
-        let mut num_zeroes = 0;
-        loop {
-            match r.b() {
-                Ok(b) => {
-                    match b {
-                        0x00 => num_zeroes += 1,
-                        0x01 => {
-                            if num_zeroes == 2 ||
-                               num_zeroes == 3 {
-                                    let len = rbsp.len();
-                                    rbsp.truncate(len - num_zeroes);
-                                    break;
-                            }
-                            num_zeroes = 0;
-                        }
-                        _ => num_zeroes = 0,
-                    }
-                    rbsp.push(b);
-                },
-                Err(e) => match e {
-                    ParserError::BitReaderEndOfStream { .. } => break,
-                    _ => return Err(e),
-                }
-            }
-        }
+        /* Read NAL bytes until the next NAL unit or end of data */
+        let mut bytes = Vec::new();
+        r.read_nal_unit(&mut bytes)?;
+        let rbsp = remove_emulation_prevention(&bytes);
 
         let nal = NalUnit {
+            offset,
             nal_ref_idc,
             nal_unit_type,
             svc_extension_flag,
@@ -135,79 +196,168 @@ impl NalUnit {
         Ok((nal, rbsp))
     }
 
-    /// Positions bitreader right after startcode.
+    /// Writes the NAL unit with a four byte start code, the header and
+    /// `rbsp` with emulation prevention bytes inserted.
+    pub fn write_rbsp(&self, rbsp: &[u8]) -> Result<Vec<u8>> {
+        if self.nal_unit_type == 14 || self.nal_unit_type == 20 ||
+           self.nal_unit_type == 21 {
+            return Err(not_impl("NAL unit header extension"));
+        }
+
+        let mut w = BitWriter::new();
+        /* forbidden_zero_bit */
+        w.u8(1, 0)?;
+        w.u8(2, self.nal_ref_idc)?;
+        w.u8(5, self.nal_unit_type)?;
+        for byte in rbsp {
+            w.b(*byte)?;
+        }
+
+        let mut bytes = vec![0x00, 0x00, 0x00, 0x01];
+        bytes.append(&mut w.into_bytes());
+
+        Ok(bytes)
+    }
+
+    /// Writes the NAL unit with `payload` in Annex B format, see
+    /// `write_rbsp`. Slices and SEI can not be written since they are
+    /// not completely parsed.
+    pub fn write(&self, payload: &NalPayload) -> Result<Vec<u8>> {
+        let mut w = BitWriter::new_rbsp();
+
+        match *payload {
+            NalPayload::SequenceParameterSet(ref sps) => sps.write(&mut w)?,
+            NalPayload::PictureParameterSet(ref pps) => pps.write(&mut w)?,
+            NalPayload::AccessUnitDelimiter(ref aud) => aud.write(&mut w)?,
+            NalPayload::EndOfSequence() | NalPayload::EndOfStream() => {},
+            NalPayload::FillerData(ref filler) => filler.write(&mut w)?,
+            NalPayload::Slice(_) => return Err(not_impl("Write slice")),
+            NalPayload::Sei(_) => return Err(not_impl("Write SEI")),
+        }
+
+        self.write_rbsp(&w.into_bytes())
+    }
+
+    /// Positions bitreader right after startcode, or the length with
+    /// length framing.
     /// Call upon start of parsing and whenever parsing fails to
     /// reposition on start of new nal.
     ///
     /// Returns ok when bitreader reached end of data.
     /// Returns err upon IO error.
-    pub fn next<R: Read>(r: &mut BitReader<R>) -> Result<bool> {
-
-        let mut num_zeroes = 0;
-
-        r.byte_align();
-        loop {
-            match r.b() {
-                Ok(b) => {
-                    match b {
-                        0x00 => num_zeroes += 1,
-                        0x01 => {
-                            if num_zeroes == 2 ||
-                               num_zeroes == 3 {
-                                return Ok(true);
-                            }
-                            num_zeroes = 0;
-                        }
-                        _ => num_zeroes = 0,
-                    }
-                },
-                Err(e) => match e {
-                    ParserError::BitReaderEndOfStream { .. } => return Ok(false),
-                    _ => return Err(e),
-                },
-            }
-        }
+    pub fn next<R: BufRead>(r: &mut BitReader<R>) -> Result<bool> {
+        r.skip_to_nal_unit()
     }
 
-    pub fn parse_payload(&mut self, rbsp: &Vec<u8>)
+    /// Parses the payload in `rbsp`. Units referring to parameter sets
+    /// are resolved by looking them up in `parameter_sets`.
+    /// Errors are located at the bit within the RBSP where they
+    /// occurred.
+    pub fn parse_payload(&mut self, rbsp: &[u8],
+                         parameter_sets: &ParameterSets)
                          -> Result<NalPayload> {
+        let mut reader = BitReader::from_rbsp(rbsp);
+
+        self.parse_payload_from(&mut reader, rbsp, parameter_sets)
+    }
+
+    /// Like `parse_payload`, also appends the syntax elements read to
+    /// `trace`, up to an error.
+    pub fn parse_payload_traced(&mut self, rbsp: &[u8],
+                                parameter_sets: &ParameterSets,
+                                trace: &mut Vec<TraceElement>)
+                                -> Result<NalPayload> {
+        let mut reader = BitReader::from_rbsp(rbsp);
+        reader.trace();
+        let payload = self.parse_payload_from(&mut reader, rbsp,
+                                              parameter_sets);
+        trace.append(&mut reader.take_trace());
+
+        payload
+    }
+
+    fn parse_payload_from<R: BufRead>(&mut self,
+                                      reader: &mut BitReader<R>,
+                                      rbsp: &[u8],
+                                      parameter_sets: &ParameterSets)
+                                      -> Result<NalPayload> {
         let rbsp_length = rbsp.len();
-        let cursor = Cursor::new(rbsp);
-        let mut reader = BitReader::new(cursor);
-        let payload = match self.nal_unit_type {
-            1 => Err(not_impl("Slice data non-IDR")),
+        let payload = self.parse_rbsp(reader, rbsp, parameter_sets)
+            .map_err(|e| e.at(location(reader, self.offset, 0)));
+
+        /* Slice data follows the slice header and is not parsed */
+        let is_slice = matches!(payload, Ok(NalPayload::Slice(_)));
+        if payload.is_ok() && !is_slice && reader.pos + 1 < rbsp_length {
+            eprintln!("Not all data consumed: {} of {}", reader.pos, rbsp_length);
+        }
+
+        payload
+    }
+
+    fn parse_rbsp<R: BufRead>(&self, reader: &mut BitReader<R>,
+                              rbsp: &[u8],
+                              parameter_sets: &ParameterSets)
+                              -> Result<NalPayload> {
+        match self.nal_unit_type {
+            /* Slice data non-IDR and IDR */
+            1 | 5 => {
+                let payload = SliceHeader::parse(reader, self,
+                                                 parameter_sets)?;
+                Ok(NalPayload::Slice(payload))
+            },
             2 => Err(not_impl("Slice data A partition")),
             3 => Err(not_impl("Slice data B partition")),
             4 => Err(not_impl("Slice data C partition")),
-            5 => Err(not_impl("Slice data IDR")),
-            6 => Err(not_impl("SEI")),
+            6 => {
+                let payload = SeiMessage::parse(reader,
+                                                parameter_sets)?;
+                Ok(NalPayload::Sei(payload))
+            },
             /* Sequence parameter set */
             7 => {
-                let payload = SequenceParameterSet::parse(&mut reader)?;
+                let payload = SequenceParameterSet::parse(reader)?;
                 Ok(NalPayload::SequenceParameterSet(payload))
             },
            13 => Err(not_impl("SPS extension")),
            15 => Err(not_impl("Subset SPS")),
             /* Picture parameter set */
             8 => {
-                let payload = PictureParameterSet::parse(&mut reader)?;
+                let payload = PictureParameterSet::parse(reader,
+                                                         parameter_sets)?;
                 Ok(NalPayload::PictureParameterSet(payload))
             },
+            9 => {
+                let payload = AccessUnitDelimiter::parse(reader)?;
+                Ok(NalPayload::AccessUnitDelimiter(payload))
+            },
+            /* End of sequence and end of stream have empty rbsp, zero
+             * bytes might be trailing_zero_8bits of the byte stream */
+            10 | 11 => {
+                if rbsp.iter().any(|b| *b != 0) {
+                    return Err(err("End of sequence or stream with payload"));
+                }
+                if self.nal_unit_type == 10 {
+                    Ok(NalPayload::EndOfSequence())
+                }
+                else {
+                    Ok(NalPayload::EndOfStream())
+                }
+            },
+            12 => {
+                let payload = FillerData::parse(reader)?;
+                Ok(NalPayload::FillerData(payload))
+            },
             _ => Err(not_impl("Unknown payload")),
-        };
-
-        if !payload.is_err() && reader.pos < (rbsp_length - 1) {
-            println!("Not all data consumed: {} of {}", reader.pos, rbsp_length);
         }
-
-        payload
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::io;
+    use std::io::{BufReader, Cursor};
+    use bitreader::Framing;
     use super::*;
 
     #[test]
@@ -257,6 +407,29 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn parse_emulation_prevention() {
+        let buf = vec![
+          /*<NAL> <RBSP                              > */
+            0x67, 0x42, 0x00, 0x00, 0x03, 0x01, 0xff,
+        ];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new(cursor);
+
+        let (_, rbsp) = NalUnit::parse(&mut reader).unwrap();
+
+        assert_eq!(rbsp, [0x42, 0x00, 0x00, 0x01, 0xff]);
+    }
+
+    #[test]
+    fn remove_emulation_prevention_bytes() {
+        let bytes = [0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x03, 0x01];
+
+        let rbsp = remove_emulation_prevention(&bytes);
+
+        assert_eq!(rbsp, [0x00, 0x00, 0x00, 0x00, 0x03, 0x01]);
+    }
+
     /* Verifies that position after parse is correct and that
      * parse can handle end of data correctly. */
     #[test]
@@ -285,6 +458,36 @@ mod tests {
         assert!(end.is_err() && reader.reached_end_of_data());
     }
 
+    #[test]
+    fn parse_length_framing() {
+        let buf = vec![
+          /*<LENGTH  > <NAL> <RBSP                       > */
+            0x00, 0x04, 0x67, 0x42, 0x00, 0x00,
+            0x00, 0x00,
+            0x00, 0x05, 0x67, 0x00, 0x00, 0x03, 0x01,
+            0x00, 0x02, 0xe7, 0x42,
+            0x00, 0x01, 0x09,
+        ];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::with_framing(cursor, Framing::Length(2))
+            .unwrap();
+
+        NalUnit::next(&mut reader).unwrap();
+        let (_, rbsp1) = NalUnit::parse(&mut reader).unwrap();
+        let (_, rbsp2) = NalUnit::parse(&mut reader).unwrap();
+        /* Forbidden bit set, skipped to the next NAL unit */
+        let forbidden = NalUnit::parse(&mut reader);
+        let res = NalUnit::next(&mut reader);
+        let (nal, rbsp4) = NalUnit::parse(&mut reader).unwrap();
+
+        assert_eq!(rbsp1, [0x42, 0x00, 0x00]);
+        assert_eq!(rbsp2, [0x00, 0x00, 0x01]);
+        assert!(forbidden.is_err());
+        assert!(res.unwrap());
+        assert_eq!((nal.offset, nal.nal_unit_type), (21, 9));
+        assert!(rbsp4.is_empty() && reader.reached_end_of_data());
+    }
+
     #[test]
     fn next() {
         let buf = vec![
@@ -315,4 +518,200 @@ mod tests {
         assert!(res.is_ok());
         assert!(reader.reached_end_of_data());
     }
+
+    #[test]
+    fn parse_payload_end_of_sequence() {
+        let buf = vec![
+          /*<NAL      > */
+            0b00001010,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        let parameter_sets = ParameterSets::new();
+        let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+
+        let payload = nal.parse_payload(&rbsp, &parameter_sets).unwrap();
+
+        match payload {
+            NalPayload::EndOfSequence() => (),
+            _ => panic!("Not end of sequence"),
+        }
+    }
+
+    #[test]
+    fn parse_payload_end_of_stream_with_data() {
+        let buf = vec![
+          /*<NAL      > <RBSP> */
+            0b00001011, 0x80,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        let parameter_sets = ParameterSets::new();
+        let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+
+        let res = nal.parse_payload(&rbsp, &parameter_sets);
+
+        assert!(res.is_err());
+    }
+
+    /* Pipe returning one byte per read */
+    struct Pipe<'a>(&'a [u8]);
+
+    impl<'a> Read for Pipe<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+
+            Ok(n)
+        }
+    }
+
+    /* Stdin is read through a boxed BufReader that can not seek and
+     * returns the NAL units in pieces */
+    #[test]
+    fn parse_payload_not_seekable() {
+        let buf = [
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0xc0, 0x1e,
+            0xed, 0x02, 0x83, 0xf2,
+            0x00, 0x00, 0x00, 0x01, 0x68, 0xce, 0x3c, 0x80,
+        ];
+        let stdin = BufReader::with_capacity(2, Pipe(&buf));
+        let stdin: Box<dyn BufRead> = Box::new(stdin);
+        let mut reader = BitReader::new(stdin);
+        let mut parameter_sets = ParameterSets::new();
+
+        NalUnit::next(&mut reader).unwrap();
+        for _ in 0..2 {
+            let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+            let payload = nal.parse_payload(&rbsp, &parameter_sets)
+                .unwrap();
+            parameter_sets.update(&payload);
+        }
+
+        assert!(parameter_sets.pps(0).is_some());
+        assert!(reader.reached_end_of_data());
+    }
+
+    #[test]
+    fn parse_error_location() {
+        let buf = vec![
+            0x00, 0x00, 0x00, 0x01,
+          /*<AUD NAL> <RBSP> */
+            0x09, 0xf0,
+            0x00, 0x00, 0x01,
+          /*<SPS NAL> <RBSP, ends after profile_idc> */
+            0x67, 0x42,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        let parameter_sets = ParameterSets::new();
+        NalUnit::next(&mut reader).unwrap();
+        NalUnit::parse(&mut reader).unwrap();
+        let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+        assert_eq!(nal.offset, 9);
+
+        let e = nal.parse_payload(&rbsp, &parameter_sets).unwrap_err();
+
+        let location = e.location().unwrap();
+        assert_eq!(location.nal_offset, 9);
+        assert_eq!(location.bit_offset, 8);
+        assert_eq!(location.element, Some("constraint_set0_flag"));
+        assert_eq!(e.to_string(), "Bitreader error: unexpected end of data \
+                   (NAL at byte 9, RBSP bit 8, constraint_set0_flag)");
+    }
+
+    #[test]
+    fn parse_forbidden_zero_bit_location() {
+        let buf = vec![0x00, 0x00, 0x01, 0b11010000, 0x42];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        NalUnit::next(&mut reader).unwrap();
+
+        let e = NalUnit::parse(&mut reader).unwrap_err();
+
+        let location = e.location().unwrap();
+        assert_eq!(location.nal_offset, 3);
+        assert_eq!(location.bit_offset, 0);
+        assert_eq!(location.element, Some("forbidden_zero_bit"));
+        assert_eq!(e.to_string(), "Parser error 'Forbidden_zero_bit is not \
+                   0' in NAL (NAL at byte 3, RBSP bit 0, forbidden_zero_bit)");
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let buf = vec![
+            0x00, 0x00, 0x00, 0x01,
+          /*<AUD NAL> <RBSP> */
+            0x09, 0xf0,
+            0x00, 0x00, 0x00, 0x01,
+          /*<SPS NAL> <RBSP, with emulation prevention                > */
+            0x67, 0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf7, 0xfe,
+            0x00, 0x08, 0x00, 0x06, 0xd4, 0x04, 0x04, 0x05,
+            0x00, 0x00, 0x03, 0x03, 0xe9, 0x00, 0x00, 0xea,
+            0x60, 0xe8, 0x60, 0x07, 0xd1, 0x00, 0x09, 0xc4,
+            0xaf, 0x7b, 0xe0, 0xf8, 0xb1, 0x75,
+            0x00, 0x00, 0x00, 0x01,
+          /*<EOS NAL> */
+            0x0a,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf.clone()));
+        let parameter_sets = ParameterSets::new();
+        let mut out = Vec::new();
+        NalUnit::next(&mut reader).unwrap();
+
+        while !reader.reached_end_of_data() {
+            let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+            let payload = nal.parse_payload(&rbsp, &parameter_sets).unwrap();
+            out.append(&mut nal.write(&payload).unwrap());
+        }
+
+        assert_eq!(out, buf);
+    }
+
+    #[test]
+    fn write_rbsp_emulation_prevention() {
+        let nal = NalUnit {
+            offset: 0,
+            nal_ref_idc: 0,
+            nal_unit_type: 12,
+            svc_extension_flag: false,
+            avc_3d_extension_flag: false,
+        };
+
+        let bytes = nal.write_rbsp(&[0x00, 0x00, 0x01, 0x00]).unwrap();
+
+        assert_eq!(bytes, [0x00, 0x00, 0x00, 0x01, 0x0c,
+                           0x00, 0x00, 0x03, 0x01, 0x00, 0x03]);
+    }
+
+    #[test]
+    fn write_sei_not_implemented() {
+        let nal = NalUnit {
+            offset: 0,
+            nal_ref_idc: 0,
+            nal_unit_type: 6,
+            svc_extension_flag: false,
+            avc_3d_extension_flag: false,
+        };
+
+        let res = nal.write(&NalPayload::Sei(Vec::new()));
+
+        assert!(res.is_err());
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn serialize() {
+        let buf = vec![
+          /*<NAL      > <RBSP> */
+            0b00001001, 0x30,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        let parameter_sets = ParameterSets::new();
+        let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+        let payload = nal.parse_payload(&rbsp, &parameter_sets).unwrap();
+
+        let nal_json = serde_json::to_value(&nal).unwrap();
+        let payload_json = serde_json::to_value(&payload).unwrap();
+
+        assert_eq!(nal_json["nal_unit_type"], 9);
+        assert_eq!(payload_json["AccessUnitDelimiter"]["primary_pic_type"], 1);
+    }
 }
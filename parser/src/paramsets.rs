@@ -0,0 +1,239 @@
+use nalunit::NalPayload;
+use sps::SequenceParameterSet;
+use pps::PictureParameterSet;
+use sei::SeiPayload;
+
+/// Parameter sets seen so far in the stream, indexed by id. Needed
+/// when parsing units that refer to previously sent parameter sets.
+///
+/// Also keeps track of the active SPS and PPS, a PPS is activated when
+/// a slice refers to it and the SPS it refers to is activated with it.
+#[derive(Debug)]
+pub struct ParameterSets {
+    sps: Vec<Option<SequenceParameterSet>>,
+    pps: Vec<Option<PictureParameterSet>>,
+    active_sps_id: Option<u8>,
+    active_pps_id: Option<u8>,
+    latest_sps_id: Option<u8>,
+}
+
+/// Outcome of storing a parameter set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum ParameterSetUpdate {
+    /// No parameter set with the id was stored before.
+    New,
+    /// A parameter set with the same id and content was already stored.
+    Unchanged,
+    /// The parameter set replaces one with the same id but with
+    /// different content.
+    Redefined,
+}
+
+impl ParameterSets {
+    pub fn new() -> ParameterSets {
+        ParameterSets {
+            sps: vec![None; 32],
+            pps: vec![None; 256],
+            active_sps_id: None,
+            active_pps_id: None,
+            latest_sps_id: None,
+        }
+    }
+
+    pub fn sps(&self, seq_parameter_set_id: u8) ->
+               Option<&SequenceParameterSet> {
+        self.sps.get(seq_parameter_set_id as usize)
+            .and_then(|sps| sps.as_ref())
+    }
+
+    pub fn pps(&self, pic_parameter_set_id: u8) ->
+               Option<&PictureParameterSet> {
+        self.pps.get(pic_parameter_set_id as usize)
+            .and_then(|pps| pps.as_ref())
+    }
+
+    pub fn active_sps(&self) -> Option<&SequenceParameterSet> {
+        self.active_sps_id.and_then(|id| self.sps(id))
+    }
+
+    pub fn active_pps(&self) -> Option<&PictureParameterSet> {
+        self.active_pps_id.and_then(|id| self.pps(id))
+    }
+
+    /// The most recently stored SPS.
+    pub fn latest_sps(&self) -> Option<&SequenceParameterSet> {
+        self.latest_sps_id.and_then(|id| self.sps(id))
+    }
+
+    /// Activates a SPS, used when a unit other than a slice refers to
+    /// a SPS, like the buffering period SEI message.
+    pub fn activate_sps(&mut self, seq_parameter_set_id: u8) {
+        if self.sps(seq_parameter_set_id).is_some() {
+            self.active_sps_id = Some(seq_parameter_set_id);
+        }
+    }
+
+    /// Activates a PPS and the SPS it refers to.
+    pub fn activate_pps(&mut self, pic_parameter_set_id: u8) {
+        let seq_parameter_set_id = match self.pps(pic_parameter_set_id) {
+            Some(pps) => pps.seq_parameter_set_id,
+            None => return,
+        };
+        self.active_pps_id = Some(pic_parameter_set_id);
+        self.activate_sps(seq_parameter_set_id);
+    }
+
+    /// Stores a copy of the payload if it is a parameter set, replacing
+    /// any previous parameter set with the same id. Slices activate the
+    /// parameter sets they refer to and buffering period SEI messages
+    /// activate the SPS they refer to.
+    ///
+    /// Returns None when the payload is not a parameter set.
+    pub fn update(&mut self, payload: &NalPayload) ->
+                  Option<ParameterSetUpdate> {
+        match *payload {
+            NalPayload::SequenceParameterSet(ref sps) => {
+                let id = sps.seq_parameter_set_id as usize;
+                let update = match self.sps[id] {
+                    None => ParameterSetUpdate::New,
+                    Some(ref old) if old == sps =>
+                        ParameterSetUpdate::Unchanged,
+                    Some(_) => ParameterSetUpdate::Redefined,
+                };
+                self.sps[id] = Some(sps.clone());
+                self.latest_sps_id = Some(sps.seq_parameter_set_id);
+                Some(update)
+            },
+            NalPayload::PictureParameterSet(ref pps) => {
+                let id = pps.pic_parameter_set_id as usize;
+                let update = match self.pps[id] {
+                    None => ParameterSetUpdate::New,
+                    Some(ref old) if old == pps =>
+                        ParameterSetUpdate::Unchanged,
+                    Some(_) => ParameterSetUpdate::Redefined,
+                };
+                self.pps[id] = Some(pps.clone());
+                Some(update)
+            },
+            NalPayload::Slice(ref header) => {
+                self.activate_pps(header.pic_parameter_set_id);
+                None
+            },
+            NalPayload::Sei(ref messages) => {
+                for message in messages {
+                    if let SeiPayload::BufferingPeriod(ref bp) =
+                        message.payload {
+                        self.activate_sps(bp.seq_parameter_set_id);
+                    }
+                }
+                None
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Default for ParameterSets {
+    fn default() -> ParameterSets {
+        ParameterSets::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use bitreader::BitReader;
+    use super::*;
+
+    /* Baseline 320x240, level 3.0 */
+    const SPS: [u8; 7] = [
+        0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2,
+    ];
+
+    /* Same as above but level 3.1 */
+    const SPS_LEVEL_31: [u8; 7] = [
+        0x42, 0xc0, 0x1f, 0xed, 0x02, 0x83, 0xf2,
+    ];
+
+    const PPS: [u8; 3] = [
+        0xce, 0x3c, 0x80,
+    ];
+
+    fn sps(buf: [u8; 7]) -> NalPayload {
+        let mut reader = BitReader::new(Cursor::new(buf));
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+
+        NalPayload::SequenceParameterSet(sps)
+    }
+
+    fn pps(parameter_sets: &ParameterSets) -> NalPayload {
+        let mut reader = BitReader::from_rbsp(&PPS);
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             parameter_sets).unwrap();
+
+        NalPayload::PictureParameterSet(pps)
+    }
+
+    #[test]
+    fn update_new() {
+        let mut parameter_sets = ParameterSets::new();
+
+        let update = parameter_sets.update(&sps(SPS));
+
+        assert_eq!(update, Some(ParameterSetUpdate::New));
+        assert!(parameter_sets.sps(0).is_some());
+        assert!(parameter_sets.sps(1).is_none());
+    }
+
+    #[test]
+    fn update_unchanged() {
+        let mut parameter_sets = ParameterSets::new();
+        parameter_sets.update(&sps(SPS));
+
+        let update = parameter_sets.update(&sps(SPS));
+
+        assert_eq!(update, Some(ParameterSetUpdate::Unchanged));
+    }
+
+    #[test]
+    fn update_redefined() {
+        let mut parameter_sets = ParameterSets::new();
+        parameter_sets.update(&sps(SPS));
+
+        let redefined = sps(SPS_LEVEL_31);
+        let update = parameter_sets.update(&redefined);
+
+        assert_eq!(update, Some(ParameterSetUpdate::Redefined));
+        match redefined {
+            NalPayload::SequenceParameterSet(ref sps) =>
+                assert_eq!(parameter_sets.sps(0), Some(sps)),
+            _ => panic!("Not a SPS"),
+        }
+    }
+
+    #[test]
+    fn activate_pps() {
+        let mut parameter_sets = ParameterSets::new();
+        parameter_sets.update(&sps(SPS));
+        let pps = pps(&parameter_sets);
+        parameter_sets.update(&pps);
+        assert!(parameter_sets.active_pps().is_none());
+        assert!(parameter_sets.active_sps().is_none());
+
+        parameter_sets.activate_pps(0);
+
+        assert!(parameter_sets.active_pps().is_some());
+        assert!(parameter_sets.active_sps().is_some());
+    }
+
+    #[test]
+    fn activate_missing_pps() {
+        let mut parameter_sets = ParameterSets::new();
+
+        parameter_sets.activate_pps(0);
+
+        assert!(parameter_sets.active_pps().is_none());
+    }
+}
@@ -0,0 +1,465 @@
+use std::io::prelude::*;
+
+use super::*;
+
+/* Link layer header types */
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+/* pcapng block types */
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const SIMPLE_PACKET_BLOCK: u32 = 3;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+/// UDP datagram of a capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datagram {
+    /// Capture time in microseconds since the epoch
+    pub time: u64,
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub payload: Vec<u8>,
+}
+
+enum Format {
+    /* Classic pcap, nanosecond time stamps */
+    Pcap { nanoseconds: bool },
+    /* Link type and time stamp units per second of each interface */
+    Pcapng { interfaces: Vec<(u16, u64)> },
+}
+
+/// Reads the UDP datagrams over IPv4 and IPv6 of a pcap or pcapng
+/// capture file. Other packets are skipped.
+pub struct PcapReader<R> {
+    reader: R,
+    format: Format,
+    big_endian: bool,
+    /* Link type of classic pcap */
+    link_type: u16,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Pcap();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn be16(data: &[u8]) -> u16 {
+    (data[0] as u16) << 8 | data[1] as u16
+}
+
+/* Reads exactly `buf.len()` bytes, false at end of data before the
+ * first byte */
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    match read_all(reader, buf) {
+        Ok(n) if n == buf.len() => Ok(true),
+        Ok(0) => Ok(false),
+        Ok(_) => Err(err("Truncated capture file")),
+        Err(e) => Err(io_err(ParserUnit::Pcap(), e)),
+    }
+}
+
+/* UDP datagram of an IP packet, None for other packets */
+fn udp(packet: &[u8]) -> Option<(u16, u16, &[u8])> {
+    let udp = match packet.first()? >> 4 {
+        4 => {
+            let header_size = (packet[0] as usize & 0x0f) * 4;
+            let total_length = be16(packet.get(2..4)?) as usize;
+            let fragment = be16(packet.get(6..8)?) & 0x1fff;
+            if *packet.get(9)? != 17 || fragment != 0 ||
+               total_length < header_size {
+                return None;
+            }
+            packet.get(header_size..total_length.min(packet.len()))?
+        },
+        /* Extension headers are not followed */
+        6 if *packet.get(6)? == 17 => packet.get(40..)?,
+        _ => return None,
+    };
+    if udp.len() < 8 {
+        return None;
+    }
+    let length = (be16(&udp[4..6]) as usize).clamp(8, udp.len());
+
+    Some((be16(&udp[0..2]), be16(&udp[2..4]), &udp[8..length]))
+}
+
+/* IP packet of a link layer frame */
+fn network(link_type: u16, frame: &[u8], big_endian: bool) -> Option<&[u8]> {
+    match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut pos = 12;
+            let mut ether_type = be16(frame.get(pos..pos + 2)?);
+            /* 802.1Q and 802.1ad tags */
+            while ether_type == 0x8100 || ether_type == 0x88a8 {
+                pos += 4;
+                ether_type = be16(frame.get(pos..pos + 2)?);
+            }
+            match ether_type {
+                0x0800 | 0x86dd => frame.get(pos + 2..),
+                _ => None,
+            }
+        },
+        LINKTYPE_NULL => {
+            let family = frame.get(..4)?;
+            let family = if big_endian { family[3] } else { family[0] };
+            /* AF_INET and the AF_INET6 values of the BSDs */
+            match family {
+                2 | 24 | 28 | 30 => frame.get(4..),
+                _ => None,
+            }
+        },
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+        LINKTYPE_LINUX_SLL => frame.get(16..),
+        LINKTYPE_LINUX_SLL2 => frame.get(20..),
+        _ => None,
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header.
+    pub fn new(mut reader: R) -> Result<PcapReader<R>> {
+        let mut header = [0; 24];
+        if !read_exact(&mut reader, &mut header)? {
+            return Err(err("Empty capture file"));
+        }
+
+        let magic = [header[0], header[1], header[2], header[3]];
+        let (format, big_endian) = match magic {
+            [0xa1, 0xb2, 0xc3, 0xd4] =>
+                (Format::Pcap { nanoseconds: false }, true),
+            [0xd4, 0xc3, 0xb2, 0xa1] =>
+                (Format::Pcap { nanoseconds: false }, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] =>
+                (Format::Pcap { nanoseconds: true }, true),
+            [0x4d, 0x3c, 0xb2, 0xa1] =>
+                (Format::Pcap { nanoseconds: true }, false),
+            _ if u32::from_be_bytes(magic) == SECTION_HEADER_BLOCK => {
+                let big_endian = header[8..12] == [0x1a, 0x2b, 0x3c, 0x4d];
+                (Format::Pcapng { interfaces: Vec::new() }, big_endian)
+            },
+            _ => return Err(err("Not a pcap or pcapng file")),
+        };
+
+        let mut pcap = PcapReader {
+            reader,
+            format,
+            big_endian,
+            link_type: 0,
+        };
+        match pcap.format {
+            Format::Pcap { .. } => {
+                pcap.link_type = pcap.u32(&header[20..]) as u16;
+            },
+            Format::Pcapng { .. } => {
+                /* Rest of the section header block */
+                let length = pcap.u32(&header[4..]) as usize;
+                if length < 28 || !length.is_multiple_of(4) {
+                    return Err(err("Invalid section header block"));
+                }
+                pcap.skip(length - 24)?;
+            },
+        }
+
+        Ok(pcap)
+    }
+
+    fn u32(&self, data: &[u8]) -> u32 {
+        let bytes = [data[0], data[1], data[2], data[3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        }
+        else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn u16(&self, data: &[u8]) -> u16 {
+        if self.big_endian {
+            (data[0] as u16) << 8 | data[1] as u16
+        }
+        else {
+            (data[1] as u16) << 8 | data[0] as u16
+        }
+    }
+
+    fn skip(&mut self, count: usize) -> Result<()> {
+        let mut data = vec![0; count];
+        if count > 0 && !read_exact(&mut self.reader, &mut data)? {
+            return Err(err("Truncated capture file"));
+        }
+
+        Ok(())
+    }
+
+    /* Next frame with its link type and time in microseconds, None at
+     * end of data */
+    fn next_frame(&mut self) -> Result<Option<(u16, u64, Vec<u8>)>> {
+        let nanoseconds = match self.format {
+            Format::Pcap { nanoseconds } => nanoseconds,
+            Format::Pcapng { .. } => return self.next_block(),
+        };
+        let mut header = [0; 16];
+        if !read_exact(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let seconds = self.u32(&header[0..]) as u64;
+        let fraction = self.u32(&header[4..]) as u64;
+        let length = self.u32(&header[8..]) as usize;
+        let mut frame = vec![0; length];
+        if length > 0 && !read_exact(&mut self.reader, &mut frame)? {
+            return Err(err("Truncated capture file"));
+        }
+        let fraction = if nanoseconds { fraction / 1000 } else { fraction };
+
+        Ok(Some((self.link_type, seconds * 1_000_000 + fraction, frame)))
+    }
+
+    /* Next packet block of pcapng, handling the other blocks on the
+     * way */
+    fn next_block(&mut self) -> Result<Option<(u16, u64, Vec<u8>)>> {
+        loop {
+            let mut header = [0; 8];
+            if !read_exact(&mut self.reader, &mut header)? {
+                return Ok(None);
+            }
+            /* Same in both byte orders */
+            if self.u32(&header[0..]) == SECTION_HEADER_BLOCK {
+                /* New section, byte order might change */
+                let mut magic = [0; 4];
+                if !read_exact(&mut self.reader, &mut magic)? {
+                    return Err(err("Truncated capture file"));
+                }
+                self.big_endian = magic == [0x1a, 0x2b, 0x3c, 0x4d];
+                let length = self.u32(&header[4..]) as usize;
+                if length < 28 || !length.is_multiple_of(4) {
+                    return Err(err("Invalid section header block"));
+                }
+                self.skip(length - 12)?;
+                self.format = Format::Pcapng { interfaces: Vec::new() };
+                continue;
+            }
+
+            let block_type = self.u32(&header[0..]);
+            let length = self.u32(&header[4..]) as usize;
+            if length < 12 || !length.is_multiple_of(4) {
+                return Err(err("Invalid block length"));
+            }
+            let mut body = vec![0; length - 8];
+            if !read_exact(&mut self.reader, &mut body)? {
+                return Err(err("Truncated capture file"));
+            }
+            /* Without the trailing block length */
+            body.truncate(length - 12);
+
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK if body.len() >= 8 => {
+                    let link_type = self.u16(&body[0..]);
+                    let resolution = self.time_resolution(&body[8..]);
+                    if let Format::Pcapng { ref mut interfaces } = self.format {
+                        interfaces.push((link_type, resolution));
+                    }
+                },
+                ENHANCED_PACKET_BLOCK if body.len() >= 20 => {
+                    let interface = self.u32(&body[0..]) as usize;
+                    let time = (self.u32(&body[4..]) as u64) << 32 |
+                        self.u32(&body[8..]) as u64;
+                    let captured = self.u32(&body[12..]) as usize;
+                    let (link_type, resolution) = self.interface(interface)?;
+                    let end = (20 + captured).min(body.len());
+                    let frame = body[20..end].to_vec();
+                    let time = (time as u128 * 1_000_000 /
+                                resolution as u128) as u64;
+
+                    return Ok(Some((link_type, time, frame)));
+                },
+                SIMPLE_PACKET_BLOCK if body.len() >= 4 => {
+                    let (link_type, _) = self.interface(0)?;
+                    let original = self.u32(&body[0..]) as usize;
+                    let end = (4 + original).min(body.len());
+
+                    return Ok(Some((link_type, 0, body[4..end].to_vec())));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn interface(&self, index: usize) -> Result<(u16, u64)> {
+        match self.format {
+            Format::Pcapng { ref interfaces } => interfaces.get(index)
+                .cloned()
+                .ok_or_else(|| err("Packet of unknown interface")),
+            Format::Pcap { .. } => Err(err("Not a pcapng file")),
+        }
+    }
+
+    /* Time stamp units per second from the if_tsresol option of an
+     * interface description block */
+    fn time_resolution(&self, mut options: &[u8]) -> u64 {
+        let mut resolution = 1_000_000;
+        while options.len() >= 4 {
+            let code = self.u16(&options[0..]);
+            let length = self.u16(&options[2..]) as usize;
+            if code == 0 || options.len() < 4 + length {
+                break;
+            }
+            if code == 9 && length == 1 {
+                let value = options[4];
+                resolution = if value & 0x80 == 0 {
+                    10u64.saturating_pow(value as u32)
+                }
+                else {
+                    1u64.checked_shl((value & 0x7f) as u32).unwrap_or(0)
+                };
+            }
+            options = &options[(4 + length).div_ceil(4) * 4..];
+        }
+
+        resolution.max(1)
+    }
+
+    /// Reads the next UDP datagram, None at end of data.
+    pub fn next_datagram(&mut self) -> Result<Option<Datagram>> {
+        while let Some((link_type, time, frame)) = self.next_frame()? {
+            let datagram = network(link_type, &frame, self.big_endian)
+                .and_then(udp);
+            if let Some((source_port, destination_port, payload)) = datagram {
+                return Ok(Some(Datagram {
+                    time,
+                    source_port,
+                    destination_port,
+                    payload: payload.to_vec(),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    /// Ethernet frame of an IPv4 UDP datagram with `payload`.
+    pub fn ethernet_udp(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x00; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        let total_length = 20 + 8 + payload.len();
+        frame.extend_from_slice(&[
+            0x45, 0x00, (total_length >> 8) as u8, total_length as u8,
+            0x00, 0x00, 0x40, 0x00, 0x40, 17, 0x00, 0x00,
+            192, 168, 0, 1, 192, 168, 0, 2,
+        ]);
+        let length = 8 + payload.len();
+        frame.extend_from_slice(&[0x13, 0x88, 0x13, 0x89,
+                                  (length >> 8) as u8, length as u8,
+                                  0x00, 0x00]);
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+
+    /// Little endian classic pcap file of Ethernet frames.
+    pub fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00];
+        file.extend_from_slice(&[0x00; 8]);
+        file.extend_from_slice(&65535u32.to_le_bytes());
+        file.extend_from_slice(&1u32.to_le_bytes());
+        for (i, frame) in frames.iter().enumerate() {
+            file.extend_from_slice(&10u32.to_le_bytes());
+            file.extend_from_slice(&(i as u32 * 20000).to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(frame);
+        }
+
+        file
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = 12 + body.len().div_ceil(4) * 4;
+        let mut block = block_type.to_be_bytes().to_vec();
+        block.extend_from_slice(&(length as u32).to_be_bytes());
+        block.extend_from_slice(body);
+        block.resize(length - 4, 0x00);
+        block.extend_from_slice(&(length as u32).to_be_bytes());
+
+        block
+    }
+
+    /* Big endian pcapng file, nanosecond interface */
+    fn pcapng(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = block(SECTION_HEADER_BLOCK, &[
+            0x1a, 0x2b, 0x3c, 0x4d, 0x00, 0x01, 0x00, 0x00,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+        file.extend(block(INTERFACE_DESCRIPTION_BLOCK, &[
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff,
+            0x00, 0x09, 0x00, 0x01, 0x09, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ]));
+        /* A block to skip */
+        file.extend(block(5, &[0x00; 8]));
+        for frame in frames {
+            let mut body = vec![0x00; 4];
+            body.extend_from_slice(&3_000_000_000u64.to_be_bytes());
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            body.extend_from_slice(frame);
+            file.extend(block(ENHANCED_PACKET_BLOCK, &body));
+        }
+
+        file
+    }
+
+    #[test]
+    fn read_pcap() {
+        let frames = [ethernet_udp(&[1, 2, 3]), vec![0x00; 20],
+                      ethernet_udp(&[4])];
+        let mut reader = PcapReader::new(Cursor::new(pcap(&frames))).unwrap();
+
+        let first = reader.next_datagram().unwrap().unwrap();
+        let second = reader.next_datagram().unwrap().unwrap();
+
+        assert_eq!(first, Datagram {
+            time: 10_000_000,
+            source_port: 5000,
+            destination_port: 5001,
+            payload: vec![1, 2, 3],
+        });
+        assert_eq!((second.time, second.payload), (10_040_000, vec![4]));
+        assert!(reader.next_datagram().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_pcapng() {
+        let frames = [ethernet_udp(&[1, 2, 3])];
+        let mut reader = PcapReader::new(Cursor::new(pcapng(&frames)))
+            .unwrap();
+
+        let datagram = reader.next_datagram().unwrap().unwrap();
+
+        assert_eq!(datagram.time, 3_000_000);
+        assert_eq!(datagram.payload, [1, 2, 3]);
+        assert!(reader.next_datagram().unwrap().is_none());
+    }
+
+    #[test]
+    fn not_a_capture() {
+        let res = PcapReader::new(Cursor::new(vec![0x00; 32]));
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use nalunit::NalUnit;
+use slice::SliceHeader;
+use sps::SequenceParameterSet;
+
+/// TopFieldOrderCnt and BottomFieldOrderCnt of a picture, only one of
+/// them is present for a field picture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct PicOrderCnt {
+    pub top_field_order_cnt: Option<i64>,
+    pub bottom_field_order_cnt: Option<i64>,
+}
+
+impl PicOrderCnt {
+    /// PicOrderCnt( picX ) as specified in equation 8-1.
+    pub fn pic_order_cnt(&self) -> i64 {
+        match (self.top_field_order_cnt, self.bottom_field_order_cnt) {
+            (Some(top), Some(bottom)) => top.min(bottom),
+            (Some(top), None) => top,
+            (None, Some(bottom)) => bottom,
+            (None, None) => 0,
+        }
+    }
+}
+
+/// Values kept from previous pictures in decoding order that are
+/// needed to derive the picture order count of the next picture.
+#[derive(Debug, Default)]
+pub struct PicOrderCntState {
+    /* Of the previous reference picture, type 0 */
+    prev_pic_order_cnt_msb: i64,
+    prev_pic_order_cnt_lsb: i64,
+    /* Of the previous picture, type 1 and 2 */
+    prev_frame_num_offset: i64,
+    prev_frame_num: i64,
+}
+
+impl PicOrderCntState {
+    pub fn new() -> PicOrderCntState {
+        PicOrderCntState {
+            prev_pic_order_cnt_msb: 0,
+            prev_pic_order_cnt_lsb: 0,
+            prev_frame_num_offset: 0,
+            prev_frame_num: 0,
+        }
+    }
+
+    /// Derives the picture order count of a primary coded picture as
+    /// specified in 8.2.1, from the first slice of the picture. Must
+    /// be called once for every picture in decoding order.
+    ///
+    /// The returned values are the ones before a
+    /// memory_management_control_operation equal to 5 resets them.
+    pub fn decode(&mut self, nal: &NalUnit, header: &SliceHeader,
+                  sps: &SequenceParameterSet) -> PicOrderCnt {
+        let idr_pic_flag = nal.nal_unit_type == 5;
+        let reference = nal.nal_ref_idc != 0;
+        let mmco5 = header.has_mmco5();
+
+        if idr_pic_flag {
+            self.prev_pic_order_cnt_msb = 0;
+            self.prev_pic_order_cnt_lsb = 0;
+        }
+
+        let (poc, pic_order_cnt_msb, frame_num_offset) =
+            match sps.pic_order_cnt_type {
+                0 => {
+                    let (poc, msb) = self.decode_type_0(header, sps);
+                    (poc, msb, 0)
+                },
+                1 => {
+                    let offset = self.frame_num_offset(idr_pic_flag, header,
+                                                       sps);
+                    (decode_type_1(reference, offset, header, sps), 0, offset)
+                },
+                _ => {
+                    let offset = self.frame_num_offset(idr_pic_flag, header,
+                                                       sps);
+                    (decode_type_2(idr_pic_flag, reference, offset, header),
+                     0, offset)
+                },
+            };
+
+        /* After a memory_management_control_operation equal to 5 the
+         * picture is treated as having a picture order count of 0 and
+         * frame_num 0 by the following pictures, see 8.2.1 */
+        if mmco5 {
+            let temp_pic_order_cnt = poc.pic_order_cnt();
+            self.prev_pic_order_cnt_msb = 0;
+            self.prev_pic_order_cnt_lsb = match poc.top_field_order_cnt {
+                Some(top) if !header.bottom_field_flag =>
+                    top - temp_pic_order_cnt,
+                _ => 0,
+            };
+            self.prev_frame_num_offset = 0;
+            self.prev_frame_num = 0;
+        }
+        else {
+            if reference {
+                self.prev_pic_order_cnt_msb = pic_order_cnt_msb;
+                self.prev_pic_order_cnt_lsb = header.pic_order_cnt_lsb as i64;
+            }
+            self.prev_frame_num_offset = frame_num_offset;
+            self.prev_frame_num = header.frame_num as i64;
+        }
+
+        poc
+    }
+
+    /* 8.2.1.1 */
+    fn decode_type_0(&self, header: &SliceHeader,
+                     sps: &SequenceParameterSet) -> (PicOrderCnt, i64) {
+        let max_pic_order_cnt_lsb = sps.max_pic_order_cnt_lsb() as i64;
+        let lsb = header.pic_order_cnt_lsb as i64;
+        let prev_lsb = self.prev_pic_order_cnt_lsb;
+        let prev_msb = self.prev_pic_order_cnt_msb;
+
+        let msb = if lsb < prev_lsb &&
+                     prev_lsb - lsb >= max_pic_order_cnt_lsb / 2 {
+            prev_msb + max_pic_order_cnt_lsb
+        }
+        else if lsb > prev_lsb &&
+                lsb - prev_lsb > max_pic_order_cnt_lsb / 2 {
+            prev_msb - max_pic_order_cnt_lsb
+        }
+        else {
+            prev_msb
+        };
+
+        let poc = if !header.field_pic_flag {
+            let top = msb + lsb;
+            PicOrderCnt {
+                top_field_order_cnt: Some(top),
+                bottom_field_order_cnt:
+                    Some(top + header.delta_pic_order_cnt_bottom),
+            }
+        }
+        else if !header.bottom_field_flag {
+            PicOrderCnt {
+                top_field_order_cnt: Some(msb + lsb),
+                bottom_field_order_cnt: None,
+            }
+        }
+        else {
+            PicOrderCnt {
+                top_field_order_cnt: None,
+                bottom_field_order_cnt: Some(msb + lsb),
+            }
+        };
+
+        (poc, msb)
+    }
+
+    /* FrameNumOffset, equations 8-6 and 8-11 */
+    fn frame_num_offset(&self, idr_pic_flag: bool, header: &SliceHeader,
+                        sps: &SequenceParameterSet) -> i64 {
+        if idr_pic_flag {
+            0
+        }
+        else if self.prev_frame_num > header.frame_num as i64 {
+            self.prev_frame_num_offset + sps.max_frame_num() as i64
+        }
+        else {
+            self.prev_frame_num_offset
+        }
+    }
+}
+
+/* 8.2.1.2 */
+fn decode_type_1(reference: bool, frame_num_offset: i64,
+                 header: &SliceHeader,
+                 sps: &SequenceParameterSet) -> PicOrderCnt {
+    let cycle = sps.offset_for_ref_frame.len() as i64;
+
+    let mut abs_frame_num = if cycle != 0 {
+        frame_num_offset + header.frame_num as i64
+    }
+    else {
+        0
+    };
+    if !reference && abs_frame_num > 0 {
+        abs_frame_num -= 1;
+    }
+
+    let mut expected_pic_order_cnt = 0;
+    if abs_frame_num > 0 {
+        let pic_order_cnt_cycle_cnt = (abs_frame_num - 1) / cycle;
+        let frame_num_in_pic_order_cnt_cycle = (abs_frame_num - 1) % cycle;
+        let expected_delta_per_pic_order_cnt_cycle: i64 =
+            sps.offset_for_ref_frame.iter().sum();
+
+        expected_pic_order_cnt = pic_order_cnt_cycle_cnt *
+            expected_delta_per_pic_order_cnt_cycle;
+        for i in 0..=frame_num_in_pic_order_cnt_cycle as usize {
+            expected_pic_order_cnt += sps.offset_for_ref_frame[i];
+        }
+    }
+    if !reference {
+        expected_pic_order_cnt += sps.offset_for_non_ref_pic;
+    }
+
+    let delta = header.delta_pic_order_cnt;
+    if !header.field_pic_flag {
+        let top = expected_pic_order_cnt + delta[0];
+        PicOrderCnt {
+            top_field_order_cnt: Some(top),
+            bottom_field_order_cnt:
+                Some(top + sps.offset_for_top_to_bottom_field + delta[1]),
+        }
+    }
+    else if !header.bottom_field_flag {
+        PicOrderCnt {
+            top_field_order_cnt: Some(expected_pic_order_cnt + delta[0]),
+            bottom_field_order_cnt: None,
+        }
+    }
+    else {
+        PicOrderCnt {
+            top_field_order_cnt: None,
+            bottom_field_order_cnt: Some(expected_pic_order_cnt +
+                                         sps.offset_for_top_to_bottom_field +
+                                         delta[0]),
+        }
+    }
+}
+
+/* 8.2.1.3 */
+fn decode_type_2(idr_pic_flag: bool, reference: bool, frame_num_offset: i64,
+                 header: &SliceHeader) -> PicOrderCnt {
+    let temp_pic_order_cnt = if idr_pic_flag {
+        0
+    }
+    else if !reference {
+        2 * (frame_num_offset + header.frame_num as i64) - 1
+    }
+    else {
+        2 * (frame_num_offset + header.frame_num as i64)
+    };
+
+    PicOrderCnt {
+        top_field_order_cnt: if !header.field_pic_flag ||
+                                !header.bottom_field_flag {
+            Some(temp_pic_order_cnt)
+        } else { None },
+        bottom_field_order_cnt: if !header.field_pic_flag ||
+                                   header.bottom_field_flag {
+            Some(temp_pic_order_cnt)
+        } else { None },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use bitreader::BitReader;
+    use slice::{DecRefPicMarking, MemoryManagementControlOperation};
+    use super::*;
+
+    /* 4 bit frame_num, type 0 with 6 bit pic_order_cnt_lsb */
+    const SPS_TYPE_0: [u8; 7] = [
+        0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2,
+    ];
+
+    /* 4 bit frame_num, type 1 with offset_for_non_ref_pic -2,
+     * offset_for_top_to_bottom_field 1 and offset_for_ref_frame 2, 4 */
+    const SPS_TYPE_1: [u8; 10] = [
+        0x42, 0xc0, 0x1e, 0xd0, 0xa9, 0x90, 0x42, 0x05,
+        0x07, 0xe4,
+    ];
+
+    /* 4 bit frame_num, type 2 */
+    const SPS_TYPE_2: [u8; 7] = [
+        0x42, 0xc0, 0x1e, 0xda, 0x05, 0x07, 0xe4,
+    ];
+
+    fn sps(buf: &[u8]) -> SequenceParameterSet {
+        let mut reader = BitReader::new(Cursor::new(buf.to_vec()));
+
+        SequenceParameterSet::parse(&mut reader).unwrap()
+    }
+
+    fn nal(nal_ref_idc: u8, nal_unit_type: u8) -> NalUnit {
+        NalUnit {
+            offset: 0,
+            nal_ref_idc,
+            nal_unit_type,
+            svc_extension_flag: false,
+            avc_3d_extension_flag: false,
+        }
+    }
+
+    fn frame(frame_num: u32, pic_order_cnt_lsb: u32) -> SliceHeader {
+        SliceHeader {
+            frame_num,
+            pic_order_cnt_lsb,
+            ..Default::default()
+        }
+    }
+
+    fn top(poc: PicOrderCnt) -> i64 {
+        poc.top_field_order_cnt.unwrap()
+    }
+
+    #[test]
+    fn type_0_wraps_lsb() {
+        let sps = sps(&SPS_TYPE_0);
+        let mut state = PicOrderCntState::new();
+
+        let idr = state.decode(&nal(3, 5), &frame(0, 0), &sps);
+        let p1 = state.decode(&nal(2, 1), &frame(1, 30), &sps);
+        let p2 = state.decode(&nal(2, 1), &frame(2, 60), &sps);
+        let p3 = state.decode(&nal(2, 1), &frame(3, 2), &sps);
+        let mut bottom = frame(4, 4);
+        bottom.field_pic_flag = true;
+        bottom.bottom_field_flag = true;
+        let p4 = state.decode(&nal(2, 1), &bottom, &sps);
+
+        assert_eq!(idr.pic_order_cnt(), 0);
+        assert_eq!(top(p1), 30);
+        assert_eq!(top(p2), 60);
+        assert_eq!(top(p3), 66);
+        assert_eq!(p4.top_field_order_cnt, None);
+        assert_eq!(p4.bottom_field_order_cnt, Some(68));
+    }
+
+    #[test]
+    fn type_0_mmco5() {
+        let sps = sps(&SPS_TYPE_0);
+        let mut state = PicOrderCntState::new();
+        let mut mmco5 = frame(2, 40);
+        mmco5.dec_ref_pic_marking = Some(DecRefPicMarking {
+            adaptive_ref_pic_marking_mode_flag: true,
+            operations: vec![MemoryManagementControlOperation {
+                memory_management_control_operation: 5,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+
+        state.decode(&nal(3, 5), &frame(0, 0), &sps);
+        state.decode(&nal(2, 1), &frame(1, 20), &sps);
+        let p2 = state.decode(&nal(2, 1), &mmco5, &sps);
+        let p3 = state.decode(&nal(2, 1), &frame(1, 2), &sps);
+
+        assert_eq!(top(p2), 40);
+        /* Compared to lsb 0 after the reset, not 40 */
+        assert_eq!(top(p3), 2);
+    }
+
+    #[test]
+    fn type_1() {
+        let sps = sps(&SPS_TYPE_1);
+        let mut state = PicOrderCntState::new();
+
+        let idr = state.decode(&nal(3, 5), &frame(0, 0), &sps);
+        let p1 = state.decode(&nal(2, 1), &frame(1, 0), &sps);
+        let p2 = state.decode(&nal(2, 1), &frame(2, 0), &sps);
+        let b = state.decode(&nal(0, 1), &frame(3, 0), &sps);
+        let p3 = state.decode(&nal(2, 1), &frame(3, 0), &sps);
+
+        assert_eq!(idr.top_field_order_cnt, Some(0));
+        assert_eq!(idr.bottom_field_order_cnt, Some(1));
+        assert_eq!(top(p1), 2);
+        assert_eq!(top(p2), 6);
+        assert_eq!(top(b), 4);
+        assert_eq!(top(p3), 8);
+    }
+
+    #[test]
+    fn type_2_wraps_frame_num() {
+        let sps = sps(&SPS_TYPE_2);
+        let mut state = PicOrderCntState::new();
+
+        let idr = state.decode(&nal(3, 5), &frame(0, 0), &sps);
+        let p1 = state.decode(&nal(2, 1), &frame(1, 0), &sps);
+        let b = state.decode(&nal(0, 1), &frame(2, 0), &sps);
+        state.decode(&nal(2, 1), &frame(15, 0), &sps);
+        let p16 = state.decode(&nal(2, 1), &frame(0, 0), &sps);
+
+        assert_eq!(idr.pic_order_cnt(), 0);
+        assert_eq!(p1.pic_order_cnt(), 2);
+        assert_eq!(b.pic_order_cnt(), 3);
+        assert_eq!(p16.pic_order_cnt(), 32);
+    }
+}
@@ -1,97 +1,126 @@
 use std::io::prelude::*;
 
 use bitreader::BitReader;
+use bitwriter::BitWriter;
+use paramsets::ParameterSets;
+use scaling::{ScalingLists, ScalingMatrix};
+use sps::SequenceParameterSet;
 use super::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct PictureParameterSet {
-    pic_parameter_set_id: u8,
-    seq_parameter_set_id: u8,
-    entropy_coding_mode_flag: bool,
-    bottom_field_pic_order_in_frame_present_flag: bool,
-    num_slice_groups_minus1: u8,
+    pub pic_parameter_set_id: u8,
+    pub seq_parameter_set_id: u8,
+    pub entropy_coding_mode_flag: bool,
+    pub bottom_field_pic_order_in_frame_present_flag: bool,
+    pub num_slice_groups_minus1: u8,
 /*
     slice_group_map_type: u8,
 */
-    num_ref_idx_l0_default_active_minus1: u8,
-    num_ref_idx_l1_default_active_minus1: u8,
-    weighted_pred_flag: bool,
-    weighted_bipred_idc: u8,
-    pic_init_qp_minus26: i8,
-    pic_init_qs_minus26: i8,
-    chroma_qp_index_offset: i8,
-    deblocking_filter_control_present_flag: bool,
-    constrained_intra_pred_flag: bool,
-    redundant_pic_cnt_present_flag: bool,
-    transform_8x8_mode_flag: bool,
-    pic_scaling_matrix_present_flag: bool,
-
-    second_chroma_qp_index_offset: i8,
+    pub num_ref_idx_l0_default_active_minus1: u8,
+    pub num_ref_idx_l1_default_active_minus1: u8,
+    pub weighted_pred_flag: bool,
+    pub weighted_bipred_idc: u8,
+    pub pic_init_qp_minus26: i8,
+    pub pic_init_qs_minus26: i8,
+    pub chroma_qp_index_offset: i8,
+    pub deblocking_filter_control_present_flag: bool,
+    pub constrained_intra_pred_flag: bool,
+    pub redundant_pic_cnt_present_flag: bool,
+    /// transform_8x8_mode_flag and the following syntax elements are
+    /// present, otherwise they have their inferred values
+    pub more_rbsp_data: bool,
+    pub transform_8x8_mode_flag: bool,
+    pub pic_scaling_matrix_present_flag: bool,
+    pub pic_scaling_lists: Option<ScalingLists>,
+    pub second_chroma_qp_index_offset: i8,
 }
 
-/*
 fn err(text: &str) -> ParserError {
     let unit = ParserUnit::Pps();
     let description = String::from(text);
-    let error = ParserUnitError { unit, description };
+    let error = ParserUnitError { unit, description, location: None };
 
     ParserError::InvalidStream(error)
 }
-*/
 
 fn not_impl(text: &str) -> ParserError {
     let unit = ParserUnit::Pps();
     let description = String::from(text);
-    let error = ParserUnitError { unit, description };
+    let error = ParserUnitError { unit, description, location: None };
 
     ParserError::NotImplemented(error)
 }
 
 impl PictureParameterSet {
 
-    pub fn parse<R: Read + Seek>(r: &mut BitReader<R>) ->
-            Result<PictureParameterSet> {
-        let pic_parameter_set_id = r.ue8()?;
-        let seq_parameter_set_id = r.ue8()?;
-        let entropy_coding_mode_flag = r.flag()?;
-        let bottom_field_pic_order_in_frame_present_flag = r.flag()?;
-        let num_slice_groups_minus1 = r.ue8()?;
+    /// Parses a PPS. The referenced SPS is only needed when the PPS
+    /// contains 8x8 scaling lists since their number depends on
+    /// chroma_format_idc.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>,
+                             parameter_sets: &ParameterSets) ->
+               Result<PictureParameterSet> {
+        let pic_parameter_set_id = r.name("pic_parameter_set_id").ue8()?;
+        let seq_parameter_set_id = r.name("seq_parameter_set_id").ue8()?;
+        let entropy_coding_mode_flag =
+            r.name("entropy_coding_mode_flag").flag()?;
+        let bottom_field_pic_order_in_frame_present_flag =
+            r.name("bottom_field_pic_order_in_frame_present_flag").flag()?;
+        let num_slice_groups_minus1 = r.name("num_slice_groups_minus1").ue8()?;
 
         if num_slice_groups_minus1 > 0 {
             return Err(not_impl("Slice groups not impl"));
         }
 
         /* Range 0 - 31 */
-        let num_ref_idx_l0_default_active_minus1 = r.ue8()?;
+        let num_ref_idx_l0_default_active_minus1 =
+            r.name("num_ref_idx_l0_default_active_minus1").ue8()?;
         /* Range 0 - 31 */
-        let num_ref_idx_l1_default_active_minus1 = r.ue8()?;
+        let num_ref_idx_l1_default_active_minus1 =
+            r.name("num_ref_idx_l1_default_active_minus1").ue8()?;
 
-        let weighted_pred_flag = r.flag()?;
-        let weighted_bipred_idc = r.u8(2)?;
+        let weighted_pred_flag = r.name("weighted_pred_flag").flag()?;
+        let weighted_bipred_idc = r.name("weighted_bipred_idc").u8(2)?;
         /* -26 to 25 inclusive */
-        let pic_init_qp_minus26 = r.se8()?;
+        let pic_init_qp_minus26 = r.name("pic_init_qp_minus26").se8()?;
         /* -26 to 25 inclusive */
-        let pic_init_qs_minus26 = r.se8()?;
+        let pic_init_qs_minus26 = r.name("pic_init_qs_minus26").se8()?;
         /* -12 to 12 inclusive */
-        let chroma_qp_index_offset = r.se8()?;
-        let deblocking_filter_control_present_flag = r.flag()?;
-        let constrained_intra_pred_flag = r.flag()?;
-        let redundant_pic_cnt_present_flag = r.flag()?;
+        let chroma_qp_index_offset = r.name("chroma_qp_index_offset").se8()?;
+        let deblocking_filter_control_present_flag =
+            r.name("deblocking_filter_control_present_flag").flag()?;
+        let constrained_intra_pred_flag =
+            r.name("constrained_intra_pred_flag").flag()?;
+        let redundant_pic_cnt_present_flag =
+            r.name("redundant_pic_cnt_present_flag").flag()?;
 
         /* Defaults when there is no more rbsp data */
         let mut transform_8x8_mode_flag = false;
         let mut pic_scaling_matrix_present_flag = false;
+        let mut pic_scaling_lists = None;
         let mut second_chroma_qp_index_offset = 0;
 
         let more_rbsp_data = r.more_rbsp_data()?;
         if more_rbsp_data {
-            transform_8x8_mode_flag = r.flag()?;
-            pic_scaling_matrix_present_flag = r.flag()?;
+            transform_8x8_mode_flag =
+                r.name("transform_8x8_mode_flag").flag()?;
+            pic_scaling_matrix_present_flag =
+                r.name("pic_scaling_matrix_present_flag").flag()?;
             if pic_scaling_matrix_present_flag {
-                return Err(not_impl("Scaling matrix not impl"));
+                let mut count = 6;
+                if transform_8x8_mode_flag {
+                    let sps = match parameter_sets.sps(seq_parameter_set_id) {
+                        Some(sps) => sps,
+                        None => return Err(err("Referenced SPS not found")),
+                    };
+                    count += if sps.chroma_format_idc != 3 { 2 } else { 6 };
+                }
+                pic_scaling_lists = Some(ScalingLists::parse(r, count)?);
             }
 
-            second_chroma_qp_index_offset = r.se8()?;
+            second_chroma_qp_index_offset =
+                r.name("second_chroma_qp_index_offset").se8()?;
         }
         r.rbsp_trailing_bits()?;
 
@@ -111,9 +140,271 @@ impl PictureParameterSet {
             deblocking_filter_control_present_flag,
             constrained_intra_pred_flag,
             redundant_pic_cnt_present_flag,
+            more_rbsp_data,
             transform_8x8_mode_flag,
             pic_scaling_matrix_present_flag,
+            pic_scaling_lists,
             second_chroma_qp_index_offset,
         })
     }
+
+    /// Writes pic_parameter_set_rbsp(), the inverse of `parse`,
+    /// including the rbsp trailing bits. The number of scaling lists
+    /// written is the number in `pic_scaling_lists`.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        if self.num_slice_groups_minus1 > 0 {
+            return Err(not_impl("Slice groups not impl"));
+        }
+
+        w.ue8(self.pic_parameter_set_id)?;
+        w.ue8(self.seq_parameter_set_id)?;
+        w.flag(self.entropy_coding_mode_flag)?;
+        w.flag(self.bottom_field_pic_order_in_frame_present_flag)?;
+        w.ue8(self.num_slice_groups_minus1)?;
+        w.ue8(self.num_ref_idx_l0_default_active_minus1)?;
+        w.ue8(self.num_ref_idx_l1_default_active_minus1)?;
+        w.flag(self.weighted_pred_flag)?;
+        w.u8(2, self.weighted_bipred_idc)?;
+        w.se8(self.pic_init_qp_minus26)?;
+        w.se8(self.pic_init_qs_minus26)?;
+        w.se8(self.chroma_qp_index_offset)?;
+        w.flag(self.deblocking_filter_control_present_flag)?;
+        w.flag(self.constrained_intra_pred_flag)?;
+        w.flag(self.redundant_pic_cnt_present_flag)?;
+
+        if self.more_rbsp_data {
+            w.flag(self.transform_8x8_mode_flag)?;
+            w.flag(self.pic_scaling_matrix_present_flag)?;
+            if self.pic_scaling_matrix_present_flag {
+                let lists = match self.pic_scaling_lists {
+                    Some(ref lists) => lists,
+                    None => return Err(err("pic_scaling_lists missing")),
+                };
+                let valid = match lists.lists.len() {
+                    6 => !self.transform_8x8_mode_flag,
+                    8 | 12 => self.transform_8x8_mode_flag,
+                    _ => false,
+                };
+                if !valid {
+                    return Err(err("pic_scaling_lists do not match \
+                                    transform_8x8_mode_flag"));
+                }
+                lists.write(w)?;
+            }
+            w.se8(self.second_chroma_qp_index_offset)?;
+        }
+
+        w.rbsp_trailing_bits()
+    }
+
+    /// Effective scaling lists for pictures referring to this PPS
+    /// when `sps` is the active SPS.
+    pub fn scaling_matrix(&self, sps: &SequenceParameterSet) ->
+                          ScalingMatrix {
+        match self.pic_scaling_lists {
+            None => sps.scaling_matrix.clone(),
+            Some(ref lists) => {
+                if sps.seq_scaling_matrix_present_flag {
+                    /* Fall-back rule B */
+                    ScalingMatrix::new(lists, Some(&sps.scaling_matrix))
+                }
+                else {
+                    /* Fall-back rule A */
+                    ScalingMatrix::new(lists, None)
+                }
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+    use bitreader;
+    use proptest::prelude::*;
+    use proptest::option;
+    use proptest::sample::select;
+    use nalunit::NalPayload;
+    use scaling::*;
+    use scaling::tests::scaling_lists;
+    use super::*;
+
+    /* High profile SPS with scaling matrix where Intra Y 4x4 uses
+     * the default list and Intra Y 8x8 is all 10 */
+    const SPS: [u8; 12] = [
+        0x64, 0x00, 0x28, 0xad, 0x84, 0x41, 0x20, 0x55,
+        0xb4, 0x0a, 0x0f, 0xc8,
+    ];
+
+    /* PPS with 8x8 transform and only Inter Y 4x4 scaling list */
+    const PPS: [u8; 6] = [
+        0xee, 0x3c, 0xc4, 0x20, 0x08, 0x43,
+    ];
+
+    fn parameter_sets() -> ParameterSets {
+        let mut reader = BitReader::new(Cursor::new(SPS));
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        let mut parameter_sets = ParameterSets::new();
+        parameter_sets.update(&NalPayload::SequenceParameterSet(sps));
+
+        parameter_sets
+    }
+
+    #[test]
+    fn parse_scaling_matrix() {
+        let parameter_sets = parameter_sets();
+        let mut reader = BitReader::from_rbsp(&PPS);
+
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             &parameter_sets).unwrap();
+
+        assert!(pps.transform_8x8_mode_flag);
+        assert_eq!(pps.pic_scaling_lists.unwrap().lists.len(), 8);
+    }
+
+    /* One byte at a time, the rest of the RBSP is never buffered */
+    #[test]
+    fn parse_with_rbsp_length() {
+        let parameter_sets = parameter_sets();
+        let length = bitreader::rbsp_length(&PPS);
+        let bytes = BufReader::with_capacity(1, &PPS[..]);
+        let mut reader = BitReader::with_rbsp_length(bytes, length);
+
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             &parameter_sets).unwrap();
+
+        assert!(pps.more_rbsp_data);
+        assert!(pps.transform_8x8_mode_flag);
+        assert_eq!(write(&pps), PPS);
+    }
+
+    #[test]
+    fn parse_scaling_matrix_without_sps() {
+        let parameter_sets = ParameterSets::new();
+        let mut reader = BitReader::from_rbsp(&PPS);
+
+        let res = PictureParameterSet::parse(&mut reader, &parameter_sets);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn scaling_matrix_fall_back_rule_b() {
+        let parameter_sets = parameter_sets();
+        let sps = parameter_sets.sps(0).unwrap();
+        let mut reader = BitReader::from_rbsp(&PPS);
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             &parameter_sets).unwrap();
+
+        let matrix = pps.scaling_matrix(sps);
+
+        /* Intra from SPS, Inter Y explicitly flat */
+        assert_eq!(matrix.scaling_list_4x4[0], DEFAULT_4X4_INTRA);
+        assert_eq!(matrix.scaling_list_4x4[3], vec![16; 16]);
+        assert_eq!(matrix.scaling_list_4x4[5], vec![16; 16]);
+        assert_eq!(matrix.scaling_list_8x8[0], vec![10; 64]);
+        assert_eq!(matrix.scaling_list_8x8[1], DEFAULT_8X8_INTER.to_vec());
+    }
+
+    fn write(pps: &PictureParameterSet) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        pps.write(&mut writer).unwrap();
+
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn write_byte_exact() {
+        let parameter_sets = parameter_sets();
+        let mut reader = BitReader::from_rbsp(&PPS);
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             &parameter_sets).unwrap();
+
+        assert_eq!(write(&pps), PPS);
+    }
+
+    #[test]
+    fn write_without_more_rbsp_data() {
+        /* Baseline PPS ending after redundant_pic_cnt_present_flag */
+        let buf = [0xce, 0x3c, 0x80];
+        let mut reader = BitReader::from_rbsp(&buf);
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             &ParameterSets::new()).unwrap();
+
+        assert!(!pps.more_rbsp_data);
+        assert_eq!(write(&pps), buf);
+    }
+
+    prop_compose! {
+        fn picture_parameter_set()(
+                ids in (any::<u8>(), 0u8..32),
+                flags in any::<[bool; 6]>(),
+                num_ref_idx in (0u8..32, 0u8..32),
+                weighted_bipred_idc in 0u8..4,
+                qp in (-26i8..26, -26i8..26, -12i8..13, -12i8..13),
+                more_rbsp_data in any::<bool>(),
+                transform_8x8_mode_flag in any::<bool>(),
+                lists in option::of(scaling_lists(12)),
+                count in select(vec![8, 12]))
+                -> PictureParameterSet {
+            let more = more_rbsp_data;
+            let pic_scaling_lists = match lists {
+                Some(mut lists) if more => {
+                    lists.lists.truncate(
+                        if transform_8x8_mode_flag { count } else { 6 });
+                    Some(lists)
+                },
+                _ => None,
+            };
+
+            PictureParameterSet {
+                pic_parameter_set_id: ids.0,
+                seq_parameter_set_id: ids.1,
+                entropy_coding_mode_flag: flags[0],
+                bottom_field_pic_order_in_frame_present_flag: flags[1],
+                num_slice_groups_minus1: 0,
+                num_ref_idx_l0_default_active_minus1: num_ref_idx.0,
+                num_ref_idx_l1_default_active_minus1: num_ref_idx.1,
+                weighted_pred_flag: flags[2],
+                weighted_bipred_idc,
+                pic_init_qp_minus26: qp.0,
+                pic_init_qs_minus26: qp.1,
+                chroma_qp_index_offset: qp.2,
+                deblocking_filter_control_present_flag: flags[3],
+                constrained_intra_pred_flag: flags[4],
+                redundant_pic_cnt_present_flag: flags[5],
+                more_rbsp_data: more,
+                transform_8x8_mode_flag: more && transform_8x8_mode_flag,
+                pic_scaling_matrix_present_flag: pic_scaling_lists.is_some(),
+                pic_scaling_lists,
+                second_chroma_qp_index_offset: if more { qp.3 } else { 0 },
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn write_read_back(pps in picture_parameter_set()) {
+            /* The number of 8x8 lists is given by the SPS */
+            let mut reader = BitReader::new(Cursor::new(SPS));
+            let mut sps = SequenceParameterSet::parse(&mut reader).unwrap();
+            sps.seq_parameter_set_id = pps.seq_parameter_set_id;
+            if let Some(ref lists) = pps.pic_scaling_lists {
+                if lists.lists.len() == 12 {
+                    sps.chroma_format_idc = 3;
+                }
+            }
+            let mut parameter_sets = ParameterSets::new();
+            parameter_sets.update(&NalPayload::SequenceParameterSet(sps));
+            let bytes = write(&pps);
+            let mut reader = BitReader::from_rbsp(&bytes);
+
+            let parsed = PictureParameterSet::parse(&mut reader,
+                                                    &parameter_sets).unwrap();
+
+            prop_assert_eq!(write(&parsed), bytes);
+            prop_assert_eq!(parsed, pps);
+        }
+    }
 }
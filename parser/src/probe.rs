@@ -0,0 +1,144 @@
+use std::fmt;
+
+use ts;
+
+/// Format of a file holding H.264 video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Byte stream of Annex B, raw H.264
+    AnnexB,
+    /// ISO base media file, .mp4 and .mov
+    Mp4,
+    MpegTs,
+    /// Matroska and WebM
+    Matroska,
+    Flv,
+    /// pcap and pcapng capture of RTP
+    Pcap,
+}
+
+const FORMATS: [(Format, &str); 6] = [
+    (Format::AnnexB, "annexb"),
+    (Format::Mp4, "mp4"),
+    (Format::MpegTs, "ts"),
+    (Format::Matroska, "mkv"),
+    (Format::Flv, "flv"),
+    (Format::Pcap, "pcap"),
+];
+
+/* Types of the first box of ISO base media files */
+const MP4_BOXES: [&[u8; 4]; 7] = [
+    b"ftyp", b"styp", b"moov", b"mdat", b"free", b"skip", b"wide",
+];
+
+impl Format {
+    /// Short name of the format, as used on the command line.
+    pub fn name(&self) -> &'static str {
+        FORMATS.iter()
+            .find(|(format, _)| format == self)
+            .map(|(_, name)| *name)
+            .unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        FORMATS.iter()
+            .find(|(_, n)| *n == name)
+            .map(|(format, _)| *format)
+    }
+
+    /// Short names of all formats.
+    pub fn names() -> Vec<&'static str> {
+        FORMATS.iter().map(|(_, name)| *name).collect()
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Format::AnnexB => "Annex B",
+            Format::Mp4 => "MP4",
+            Format::MpegTs => "MPEG-TS",
+            Format::Matroska => "Matroska",
+            Format::Flv => "FLV",
+            Format::Pcap => "pcap",
+        })
+    }
+}
+
+/// Detects the format from the first bytes of a file, a few hundred
+/// bytes are enough. None when the format is not recognized.
+pub fn probe(data: &[u8]) -> Option<Format> {
+    if data.starts_with(b"FLV\x01") {
+        return Some(Format::Flv);
+    }
+    if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        return Some(Format::Matroska);
+    }
+    if data.len() >= 8 && MP4_BOXES.iter().any(|b| data[4..8] == b[..]) {
+        return Some(Format::Mp4);
+    }
+    let pcap_magic: [[u8; 4]; 5] = [
+        [0xa1, 0xb2, 0xc3, 0xd4], [0xd4, 0xc3, 0xb2, 0xa1],
+        [0xa1, 0xb2, 0x3c, 0x4d], [0x4d, 0x3c, 0xb2, 0xa1],
+        [0x0a, 0x0d, 0x0d, 0x0a],
+    ];
+    if pcap_magic.iter().any(|magic| data.starts_with(magic)) {
+        return Some(Format::Pcap);
+    }
+    /* Sync bytes of at least two packets */
+    let packets = data.len() / ts::PACKET_SIZE;
+    if packets >= 2 &&
+       (0..packets).all(|i| data[i * ts::PACKET_SIZE] == 0x47) {
+        return Some(Format::MpegTs);
+    }
+    /* Start code prefix, possibly after leading_zero_8bits */
+    let zeroes = data.iter().take_while(|b| **b == 0x00).count();
+    if zeroes >= 2 && data.get(zeroes) == Some(&0x01) {
+        return Some(Format::AnnexB);
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_formats() {
+        let mut ts = vec![0xff; 2 * ts::PACKET_SIZE];
+        ts[0] = 0x47;
+        ts[ts::PACKET_SIZE] = 0x47;
+
+        assert_eq!(probe(&[0x00, 0x00, 0x00, 0x01, 0x67]),
+                   Some(Format::AnnexB));
+        assert_eq!(probe(&[0x00, 0x00, 0x01, 0x09, 0xf0]),
+                   Some(Format::AnnexB));
+        assert_eq!(probe(b"\x00\x00\x00\x20ftypisom"), Some(Format::Mp4));
+        assert_eq!(probe(&ts), Some(Format::MpegTs));
+        assert_eq!(probe(&[0x1a, 0x45, 0xdf, 0xa3, 0x9f]),
+                   Some(Format::Matroska));
+        assert_eq!(probe(b"FLV\x01\x05\x00\x00\x00\x09"), Some(Format::Flv));
+        assert_eq!(probe(&[0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00]),
+                   Some(Format::Pcap));
+    }
+
+    #[test]
+    fn probe_unknown() {
+        let mut ts = vec![0xff; 2 * ts::PACKET_SIZE];
+        ts[0] = 0x47;
+
+        assert_eq!(probe(&ts), None);
+        assert_eq!(probe(&[0x00, 0x00, 0x02]), None);
+        assert_eq!(probe(&[]), None);
+    }
+
+    #[test]
+    fn names() {
+        for name in Format::names() {
+            assert_eq!(Format::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(Format::from_name("avi"), None);
+    }
+}
@@ -0,0 +1,431 @@
+use std::io::prelude::*;
+
+use demux::Demuxer;
+use pcap::PcapReader;
+use super::*;
+
+/// RTP packet, RFC 3550 5.1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtpPacket<'a> {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    /// Payload without the padding
+    pub payload: &'a [u8],
+}
+
+/// Sequence number discontinuity of an RTP stream.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum SequenceError {
+    /// Packets from `expected` up to `found` are missing
+    Gap { expected: u16, found: u16 },
+    /// `found` arrived after packets following it, or twice
+    Reordered { expected: u16, found: u16 },
+}
+
+/// Depacketizer of the H.264 RTP payload format of RFC 6184.
+///
+/// NAL units are returned in transmission order, the decoding order
+/// numbers of the interleaved mode are not used for reordering.
+#[derive(Default)]
+pub struct Depacketizer {
+    /* NAL unit of the fragmentation units received so far */
+    fragments: Option<Vec<u8>>,
+    next_sequence_number: Option<u16>,
+    sequence_errors: Vec<SequenceError>,
+    skipped: usize,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Rtp();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn be16(data: &[u8]) -> usize {
+    (data[0] as usize) << 8 | data[1] as usize
+}
+
+impl<'a> RtpPacket<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<RtpPacket<'a>> {
+        if data.len() < 12 {
+            return Err(err("RTP header past end of packet"));
+        }
+        if data[0] >> 6 != 2 {
+            return Err(err("RTP version is not 2"));
+        }
+        let padding = data[0] & 0x20 != 0;
+        let extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0x0f) as usize;
+
+        let mut start = 12 + 4 * csrc_count;
+        if extension {
+            if data.len() < start + 4 {
+                return Err(err("RTP header extension past end of packet"));
+            }
+            start += 4 + 4 * be16(&data[start + 2..]);
+        }
+        let mut end = data.len();
+        if padding {
+            end = end.saturating_sub(data[end - 1] as usize);
+        }
+        if start > end {
+            return Err(err("RTP payload past end of packet"));
+        }
+
+        Ok(RtpPacket {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7f,
+            sequence_number: be16(&data[2..]) as u16,
+            timestamp: u32::from_be_bytes([data[4], data[5], data[6],
+                                           data[7]]),
+            ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            payload: &data[start..end],
+        })
+    }
+}
+
+/* NAL units of an aggregation packet, each preceded by its 16 bit
+ * size and `skip` bytes of DOND and TS offset after the size */
+fn aggregation(mut data: &[u8], skip: usize) -> Result<Vec<Vec<u8>>> {
+    let mut nal_units = Vec::new();
+
+    while !data.is_empty() {
+        if data.len() < 2 + skip {
+            return Err(err("Aggregation unit size past end of packet"));
+        }
+        let size = be16(data);
+        data = &data[2 + skip..];
+        if size == 0 || data.len() < size {
+            return Err(err("Aggregation unit past end of packet"));
+        }
+        nal_units.push(data[..size].to_vec());
+        data = &data[size..];
+    }
+
+    Ok(nal_units)
+}
+
+impl Depacketizer {
+    pub fn new() -> Depacketizer {
+        Depacketizer::default()
+    }
+
+    /// Sequence number errors found so far.
+    pub fn sequence_errors(&self) -> &[SequenceError] {
+        &self.sequence_errors
+    }
+
+    /// Number of packets skipped for an empty payload, as sent for
+    /// padding, or a reserved NAL unit type.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /* Checks the sequence number, false for late packets */
+    fn check_sequence(&mut self, sequence_number: u16) -> bool {
+        let expected = match self.next_sequence_number {
+            Some(expected) => expected,
+            None => sequence_number,
+        };
+        let distance = sequence_number.wrapping_sub(expected) as i16;
+        if distance < 0 {
+            self.sequence_errors.push(SequenceError::Reordered {
+                expected,
+                found: sequence_number,
+            });
+            return false;
+        }
+        if distance > 0 {
+            self.sequence_errors.push(SequenceError::Gap {
+                expected,
+                found: sequence_number,
+            });
+            /* The rest of the fragmented NAL unit is lost */
+            self.fragments = None;
+        }
+        self.next_sequence_number = Some(sequence_number.wrapping_add(1));
+
+        true
+    }
+
+    /// Returns the NAL units completed by `packet`. Late packets are
+    /// reported as reordered and dropped, packets without a NAL unit
+    /// are skipped.
+    pub fn push(&mut self, packet: &RtpPacket) -> Result<Vec<Vec<u8>>> {
+        if !self.check_sequence(packet.sequence_number) {
+            return Ok(Vec::new());
+        }
+        let payload = packet.payload;
+        let header = match payload.first() {
+            Some(header) if header & 0x1f != 0 && header & 0x1f < 30 =>
+                *header,
+            _ => {
+                self.skipped += 1;
+                return Ok(Vec::new());
+            },
+        };
+
+        match header & 0x1f {
+            /* Single NAL unit packet */
+            1..=23 => Ok(vec![payload.to_vec()]),
+            /* STAP-A */
+            24 => aggregation(&payload[1..], 0),
+            /* STAP-B, after the DON */
+            25 if payload.len() >= 3 => aggregation(&payload[3..], 0),
+            /* MTAP16 and MTAP24, after the DONB */
+            26 if payload.len() >= 3 => aggregation(&payload[3..], 3),
+            27 if payload.len() >= 3 => aggregation(&payload[3..], 4),
+            /* FU-A and FU-B, the latter with DON in the first
+             * fragment */
+            nal_type @ 28..=29 => {
+                let start = if nal_type == 29 { 4 } else { 2 };
+                if payload.len() < start {
+                    return Err(err("FU header past end of packet"));
+                }
+                let fu_header = payload[1];
+                let data = &payload[start..];
+                if fu_header & 0x80 != 0 {
+                    let mut nal_unit = vec![header & 0xe0 | fu_header & 0x1f];
+                    nal_unit.extend_from_slice(data);
+                    self.fragments = Some(nal_unit);
+                }
+                else if let Some(ref mut nal_unit) = self.fragments {
+                    nal_unit.extend_from_slice(data);
+                }
+                if fu_header & 0x40 != 0 {
+                    return Ok(self.fragments.take().into_iter().collect());
+                }
+
+                Ok(Vec::new())
+            },
+            _ => Err(err("Aggregation header past end of packet")),
+        }
+    }
+}
+
+/// Reads the H.264 RTP stream of a pcap or pcapng capture, as an
+/// Annex B byte stream with `AnnexBReader`.
+///
+/// The stream is the first SSRC with `payload_type`, or with a dynamic
+/// payload type and a valid NAL unit header when None.
+pub struct RtpReader<R> {
+    pcap: PcapReader<R>,
+    payload_type: Option<u8>,
+    ssrc: Option<u32>,
+    depacketizer: Depacketizer,
+}
+
+impl<R: Read> RtpReader<R> {
+    pub fn new(reader: R, payload_type: Option<u8>) -> Result<RtpReader<R>> {
+        Ok(RtpReader {
+            pcap: PcapReader::new(reader)?,
+            payload_type,
+            ssrc: None,
+            depacketizer: Depacketizer::new(),
+        })
+    }
+
+    /// SSRC of the stream read, once its first packet was found.
+    pub fn ssrc(&self) -> Option<u32> {
+        self.ssrc
+    }
+
+    /// Sequence number errors found so far.
+    pub fn sequence_errors(&self) -> &[SequenceError] {
+        self.depacketizer.sequence_errors()
+    }
+
+    /// Number of packets of the stream skipped so far.
+    pub fn skipped(&self) -> usize {
+        self.depacketizer.skipped()
+    }
+
+    fn is_stream(&self, packet: &RtpPacket) -> bool {
+        if let Some(ssrc) = self.ssrc {
+            return packet.ssrc == ssrc;
+        }
+        match self.payload_type {
+            Some(payload_type) => packet.payload_type == payload_type,
+            /* RTCP packet types 200 to 204 look like payload types 72
+             * to 76 with marker */
+            None => packet.payload_type >= 96 &&
+                packet.payload.first()
+                    .is_some_and(|h| h & 0x80 == 0 && h & 0x1f != 0 &&
+                                 h & 0x1f <= 29),
+        }
+    }
+
+    /// Reads the NAL units completed by the next packet of the stream,
+    /// None at end of data.
+    pub fn next_nal_units(&mut self) -> Result<Option<Vec<Vec<u8>>>> {
+        while let Some(datagram) = self.pcap.next_datagram()? {
+            let packet = match RtpPacket::parse(&datagram.payload) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+            if !self.is_stream(&packet) {
+                continue;
+            }
+            self.ssrc = Some(packet.ssrc);
+
+            return self.depacketizer.push(&packet).map(Some);
+        }
+
+        Ok(None)
+    }
+}
+
+impl<R: Read> Demuxer for RtpReader<R> {
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>> {
+        let nal_units = match self.next_nal_units()? {
+            Some(nal_units) => nal_units,
+            None => return Ok(None),
+        };
+        let mut annex_b = Vec::new();
+        for nal_unit in nal_units {
+            annex_b.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+            annex_b.extend_from_slice(&nal_unit);
+        }
+
+        Ok(Some(annex_b))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use demux::tests::nal_types;
+    use pcap::tests::{ethernet_udp, pcap};
+    use super::*;
+
+    const SPS: [u8; 8] = [0x67, 0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    fn rtp(sequence_number: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 0x60];
+        packet.extend_from_slice(&sequence_number.to_be_bytes());
+        packet.extend_from_slice(&[0x00, 0x00, 0x0b, 0xb8,
+                                   0x12, 0x34, 0x56, 0x78]);
+        packet.extend_from_slice(payload);
+
+        packet
+    }
+
+    fn push(depacketizer: &mut Depacketizer, sequence_number: u16,
+            payload: &[u8]) -> Vec<Vec<u8>> {
+        let packet = rtp(sequence_number, payload);
+        let packet = RtpPacket::parse(&packet).unwrap();
+
+        depacketizer.push(&packet).unwrap()
+    }
+
+    #[test]
+    fn parse_header() {
+        /* Padding, one CSRC and a header extension of one word */
+        let data = [
+            0xb1, 0xe0, 0x00, 0x07, 0x00, 0x00, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03,
+            0xbe, 0xde, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x09, 0xf0, 0x00, 0x02,
+        ];
+
+        let packet = RtpPacket::parse(&data).unwrap();
+
+        assert!(packet.marker);
+        assert_eq!(packet.payload_type, 96);
+        assert_eq!(packet.sequence_number, 7);
+        assert_eq!((packet.timestamp, packet.ssrc), (1, 2));
+        assert_eq!(packet.payload, [0x09, 0xf0]);
+        assert!(RtpPacket::parse(&data[..20]).is_err());
+    }
+
+    #[test]
+    fn single_and_aggregation() {
+        let mut depacketizer = Depacketizer::new();
+        let mut stap_a = vec![0x78, 0x00, 0x08];
+        stap_a.extend_from_slice(&SPS);
+        stap_a.extend_from_slice(&[0x00, 0x04]);
+        stap_a.extend_from_slice(&PPS);
+        let mut mtap16 = vec![0x7a, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00];
+        mtap16.extend_from_slice(&PPS);
+
+        assert_eq!(push(&mut depacketizer, 1, &[0x09, 0xf0]),
+                   [vec![0x09, 0xf0]]);
+        assert_eq!(push(&mut depacketizer, 2, &stap_a),
+                   [SPS.to_vec(), PPS.to_vec()]);
+        assert_eq!(push(&mut depacketizer, 3, &mtap16), [PPS.to_vec()]);
+        assert!(depacketizer.sequence_errors().is_empty());
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut depacketizer = Depacketizer::new();
+
+        assert!(push(&mut depacketizer, 10, &[0x7c, 0x85, 0x88, 0x84])
+                .is_empty());
+        assert!(push(&mut depacketizer, 11, &[0x7c, 0x05, 0x00]).is_empty());
+        assert_eq!(push(&mut depacketizer, 12, &[0x7c, 0x45, 0x21]),
+                   [vec![0x65, 0x88, 0x84, 0x00, 0x21]]);
+        /* FU-B with DON */
+        assert_eq!(push(&mut depacketizer, 13, &[0x7d, 0xc1, 0x00, 0x01, 0x9a]),
+                   [vec![0x61, 0x9a]]);
+    }
+
+    #[test]
+    fn sequence_errors() {
+        let mut depacketizer = Depacketizer::new();
+
+        push(&mut depacketizer, 65535, &[0x7c, 0x85, 0x88]);
+        /* Gap drops the fragmented NAL unit */
+        let nal_units = push(&mut depacketizer, 2, &[0x7c, 0x45, 0x21]);
+        let late = push(&mut depacketizer, 1, &[0x09, 0xf0]);
+
+        assert!(nal_units.is_empty());
+        assert!(late.is_empty());
+        assert_eq!(depacketizer.sequence_errors(), [
+            SequenceError::Gap { expected: 0, found: 2 },
+            SequenceError::Reordered { expected: 3, found: 1 },
+        ]);
+    }
+
+    #[test]
+    fn read_annex_b() {
+        /* An RTCP receiver report, a STAP-A and a padding packet */
+        let frames = [
+            ethernet_udp(&[0x81, 0xc9, 0x00, 0x07, 0x00, 0x00, 0x00, 0x01,
+                           0x00, 0x00, 0x00, 0x00]),
+            ethernet_udp(&rtp(1, &[0x78, 0x00, 0x02, 0x09, 0xf0,
+                                   0x00, 0x08, 0x67, 0x42, 0xc0, 0x1e,
+                                   0xed, 0x02, 0x83, 0xf2])),
+            ethernet_udp(&rtp(2, &[])),
+            ethernet_udp(&rtp(3, &PPS)),
+        ];
+        let reader = RtpReader::new(Cursor::new(pcap(&frames)), None)
+            .unwrap();
+
+        let types = nal_types(reader);
+
+        assert_eq!(types, [9, 7, 8]);
+    }
+
+    #[test]
+    fn skip_padding() {
+        let mut depacketizer = Depacketizer::new();
+
+        assert_eq!(push(&mut depacketizer, 1, &[0x09, 0xf0]),
+                   [vec![0x09, 0xf0]]);
+        assert!(push(&mut depacketizer, 2, &[]).is_empty());
+        assert!(push(&mut depacketizer, 3, &[0x1e, 0x00]).is_empty());
+        assert_eq!(push(&mut depacketizer, 4, &[0x09, 0xf0]),
+                   [vec![0x09, 0xf0]]);
+        assert_eq!(depacketizer.skipped(), 2);
+        assert!(depacketizer.sequence_errors().is_empty());
+    }
+}
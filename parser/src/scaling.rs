@@ -0,0 +1,370 @@
+use std::io::prelude::*;
+
+use bitreader::BitReader;
+use bitwriter::BitWriter;
+use super::*;
+
+/* Table 7-3 and 7-4, default scaling lists in zig-zag scan order */
+pub const DEFAULT_4X4_INTRA: [u8; 16] = [
+     6, 13, 13, 20, 20, 20, 28, 28, 28, 28, 32, 32, 32, 37, 37, 42,
+];
+
+pub const DEFAULT_4X4_INTER: [u8; 16] = [
+    10, 14, 14, 20, 20, 20, 24, 24, 24, 24, 27, 27, 27, 30, 30, 34,
+];
+
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+     6, 10, 10, 13, 11, 13, 16, 16, 16, 16, 18, 18, 18, 18, 18, 23,
+    23, 23, 23, 23, 23, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27,
+    27, 27, 27, 27, 29, 29, 29, 29, 29, 29, 29, 31, 31, 31, 31, 31,
+    31, 33, 33, 33, 33, 33, 36, 36, 36, 36, 38, 38, 38, 40, 40, 42,
+];
+
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+     9, 13, 13, 15, 13, 15, 17, 17, 17, 17, 19, 19, 19, 19, 19, 21,
+    21, 21, 21, 21, 21, 22, 22, 22, 22, 22, 22, 22, 24, 24, 24, 24,
+    24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27, 27,
+    27, 28, 28, 28, 28, 28, 30, 30, 30, 30, 32, 32, 32, 33, 33, 35,
+];
+
+fn err(text: &str) -> ParserError {
+    let description = String::from(text);
+    let error = BitWriterError { description };
+
+    ParserError::BitWriterError(error)
+}
+
+/// A single scaling_list() as coded in the bitstream.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ScalingList {
+    /// The coded delta_scale values. Coding stops when nextScale
+    /// becomes 0, so there might be fewer values than list entries.
+    pub delta_scale: Vec<i8>,
+}
+
+/// The scaling lists of a SPS or PPS as coded in the bitstream.
+/// One entry per list index, None when scaling_list_present_flag
+/// for that index is 0.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ScalingLists {
+    pub lists: Vec<Option<ScalingList>>,
+}
+
+/// The effective scaling lists after applying the inference rules
+/// of Table 7-2. Lists are in zig-zag scan order, indexed as in
+/// the bitstream: Intra Y, Intra Cb, Intra Cr, Inter Y, Inter Cb,
+/// Inter Cr.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ScalingMatrix {
+    pub scaling_list_4x4: Vec<Vec<u8>>,
+    pub scaling_list_8x8: Vec<Vec<u8>>,
+}
+
+impl ScalingList {
+    /// Parses scaling_list() as specified in 7.3.2.1.1.1.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, size: usize) ->
+                             Result<ScalingList> {
+        let mut delta_scale = Vec::new();
+        let mut last_scale: i32 = 8;
+        let mut next_scale: i32 = 8;
+
+        for _ in 0..size {
+            if next_scale != 0 {
+                /* Range -128 to 127 */
+                let delta = r.name("delta_scale").se8()?;
+                delta_scale.push(delta);
+                next_scale = (last_scale + delta as i32 + 256) % 256;
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+        }
+
+        Ok(ScalingList { delta_scale })
+    }
+
+    /// Writes scaling_list() for a list of `size` entries. The number
+    /// of delta_scale values must be the number the parser reads.
+    pub fn write(&self, w: &mut BitWriter, size: usize) -> Result<()> {
+        let mut deltas = self.delta_scale.iter();
+        let mut last_scale: i32 = 8;
+        let mut next_scale: i32 = 8;
+
+        for _ in 0..size {
+            if next_scale != 0 {
+                let delta = match deltas.next() {
+                    Some(delta) => *delta,
+                    None => return Err(err("too few delta_scale values")),
+                };
+                w.se8(delta)?;
+                next_scale = (last_scale + delta as i32 + 256) % 256;
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+        }
+        if deltas.next().is_some() {
+            return Err(err("too many delta_scale values"));
+        }
+
+        Ok(())
+    }
+
+    /// Values of the list in zig-zag scan order, None when
+    /// useDefaultScalingMatrixFlag is set.
+    pub fn values(&self, size: usize) -> Option<Vec<u8>> {
+        let mut list = Vec::with_capacity(size);
+        let mut deltas = self.delta_scale.iter();
+        let mut last_scale: i32 = 8;
+        let mut next_scale: i32 = 8;
+
+        for j in 0..size {
+            if next_scale != 0 {
+                let delta = *deltas.next().unwrap_or(&0) as i32;
+                next_scale = (last_scale + delta + 256) % 256;
+                if j == 0 && next_scale == 0 {
+                    return None;
+                }
+            }
+            if next_scale != 0 {
+                last_scale = next_scale;
+            }
+            list.push(last_scale as u8);
+        }
+
+        Some(list)
+    }
+}
+
+impl ScalingLists {
+    /// Parses the scaling_list_present_flags and the scaling lists
+    /// of a SPS or PPS, the first six lists are 4x4 and the rest 8x8.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, count: usize) ->
+                             Result<ScalingLists> {
+        let mut lists = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let scaling_list_present_flag =
+                r.name("scaling_list_present_flag").flag()?;
+            if scaling_list_present_flag {
+                let size = if i < 6 { 16 } else { 64 };
+                lists.push(Some(ScalingList::parse(r, size)?));
+            }
+            else {
+                lists.push(None);
+            }
+        }
+
+        Ok(ScalingLists { lists })
+    }
+
+    /// Writes the scaling_list_present_flags and the scaling lists,
+    /// one per entry of `lists`.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        for (i, list) in self.lists.iter().enumerate() {
+            w.flag(list.is_some())?;
+            if let Some(ref list) = *list {
+                let size = if i < 6 { 16 } else { 64 };
+                list.write(w, size)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_list(i: usize) -> Vec<u8> {
+    match i {
+        0..=2 => DEFAULT_4X4_INTRA.to_vec(),
+        3..=5 => DEFAULT_4X4_INTER.to_vec(),
+        6 | 8 | 10 => DEFAULT_8X8_INTRA.to_vec(),
+        _ => DEFAULT_8X8_INTER.to_vec(),
+    }
+}
+
+impl ScalingMatrix {
+    /// Flat_4x4_16 and Flat_8x8_16, used when no scaling matrix
+    /// is present.
+    pub fn flat() -> ScalingMatrix {
+        ScalingMatrix {
+            scaling_list_4x4: vec![vec![16; 16]; 6],
+            scaling_list_8x8: vec![vec![16; 64]; 6],
+        }
+    }
+
+    /// Derives the effective scaling lists. Lists that are not present
+    /// are inferred by fall-back rule A when `fallback` is None and by
+    /// fall-back rule B, using the lists in `fallback`, otherwise.
+    pub fn new(coded: &ScalingLists, fallback: Option<&ScalingMatrix>)
+               -> ScalingMatrix {
+        let mut lists: Vec<Vec<u8>> = Vec::with_capacity(12);
+
+        for i in 0..12 {
+            let list = match coded.lists.get(i) {
+                Some(Some(list)) => {
+                    let size = if i < 6 { 16 } else { 64 };
+                    list.values(size).unwrap_or_else(|| default_list(i))
+                },
+                /* Not present, first list of each kind falls back to
+                 * default or to the sequence level list */
+                _ => match (i, fallback) {
+                    (0, Some(f)) | (3, Some(f)) =>
+                        f.scaling_list_4x4[i].clone(),
+                    (6, Some(f)) | (7, Some(f)) =>
+                        f.scaling_list_8x8[i - 6].clone(),
+                    (0, None) | (3, None) | (6, None) | (7, None) =>
+                        default_list(i),
+                    /* Inter Y and Intra Cb 8x8 are interleaved */
+                    (i, _) if i >= 8 => lists[i - 2].clone(),
+                    (i, _) => lists[i - 1].clone(),
+                },
+            };
+            lists.push(list);
+        }
+
+        let scaling_list_8x8 = lists.split_off(6);
+        ScalingMatrix {
+            scaling_list_4x4: lists,
+            scaling_list_8x8,
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Cursor;
+    use proptest::prelude::*;
+    use proptest::collection::vec;
+    use proptest::option;
+    use bitwriter::BitWriter;
+    use super::*;
+
+    /* Coded list of `size` entries with the deltas used up to
+     * nextScale 0, which is forced at entry `stop` */
+    fn coded(deltas: &[i8], stop: Option<usize>, size: usize)
+             -> ScalingList {
+        let mut delta_scale = Vec::new();
+        let mut last_scale: i32 = 8;
+
+        for (j, delta) in deltas.iter().take(size).enumerate() {
+            let delta = if stop == Some(j) {
+                (256 - last_scale) as u8 as i8
+            }
+            else {
+                *delta
+            };
+            delta_scale.push(delta);
+            let next_scale = (last_scale + delta as i32 + 256) % 256;
+            if next_scale == 0 {
+                break;
+            }
+            last_scale = next_scale;
+        }
+
+        ScalingList { delta_scale }
+    }
+
+    /// Strategy for `count` scaling lists that can be coded.
+    pub fn scaling_lists(count: usize)
+                         -> impl Strategy<Value = ScalingLists> {
+        vec(option::of((vec(any::<i8>(), 64), option::of(0usize..64))),
+            count).prop_map(|lists| {
+            let lists = lists.iter().enumerate().map(|(i, list)| {
+                let size = if i < 6 { 16 } else { 64 };
+                list.as_ref().map(|&(ref deltas, stop)| {
+                    coded(deltas, stop, size)
+                })
+            }).collect();
+
+            ScalingLists { lists }
+        })
+    }
+
+    #[test]
+    fn scaling_list_use_default() {
+        /* delta_scale -8 => nextScale 0 at first entry */
+        let buf: [u8; 2] = [0b00001000, 0b10000000];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new(cursor);
+
+        let list = ScalingList::parse(&mut reader, 16).unwrap();
+
+        assert_eq!(list.delta_scale, [-8]);
+        assert!(list.values(16).is_none());
+    }
+
+    #[test]
+    fn scaling_list_repeats_last_scale() {
+        /* delta_scale 2, 1, -11 => 10, 11, then repeat 11 */
+        let buf: [u8; 3] = [0b00100010, 0b00001011, 0b10000000];
+        let cursor = Cursor::new(buf);
+        let mut reader = BitReader::new(cursor);
+
+        let list = ScalingList::parse(&mut reader, 16).unwrap();
+        let values = list.values(16).unwrap();
+
+        assert_eq!(list.delta_scale, [2, 1, -11]);
+        assert_eq!(values[0], 10);
+        assert_eq!(values[1], 11);
+        assert!(values[2..].iter().all(|v| *v == 11));
+    }
+
+    #[test]
+    fn fall_back_rule_a() {
+        let mut lists = vec![None; 8];
+        lists[1] = Some(ScalingList { delta_scale: vec![-8] });
+        lists[4] = Some(ScalingList { delta_scale: vec![8, -16] });
+        let coded = ScalingLists { lists };
+
+        let matrix = ScalingMatrix::new(&coded, None);
+
+        assert_eq!(matrix.scaling_list_4x4[0], DEFAULT_4X4_INTRA);
+        assert_eq!(matrix.scaling_list_4x4[1], DEFAULT_4X4_INTRA);
+        assert_eq!(matrix.scaling_list_4x4[2], DEFAULT_4X4_INTRA);
+        assert_eq!(matrix.scaling_list_4x4[3], DEFAULT_4X4_INTER);
+        assert_eq!(matrix.scaling_list_4x4[4], vec![16; 16]);
+        assert_eq!(matrix.scaling_list_4x4[5], vec![16; 16]);
+        assert_eq!(matrix.scaling_list_8x8[0], DEFAULT_8X8_INTRA.to_vec());
+        assert_eq!(matrix.scaling_list_8x8[1], DEFAULT_8X8_INTER.to_vec());
+        assert_eq!(matrix.scaling_list_8x8[4], DEFAULT_8X8_INTRA.to_vec());
+        assert_eq!(matrix.scaling_list_8x8[5], DEFAULT_8X8_INTER.to_vec());
+    }
+
+    #[test]
+    fn fall_back_rule_b() {
+        let sequence = ScalingMatrix::flat();
+        let coded = ScalingLists { lists: vec![None; 6] };
+
+        let matrix = ScalingMatrix::new(&coded, Some(&sequence));
+
+        assert!(matrix.scaling_list_4x4.iter().all(|l| *l == [16; 16]));
+        assert!(matrix.scaling_list_8x8.iter().all(|l| l[..] == [16; 64][..]));
+    }
+
+    #[test]
+    fn write_scaling_list_wrong_length() {
+        let list = ScalingList { delta_scale: vec![2, 1] };
+        let mut writer = BitWriter::new_rbsp();
+
+        assert!(list.write(&mut writer, 16).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn write_read_back(lists in scaling_lists(12)) {
+            let mut writer = BitWriter::new();
+            lists.write(&mut writer).unwrap();
+            writer.rbsp_trailing_bits().unwrap();
+            let bytes = writer.into_bytes();
+            let mut reader = BitReader::new(&bytes[..]);
+
+            let parsed = ScalingLists::parse(&mut reader, 12).unwrap();
+
+            prop_assert_eq!(parsed, lists);
+        }
+    }
+}
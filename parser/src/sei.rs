@@ -0,0 +1,733 @@
+use std::io::prelude::*;
+use std::io::Cursor;
+
+use bitreader::BitReader;
+use paramsets::ParameterSets;
+use sps::{SequenceParameterSet, HrdParameters};
+use super::*;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct BufferingPeriod {
+    pub seq_parameter_set_id: u8,
+    /* One entry per SchedSelIdx, empty when not present */
+    pub nal_initial_cpb_removal_delay: Vec<u32>,
+    pub nal_initial_cpb_removal_delay_offset: Vec<u32>,
+    pub vcl_initial_cpb_removal_delay: Vec<u32>,
+    pub vcl_initial_cpb_removal_delay_offset: Vec<u32>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ClockTimestamp {
+    pub ct_type: u8,
+    pub nuit_field_based_flag: bool,
+    pub counting_type: u8,
+    pub full_timestamp_flag: bool,
+    pub discontinuity_flag: bool,
+    pub cnt_dropped_flag: bool,
+    pub n_frames: u8,
+    pub seconds_value: Option<u8>,
+    pub minutes_value: Option<u8>,
+    pub hours_value: Option<u8>,
+    pub time_offset: i32,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct PicTiming {
+    pub cpb_removal_delay: Option<u32>,
+    pub dpb_output_delay: Option<u32>,
+    pub pic_struct: Option<u8>,
+    /* One entry per NumClockTS, None when clock_timestamp_flag is 0 */
+    pub clock_timestamps: Vec<Option<ClockTimestamp>>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct UserDataRegistered {
+    pub itu_t_t35_country_code: u8,
+    pub itu_t_t35_country_code_extension_byte: Option<u8>,
+    pub itu_t_t35_payload_byte: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct UserDataUnregistered {
+    pub uuid_iso_iec_11578: [u8; 16],
+    pub user_data_payload_byte: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct RecoveryPoint {
+    pub recovery_frame_cnt: u32,
+    pub exact_match_flag: bool,
+    pub broken_link_flag: bool,
+    pub changing_slice_group_idc: u8,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct MasteringDisplayColourVolume {
+    /* Green, blue and red in units of 0.00002 */
+    pub display_primaries_x: [u16; 3],
+    pub display_primaries_y: [u16; 3],
+    pub white_point_x: u16,
+    pub white_point_y: u16,
+    /* Units of 0.0001 candelas per square metre */
+    pub max_display_mastering_luminance: u32,
+    pub min_display_mastering_luminance: u32,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ContentLightLevelInfo {
+    pub max_content_light_level: u16,
+    pub max_pic_average_light_level: u16,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum SeiPayload {
+    BufferingPeriod(BufferingPeriod),
+    PicTiming(PicTiming),
+    UserDataRegistered(UserDataRegistered),
+    UserDataUnregistered(UserDataUnregistered),
+    RecoveryPoint(RecoveryPoint),
+    MasteringDisplayColourVolume(MasteringDisplayColourVolume),
+    ContentLightLevelInfo(ContentLightLevelInfo),
+    /// Payload types that are not decoded, kept as raw payload bytes.
+    Unknown(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct SeiMessage {
+    pub payload_type: u32,
+    pub payload_size: u32,
+    pub payload: SeiPayload,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Sei();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+/* Payload types of the decoded messages, Annex D */
+const BUFFERING_PERIOD: u32 = 0;
+const PIC_TIMING: u32 = 1;
+const USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+const USER_DATA_UNREGISTERED: u32 = 5;
+const RECOVERY_POINT: u32 = 6;
+const MASTERING_DISPLAY_COLOUR_VOLUME: u32 = 137;
+const CONTENT_LIGHT_LEVEL_INFO: u32 = 144;
+
+impl fmt::Display for SeiPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SeiPayload::BufferingPeriod(_) =>
+                write!(f, "buffering period"),
+            SeiPayload::PicTiming(_) =>
+                write!(f, "pic timing"),
+            SeiPayload::UserDataRegistered(_) =>
+                write!(f, "user data registered"),
+            SeiPayload::UserDataUnregistered(_) =>
+                write!(f, "user data unregistered"),
+            SeiPayload::RecoveryPoint(_) =>
+                write!(f, "recovery point"),
+            SeiPayload::MasteringDisplayColourVolume(_) =>
+                write!(f, "mastering display colour volume"),
+            SeiPayload::ContentLightLevelInfo(_) =>
+                write!(f, "content light level info"),
+            SeiPayload::Unknown(_) =>
+                write!(f, "unknown"),
+        }
+    }
+}
+
+/* Reads payloadType or payloadSize, coded as a sequence of 0xFF bytes
+ * followed by a last byte. */
+fn sei_value<R: BufRead>(r: &mut BitReader<R>) -> Result<u32> {
+    let mut value: u32 = 0;
+
+    loop {
+        let byte = r.name("payload_type_or_size_byte").b()?;
+        value = match value.checked_add(byte as u32) {
+            Some(value) => value,
+            None => return Err(err("payload type or size overflow")),
+        };
+        if byte != 0xff {
+            return Ok(value);
+        }
+    }
+}
+
+/* Reads a two's complement signed integer of n bits */
+fn signed<R: BufRead>(r: &mut BitReader<R>, n: u8) -> Result<i32> {
+    if n == 0 {
+        return Ok(0);
+    }
+
+    let u = r.u64(n)?;
+    let sign = 1u64 << (n - 1);
+
+    Ok((u as i64 - ((u & sign) << 1) as i64) as i32)
+}
+
+fn initial_cpb_removal_delays<R: BufRead>(r: &mut BitReader<R>,
+                                          hrd: &HrdParameters) ->
+                                          Result<(Vec<u32>, Vec<u32>)> {
+    let length = hrd.initial_cpb_removal_delay_length_minus1 + 1;
+    let mut delays = Vec::new();
+    let mut offsets = Vec::new();
+
+    for _ in 0..(hrd.cpb_cnt_minus1 as usize + 1) {
+        delays.push(r.name("initial_cpb_removal_delay").u32(length)?);
+        offsets.push(
+            r.name("initial_cpb_removal_delay_offset").u32(length)?);
+    }
+
+    Ok((delays, offsets))
+}
+
+impl BufferingPeriod {
+    /// Parses buffering_period() as specified in D.1.2.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>,
+                             parameter_sets: &ParameterSets) ->
+                             Result<BufferingPeriod> {
+        let seq_parameter_set_id = r.name("seq_parameter_set_id").ue8()?;
+        let sps = match parameter_sets.sps(seq_parameter_set_id) {
+            Some(sps) => sps,
+            None => return Err(err("Referenced SPS not found")),
+        };
+
+        let mut nal_initial_cpb_removal_delay = Vec::new();
+        let mut nal_initial_cpb_removal_delay_offset = Vec::new();
+        let mut vcl_initial_cpb_removal_delay = Vec::new();
+        let mut vcl_initial_cpb_removal_delay_offset = Vec::new();
+        if let Some(ref vui) = sps.vui_parameters {
+            if let Some(ref hrd) = vui.nal_hrd_parameters {
+                let (delays, offsets) = initial_cpb_removal_delays(r, hrd)?;
+                nal_initial_cpb_removal_delay = delays;
+                nal_initial_cpb_removal_delay_offset = offsets;
+            }
+            if let Some(ref hrd) = vui.vcl_hrd_parameters {
+                let (delays, offsets) = initial_cpb_removal_delays(r, hrd)?;
+                vcl_initial_cpb_removal_delay = delays;
+                vcl_initial_cpb_removal_delay_offset = offsets;
+            }
+        }
+
+        Ok(BufferingPeriod {
+            seq_parameter_set_id,
+            nal_initial_cpb_removal_delay,
+            nal_initial_cpb_removal_delay_offset,
+            vcl_initial_cpb_removal_delay,
+            vcl_initial_cpb_removal_delay_offset,
+        })
+    }
+}
+
+impl ClockTimestamp {
+    fn parse<R: BufRead>(r: &mut BitReader<R>, time_offset_length: u8) ->
+                         Result<ClockTimestamp> {
+        let ct_type = r.name("ct_type").u8(2)?;
+        let nuit_field_based_flag = r.name("nuit_field_based_flag").flag()?;
+        let counting_type = r.name("counting_type").u8(5)?;
+        let full_timestamp_flag = r.name("full_timestamp_flag").flag()?;
+        let discontinuity_flag = r.name("discontinuity_flag").flag()?;
+        let cnt_dropped_flag = r.name("cnt_dropped_flag").flag()?;
+        let n_frames = r.name("n_frames").u8(8)?;
+
+        let mut seconds_value = None;
+        let mut minutes_value = None;
+        let mut hours_value = None;
+        if full_timestamp_flag {
+            seconds_value = Some(r.name("seconds_value").u8(6)?);
+            minutes_value = Some(r.name("minutes_value").u8(6)?);
+            hours_value = Some(r.name("hours_value").u8(5)?);
+        }
+        else if r.name("seconds_flag").flag()? {
+            seconds_value = Some(r.name("seconds_value").u8(6)?);
+            if r.name("minutes_flag").flag()? {
+                minutes_value = Some(r.name("minutes_value").u8(6)?);
+                if r.name("hours_flag").flag()? {
+                    hours_value = Some(r.name("hours_value").u8(5)?);
+                }
+            }
+        }
+
+        let time_offset =
+            signed(r.name("time_offset"), time_offset_length)?;
+
+        Ok(ClockTimestamp {
+            ct_type,
+            nuit_field_based_flag,
+            counting_type,
+            full_timestamp_flag,
+            discontinuity_flag,
+            cnt_dropped_flag,
+            n_frames,
+            seconds_value,
+            minutes_value,
+            hours_value,
+            time_offset,
+        })
+    }
+}
+
+impl PicTiming {
+    /// Parses pic_timing() as specified in D.1.3 using the syntax
+    /// element lengths of `sps`.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>,
+                             sps: &SequenceParameterSet) -> Result<PicTiming> {
+        let mut cpb_removal_delay = None;
+        let mut dpb_output_delay = None;
+        let mut pic_struct = None;
+        let mut clock_timestamps = Vec::new();
+
+        let vui = match sps.vui_parameters {
+            Some(ref vui) => vui,
+            None => return Err(err("pic timing without VUI")),
+        };
+
+        /* NAL and VCL HRD shall use the same lengths */
+        let hrd = vui.nal_hrd_parameters.as_ref()
+            .or(vui.vcl_hrd_parameters.as_ref());
+        if let Some(hrd) = hrd {
+            cpb_removal_delay =
+                Some(r.name("cpb_removal_delay")
+                     .u32(hrd.cpb_removal_delay_length_minus1 + 1)?);
+            dpb_output_delay =
+                Some(r.name("dpb_output_delay")
+                     .u32(hrd.dpb_output_delay_length_minus1 + 1)?);
+        }
+
+        if vui.pic_struct_present_flag {
+            let value = r.name("pic_struct").u8(4)?;
+            /* Table D-1 */
+            let num_clock_ts = match value {
+                0..=2 => 1,
+                3 | 4 | 7 => 2,
+                5 | 6 | 8 => 3,
+                _ => return Err(err("pic_struct larger than 8")),
+            };
+            let time_offset_length = hrd.map_or(24, |hrd| {
+                hrd.time_offset_length
+            });
+            for _ in 0..num_clock_ts {
+                let clock_timestamp_flag =
+                    r.name("clock_timestamp_flag").flag()?;
+                if clock_timestamp_flag {
+                    let timestamp = ClockTimestamp::parse(r,
+                                                          time_offset_length)?;
+                    clock_timestamps.push(Some(timestamp));
+                }
+                else {
+                    clock_timestamps.push(None);
+                }
+            }
+            pic_struct = Some(value);
+        }
+
+        Ok(PicTiming {
+            cpb_removal_delay,
+            dpb_output_delay,
+            pic_struct,
+            clock_timestamps,
+        })
+    }
+}
+
+impl UserDataRegistered {
+    /// Parses user_data_registered_itu_t_t35() as specified in D.1.5.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, payload_size: u32) ->
+                             Result<UserDataRegistered> {
+        let mut remaining = payload_size;
+        if remaining == 0 {
+            return Err(err("empty user data registered"));
+        }
+
+        let itu_t_t35_country_code = r.name("itu_t_t35_country_code").u8(8)?;
+        remaining -= 1;
+        let mut itu_t_t35_country_code_extension_byte = None;
+        if itu_t_t35_country_code == 0xff {
+            if remaining == 0 {
+                return Err(err("missing country code extension byte"));
+            }
+            itu_t_t35_country_code_extension_byte = Some(
+                r.name("itu_t_t35_country_code_extension_byte").u8(8)?);
+            remaining -= 1;
+        }
+
+        let mut itu_t_t35_payload_byte = Vec::new();
+        for _ in 0..remaining {
+            itu_t_t35_payload_byte.push(
+                r.name("itu_t_t35_payload_byte").b()?);
+        }
+
+        Ok(UserDataRegistered {
+            itu_t_t35_country_code,
+            itu_t_t35_country_code_extension_byte,
+            itu_t_t35_payload_byte,
+        })
+    }
+}
+
+impl UserDataUnregistered {
+    /// Parses user_data_unregistered() as specified in D.1.6.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, payload_size: u32) ->
+                             Result<UserDataUnregistered> {
+        if payload_size < 16 {
+            return Err(err("user data unregistered shorter than UUID"));
+        }
+
+        let mut uuid_iso_iec_11578 = [0; 16];
+        for byte in uuid_iso_iec_11578.iter_mut() {
+            *byte = r.name("uuid_iso_iec_11578").b()?;
+        }
+
+        let mut user_data_payload_byte = Vec::new();
+        for _ in 16..payload_size {
+            user_data_payload_byte.push(
+                r.name("user_data_payload_byte").b()?);
+        }
+
+        Ok(UserDataUnregistered {
+            uuid_iso_iec_11578,
+            user_data_payload_byte,
+        })
+    }
+}
+
+impl RecoveryPoint {
+    /// Parses recovery_point() as specified in D.1.8.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) -> Result<RecoveryPoint> {
+        let recovery_frame_cnt = r.name("recovery_frame_cnt").ue32()?;
+        let exact_match_flag = r.name("exact_match_flag").flag()?;
+        let broken_link_flag = r.name("broken_link_flag").flag()?;
+        let changing_slice_group_idc =
+            r.name("changing_slice_group_idc").u8(2)?;
+
+        Ok(RecoveryPoint {
+            recovery_frame_cnt,
+            exact_match_flag,
+            broken_link_flag,
+            changing_slice_group_idc,
+        })
+    }
+}
+
+impl MasteringDisplayColourVolume {
+    /// Parses mastering_display_colour_volume() as specified in D.1.29.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) ->
+                             Result<MasteringDisplayColourVolume> {
+        let mut display_primaries_x = [0; 3];
+        let mut display_primaries_y = [0; 3];
+        for c in 0..3 {
+            display_primaries_x[c] =
+                r.name("display_primaries_x").u32(16)? as u16;
+            display_primaries_y[c] =
+                r.name("display_primaries_y").u32(16)? as u16;
+        }
+        let white_point_x = r.name("white_point_x").u32(16)? as u16;
+        let white_point_y = r.name("white_point_y").u32(16)? as u16;
+        let max_display_mastering_luminance =
+            r.name("max_display_mastering_luminance").u32(32)?;
+        let min_display_mastering_luminance =
+            r.name("min_display_mastering_luminance").u32(32)?;
+
+        Ok(MasteringDisplayColourVolume {
+            display_primaries_x,
+            display_primaries_y,
+            white_point_x,
+            white_point_y,
+            max_display_mastering_luminance,
+            min_display_mastering_luminance,
+        })
+    }
+}
+
+impl ContentLightLevelInfo {
+    /// Parses content_light_level_info() as specified in D.1.31.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) ->
+                             Result<ContentLightLevelInfo> {
+        let max_content_light_level =
+            r.name("max_content_light_level").u32(16)? as u16;
+        let max_pic_average_light_level =
+            r.name("max_pic_average_light_level").u32(16)? as u16;
+
+        Ok(ContentLightLevelInfo {
+            max_content_light_level,
+            max_pic_average_light_level,
+        })
+    }
+}
+
+impl SeiMessage {
+    /// Parses all sei_message() in a sei_rbsp() as specified in 7.3.2.3.
+    ///
+    /// The buffering period and pic timing messages depend on the SPS.
+    /// Buffering period refers to a SPS by id, pic timing uses the SPS
+    /// activated by a preceding buffering period in the same unit,
+    /// the active SPS or the most recently received SPS, in that order.
+    /// When the SPS is missing or does not describe the message, the
+    /// message is returned as unknown payload.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>,
+                             parameter_sets: &ParameterSets) ->
+                             Result<Vec<SeiMessage>> {
+        let mut messages = Vec::new();
+        let mut sps = parameter_sets.active_sps()
+            .or_else(|| parameter_sets.latest_sps());
+
+        loop {
+            let payload_type = sei_value(r)?;
+            let payload_size = sei_value(r)?;
+
+            let mut bytes = Vec::with_capacity(payload_size as usize);
+            for _ in 0..payload_size {
+                bytes.push(r.name("sei_payload_byte").b()?);
+            }
+
+            let mut reader = BitReader::new_rbsp(Cursor::new(&bytes));
+            let payload = match payload_type {
+                BUFFERING_PERIOD => {
+                    match BufferingPeriod::parse(&mut reader,
+                                                 parameter_sets) {
+                        Ok(bp) => {
+                            sps = parameter_sets.sps(bp.seq_parameter_set_id);
+                            SeiPayload::BufferingPeriod(bp)
+                        },
+                        Err(_) => SeiPayload::Unknown(bytes.clone()),
+                    }
+                },
+                PIC_TIMING => {
+                    match sps.map(|sps| PicTiming::parse(&mut reader, sps)) {
+                        Some(Ok(pt)) => SeiPayload::PicTiming(pt),
+                        _ => SeiPayload::Unknown(bytes.clone()),
+                    }
+                },
+                USER_DATA_REGISTERED_ITU_T_T35 =>
+                    SeiPayload::UserDataRegistered(
+                        UserDataRegistered::parse(&mut reader, payload_size)?),
+                USER_DATA_UNREGISTERED =>
+                    SeiPayload::UserDataUnregistered(
+                        UserDataUnregistered::parse(&mut reader,
+                                                    payload_size)?),
+                RECOVERY_POINT =>
+                    SeiPayload::RecoveryPoint(
+                        RecoveryPoint::parse(&mut reader)?),
+                MASTERING_DISPLAY_COLOUR_VOLUME =>
+                    SeiPayload::MasteringDisplayColourVolume(
+                        MasteringDisplayColourVolume::parse(&mut reader)?),
+                CONTENT_LIGHT_LEVEL_INFO =>
+                    SeiPayload::ContentLightLevelInfo(
+                        ContentLightLevelInfo::parse(&mut reader)?),
+                _ => SeiPayload::Unknown(bytes.clone()),
+            };
+
+            messages.push(SeiMessage {
+                payload_type,
+                payload_size,
+                payload,
+            });
+
+            if !r.more_rbsp_data()? {
+                break;
+            }
+        }
+        r.rbsp_trailing_bits()?;
+
+        Ok(messages)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use nalunit::{NalUnit, NalPayload};
+    use super::*;
+
+    /* Baseline 320x240 with NAL HRD, all lengths 24 bits and
+     * pic_struct_present_flag set */
+    const SPS: [u8; 38] = [
+        0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf7, 0xfe,
+        0x00, 0x08, 0x00, 0x06, 0xd4, 0x04, 0x04, 0x05,
+        0x00, 0x00, 0x03, 0x03, 0xe9, 0x00, 0x00, 0xea,
+        0x60, 0xe8, 0x60, 0x07, 0xd1, 0x00, 0x09, 0xc4,
+        0xaf, 0x7b, 0xe0, 0xf8, 0xb1, 0x75,
+    ];
+
+    fn parameter_sets() -> ParameterSets {
+        let mut reader = BitReader::new(Cursor::new(SPS));
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        let mut parameter_sets = ParameterSets::new();
+        parameter_sets.update(&NalPayload::SequenceParameterSet(sps));
+
+        parameter_sets
+    }
+
+    /* Parses `buf` with emulation prevention bytes as the payload of
+     * an SEI NAL unit */
+    fn parse(buf: &[u8], parameter_sets: &ParameterSets) ->
+             Result<Vec<SeiMessage>> {
+        let nal_unit = [&[0x06][..], buf].concat();
+        let (_, rbsp) = NalUnit::parse(&mut BitReader::new(&nal_unit[..]))
+            .unwrap();
+        let mut reader = BitReader::from_rbsp(&rbsp);
+
+        SeiMessage::parse(&mut reader, parameter_sets)
+    }
+
+    #[test]
+    fn parse_buffering_period_and_pic_timing() {
+        let buf = [
+            /* Buffering period, 7 bytes */
+            0x00, 0x07, 0x80, 0x00, 0x01, 0x00, 0x00, 0x03, 0x02, 0xc0,
+            /* Pic timing, 15 bytes */
+            0x01, 0x0f, 0x00, 0x00, 0x04, 0x00, 0x00, 0x06, 0x08, 0x04,
+            0x05, 0x28, 0x20, 0xff, 0xff, 0xfe, 0xc0,
+            0x80,
+        ];
+
+        let messages = parse(&buf, &parameter_sets()).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        match messages[0].payload {
+            SeiPayload::BufferingPeriod(ref bp) => {
+                assert_eq!(bp.seq_parameter_set_id, 0);
+                assert_eq!(bp.nal_initial_cpb_removal_delay, [2]);
+                assert_eq!(bp.nal_initial_cpb_removal_delay_offset, [5]);
+                assert!(bp.vcl_initial_cpb_removal_delay.is_empty());
+            },
+            _ => panic!("Not a buffering period"),
+        }
+        match messages[1].payload {
+            SeiPayload::PicTiming(ref pt) => {
+                let ts = pt.clock_timestamps[0].as_ref().unwrap();
+                assert_eq!(pt.cpb_removal_delay, Some(4));
+                assert_eq!(pt.dpb_output_delay, Some(6));
+                assert_eq!(pt.pic_struct, Some(0));
+                assert_eq!(ts.n_frames, 5);
+                assert_eq!(ts.seconds_value, Some(10));
+                assert_eq!(ts.minutes_value, Some(2));
+                assert_eq!(ts.hours_value, Some(1));
+                assert_eq!(ts.time_offset, -3);
+            },
+            _ => panic!("Not a pic timing"),
+        }
+    }
+
+    #[test]
+    fn parse_user_data_unregistered() {
+        let buf = [
+            0x05, 0x14,
+            /* UUID */
+            0xdc, 0x45, 0xe9, 0xbd, 0xe6, 0xd9, 0x48, 0xb7,
+            0x96, 0x2c, 0xd8, 0x20, 0xd9, 0x23, 0xee, 0xef,
+            /* "x264" */
+            0x78, 0x32, 0x36, 0x34,
+            0x80,
+        ];
+
+        let messages = parse(&buf, &ParameterSets::new()).unwrap();
+
+        match messages[0].payload {
+            SeiPayload::UserDataUnregistered(ref user_data) => {
+                assert_eq!(user_data.uuid_iso_iec_11578[0], 0xdc);
+                assert_eq!(user_data.uuid_iso_iec_11578[15], 0xef);
+                assert_eq!(user_data.user_data_payload_byte, b"x264");
+            },
+            _ => panic!("Not user data unregistered"),
+        }
+    }
+
+    #[test]
+    fn parse_extended_payload_type_kept_raw() {
+        let buf = [
+            /* Payload type 255 + 45 = 300, size 2 */
+            0xff, 0x2d, 0x02, 0x12, 0x34,
+            /* Recovery point, 1 byte */
+            0x06, 0x01, 0x84,
+            0x80,
+        ];
+
+        let messages = parse(&buf, &ParameterSets::new()).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload_type, 300);
+        assert_eq!(messages[0].payload_size, 2);
+        match messages[0].payload {
+            SeiPayload::Unknown(ref bytes) => assert_eq!(*bytes, [0x12, 0x34]),
+            _ => panic!("Not unknown"),
+        }
+        match messages[1].payload {
+            SeiPayload::RecoveryPoint(ref rp) => {
+                assert_eq!(rp.recovery_frame_cnt, 0);
+                assert!(!rp.exact_match_flag);
+                assert!(!rp.broken_link_flag);
+                assert_eq!(rp.changing_slice_group_idc, 0);
+            },
+            _ => panic!("Not a recovery point"),
+        }
+    }
+
+    #[test]
+    fn parse_hdr_metadata() {
+        let buf = [
+            /* Mastering display colour volume, 24 bytes */
+            0x89, 0x18,
+            0x33, 0xc1, 0x86, 0xc4, 0x1d, 0x4c, 0x0b, 0xb8,
+            0x84, 0xd0, 0x3e, 0x80, 0x3d, 0x13, 0x40, 0x42,
+            0x00, 0x98, 0x96, 0x80, 0x00, 0x00, 0x03, 0x00,
+            0x32,
+            /* Content light level info, 4 bytes */
+            0x90, 0x04, 0x03, 0xe8, 0x01, 0x90,
+            0x80,
+        ];
+
+        let messages = parse(&buf, &ParameterSets::new()).unwrap();
+
+        match messages[0].payload {
+            SeiPayload::MasteringDisplayColourVolume(ref mdcv) => {
+                assert_eq!(mdcv.display_primaries_x, [13249, 7500, 34000]);
+                assert_eq!(mdcv.display_primaries_y, [34500, 3000, 16000]);
+                assert_eq!(mdcv.white_point_x, 15635);
+                assert_eq!(mdcv.white_point_y, 16450);
+                assert_eq!(mdcv.max_display_mastering_luminance, 10000000);
+                assert_eq!(mdcv.min_display_mastering_luminance, 50);
+            },
+            _ => panic!("Not mastering display colour volume"),
+        }
+        match messages[1].payload {
+            SeiPayload::ContentLightLevelInfo(ref cll) => {
+                assert_eq!(cll.max_content_light_level, 1000);
+                assert_eq!(cll.max_pic_average_light_level, 400);
+            },
+            _ => panic!("Not content light level info"),
+        }
+    }
+
+    #[test]
+    fn parse_pic_timing_without_sps() {
+        /* Buffering period of SPS 0 and pic timing */
+        let buf = [0x00, 0x01, 0x80, 0x01, 0x01, 0x00, 0x80];
+
+        let messages = parse(&buf, &ParameterSets::new()).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        match messages[0].payload {
+            SeiPayload::Unknown(ref bytes) => assert_eq!(*bytes, [0x80]),
+            _ => panic!("Not unknown"),
+        }
+        match messages[1].payload {
+            SeiPayload::Unknown(ref bytes) => assert_eq!(*bytes, [0x00]),
+            _ => panic!("Not unknown"),
+        }
+    }
+}
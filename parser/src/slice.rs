@@ -0,0 +1,688 @@
+use std::io::prelude::*;
+
+use bitreader::BitReader;
+use nalunit::NalUnit;
+use paramsets::ParameterSets;
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum SliceType {
+    P,
+    B,
+    I,
+    SP,
+    SI,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct PicNumModification {
+    pub modification_of_pic_nums_idc: u8,
+    pub abs_diff_pic_num_minus1: u32,
+    pub long_term_pic_num: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct RefPicListModification {
+    pub ref_pic_list_modification_flag_l0: bool,
+    pub modifications_l0: Vec<PicNumModification>,
+    pub ref_pic_list_modification_flag_l1: bool,
+    pub modifications_l1: Vec<PicNumModification>,
+}
+
+/// Weights for one reference index. Weights and offsets that are not
+/// present are set to the inferred default values.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct PredWeight {
+    pub luma_weight_flag: bool,
+    pub luma_weight: i32,
+    pub luma_offset: i32,
+    pub chroma_weight_flag: bool,
+    pub chroma_weight: [i32; 2],
+    pub chroma_offset: [i32; 2],
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct PredWeightTable {
+    pub luma_log2_weight_denom: u8,
+    pub chroma_log2_weight_denom: u8,
+    pub l0: Vec<PredWeight>,
+    pub l1: Vec<PredWeight>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct MemoryManagementControlOperation {
+    pub memory_management_control_operation: u8,
+    pub difference_of_pic_nums_minus1: u32,
+    pub long_term_pic_num: u32,
+    pub long_term_frame_idx: u32,
+    pub max_long_term_frame_idx_plus1: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct DecRefPicMarking {
+    pub no_output_of_prior_pics_flag: bool,
+    pub long_term_reference_flag: bool,
+    pub adaptive_ref_pic_marking_mode_flag: bool,
+    pub operations: Vec<MemoryManagementControlOperation>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct SliceHeader {
+    pub first_mb_in_slice: u32,
+    pub slice_type: u8,
+    pub pic_parameter_set_id: u8,
+    pub colour_plane_id: u8,
+    pub frame_num: u32,
+    pub field_pic_flag: bool,
+    pub bottom_field_flag: bool,
+    pub idr_pic_id: u32,
+    pub pic_order_cnt_lsb: u32,
+    pub delta_pic_order_cnt_bottom: i64,
+    pub delta_pic_order_cnt: [i64; 2],
+    pub redundant_pic_cnt: u8,
+    pub direct_spatial_mv_pred_flag: bool,
+    pub num_ref_idx_active_override_flag: bool,
+    pub num_ref_idx_l0_active_minus1: u8,
+    pub num_ref_idx_l1_active_minus1: u8,
+    pub ref_pic_list_modification: RefPicListModification,
+    pub pred_weight_table: Option<PredWeightTable>,
+    pub dec_ref_pic_marking: Option<DecRefPicMarking>,
+    pub cabac_init_idc: u8,
+    pub slice_qp_delta: i8,
+    pub sp_for_switch_flag: bool,
+    pub slice_qs_delta: i8,
+    pub disable_deblocking_filter_idc: u8,
+    pub slice_alpha_c0_offset_div2: i8,
+    pub slice_beta_offset_div2: i8,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Slice();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+fn not_impl(text: &str) -> ParserError {
+    let unit = ParserUnit::Slice();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::NotImplemented(error)
+}
+
+impl SliceType {
+    /// Slice types 5 to 9 are the same as 0 to 4 but also tells that
+    /// all slices of the picture have the same type.
+    pub fn from_slice_type(slice_type: u8) -> SliceType {
+        match slice_type % 5 {
+            0 => SliceType::P,
+            1 => SliceType::B,
+            2 => SliceType::I,
+            3 => SliceType::SP,
+            _ => SliceType::SI,
+        }
+    }
+}
+
+fn pic_num_modifications<R: BufRead>(r: &mut BitReader<R>) ->
+                                     Result<Vec<PicNumModification>> {
+    let mut modifications = Vec::new();
+
+    loop {
+        let modification_of_pic_nums_idc =
+            r.name("modification_of_pic_nums_idc").ue8()?;
+        let mut abs_diff_pic_num_minus1 = 0;
+        let mut long_term_pic_num = 0;
+        match modification_of_pic_nums_idc {
+            0 | 1 => abs_diff_pic_num_minus1 =
+                r.name("abs_diff_pic_num_minus1").ue32()?,
+            2 => long_term_pic_num = r.name("long_term_pic_num").ue32()?,
+            3 => break,
+            _ => return Err(err("modification_of_pic_nums_idc larger than 3")),
+        }
+        modifications.push(PicNumModification {
+            modification_of_pic_nums_idc,
+            abs_diff_pic_num_minus1,
+            long_term_pic_num,
+        });
+    }
+
+    Ok(modifications)
+}
+
+impl RefPicListModification {
+    /// Parses ref_pic_list_modification() as specified in 7.3.3.1.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, slice_type: SliceType) ->
+                             Result<RefPicListModification> {
+        let mut ref_pic_list_modification_flag_l0 = false;
+        let mut modifications_l0 = Vec::new();
+        let mut ref_pic_list_modification_flag_l1 = false;
+        let mut modifications_l1 = Vec::new();
+
+        if slice_type != SliceType::I && slice_type != SliceType::SI {
+            ref_pic_list_modification_flag_l0 =
+                r.name("ref_pic_list_modification_flag_l0").flag()?;
+            if ref_pic_list_modification_flag_l0 {
+                modifications_l0 = pic_num_modifications(r)?;
+            }
+        }
+        if slice_type == SliceType::B {
+            ref_pic_list_modification_flag_l1 =
+                r.name("ref_pic_list_modification_flag_l1").flag()?;
+            if ref_pic_list_modification_flag_l1 {
+                modifications_l1 = pic_num_modifications(r)?;
+            }
+        }
+
+        Ok(RefPicListModification {
+            ref_pic_list_modification_flag_l0,
+            modifications_l0,
+            ref_pic_list_modification_flag_l1,
+            modifications_l1,
+        })
+    }
+}
+
+fn pred_weights<R: BufRead>(r: &mut BitReader<R>,
+                            num_ref_idx_active_minus1: u8,
+                            luma_log2_weight_denom: u8,
+                            chroma_log2_weight_denom: u8,
+                            chroma_array_type: u8) -> Result<Vec<PredWeight>> {
+    let mut weights = Vec::new();
+
+    for _ in 0..(num_ref_idx_active_minus1 as usize + 1) {
+        let luma_weight_flag = r.name("luma_weight_flag").flag()?;
+        let mut luma_weight = 1 << luma_log2_weight_denom;
+        let mut luma_offset = 0;
+        if luma_weight_flag {
+            /* Range -128 to 127 */
+            luma_weight = r.name("luma_weight").se8()? as i32;
+            luma_offset = r.name("luma_offset").se8()? as i32;
+        }
+
+        let mut chroma_weight_flag = false;
+        let default_chroma_weight = 1 << chroma_log2_weight_denom;
+        let mut chroma_weight = [default_chroma_weight; 2];
+        let mut chroma_offset = [0; 2];
+        if chroma_array_type != 0 {
+            chroma_weight_flag = r.name("chroma_weight_flag").flag()?;
+            if chroma_weight_flag {
+                for j in 0..2 {
+                    chroma_weight[j] = r.name("chroma_weight").se8()? as i32;
+                    chroma_offset[j] = r.name("chroma_offset").se8()? as i32;
+                }
+            }
+        }
+
+        weights.push(PredWeight {
+            luma_weight_flag,
+            luma_weight,
+            luma_offset,
+            chroma_weight_flag,
+            chroma_weight,
+            chroma_offset,
+        });
+    }
+
+    Ok(weights)
+}
+
+impl PredWeightTable {
+    /// Parses pred_weight_table() as specified in 7.3.3.2.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>,
+                             slice_type: SliceType,
+                             num_ref_idx_l0_active_minus1: u8,
+                             num_ref_idx_l1_active_minus1: u8,
+                             chroma_array_type: u8) ->
+                             Result<PredWeightTable> {
+        /* Range 0 - 7 */
+        let luma_log2_weight_denom = r.name("luma_log2_weight_denom").ue8()?;
+        if luma_log2_weight_denom > 7 {
+            return Err(err("luma_log2_weight_denom larger than 7"));
+        }
+        let mut chroma_log2_weight_denom = 0;
+        if chroma_array_type != 0 {
+            chroma_log2_weight_denom =
+                r.name("chroma_log2_weight_denom").ue8()?;
+            if chroma_log2_weight_denom > 7 {
+                return Err(err("chroma_log2_weight_denom larger than 7"));
+            }
+        }
+
+        let l0 = pred_weights(r, num_ref_idx_l0_active_minus1,
+                              luma_log2_weight_denom,
+                              chroma_log2_weight_denom,
+                              chroma_array_type)?;
+        let mut l1 = Vec::new();
+        if slice_type == SliceType::B {
+            l1 = pred_weights(r, num_ref_idx_l1_active_minus1,
+                              luma_log2_weight_denom,
+                              chroma_log2_weight_denom,
+                              chroma_array_type)?;
+        }
+
+        Ok(PredWeightTable {
+            luma_log2_weight_denom,
+            chroma_log2_weight_denom,
+            l0,
+            l1,
+        })
+    }
+}
+
+impl DecRefPicMarking {
+    /// Parses dec_ref_pic_marking() as specified in 7.3.3.3.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, idr_pic_flag: bool) ->
+                             Result<DecRefPicMarking> {
+        let mut no_output_of_prior_pics_flag = false;
+        let mut long_term_reference_flag = false;
+        let mut adaptive_ref_pic_marking_mode_flag = false;
+        let mut operations = Vec::new();
+
+        if idr_pic_flag {
+            no_output_of_prior_pics_flag =
+                r.name("no_output_of_prior_pics_flag").flag()?;
+            long_term_reference_flag =
+                r.name("long_term_reference_flag").flag()?;
+        }
+        else {
+            adaptive_ref_pic_marking_mode_flag =
+                r.name("adaptive_ref_pic_marking_mode_flag").flag()?;
+            if adaptive_ref_pic_marking_mode_flag {
+                loop {
+                    let memory_management_control_operation =
+                        r.name("memory_management_control_operation").ue8()?;
+                    let mut difference_of_pic_nums_minus1 = 0;
+                    let mut long_term_pic_num = 0;
+                    let mut long_term_frame_idx = 0;
+                    let mut max_long_term_frame_idx_plus1 = 0;
+                    match memory_management_control_operation {
+                        0 => break,
+                        1 => difference_of_pic_nums_minus1 =
+                            r.name("difference_of_pic_nums_minus1").ue32()?,
+                        2 => long_term_pic_num =
+                            r.name("long_term_pic_num").ue32()?,
+                        3 => {
+                            difference_of_pic_nums_minus1 =
+                                r.name("difference_of_pic_nums_minus1")
+                                    .ue32()?;
+                            long_term_frame_idx =
+                                r.name("long_term_frame_idx").ue32()?;
+                        },
+                        4 => max_long_term_frame_idx_plus1 =
+                            r.name("max_long_term_frame_idx_plus1").ue32()?,
+                        5 => {},
+                        6 => long_term_frame_idx =
+                            r.name("long_term_frame_idx").ue32()?,
+                        _ => return Err(err("memory_management_control_\
+                                             operation larger than 6")),
+                    }
+                    operations.push(MemoryManagementControlOperation {
+                        memory_management_control_operation,
+                        difference_of_pic_nums_minus1,
+                        long_term_pic_num,
+                        long_term_frame_idx,
+                        max_long_term_frame_idx_plus1,
+                    });
+                }
+            }
+        }
+
+        Ok(DecRefPicMarking {
+            no_output_of_prior_pics_flag,
+            long_term_reference_flag,
+            adaptive_ref_pic_marking_mode_flag,
+            operations,
+        })
+    }
+}
+
+impl SliceHeader {
+    /// Parses slice_header() as specified in 7.3.3. The PPS and SPS
+    /// referred to by the slice must be present in `parameter_sets`.
+    /// The reader is left at the start of slice_data().
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>, nal: &NalUnit,
+                             parameter_sets: &ParameterSets) ->
+                             Result<SliceHeader> {
+        let idr_pic_flag = nal.nal_unit_type == 5;
+
+        let first_mb_in_slice = r.name("first_mb_in_slice").ue32()?;
+        let slice_type = r.name("slice_type").ue8()?;
+        if slice_type > 9 {
+            return Err(err("slice_type larger than 9"));
+        }
+        let kind = SliceType::from_slice_type(slice_type);
+        if idr_pic_flag && kind != SliceType::I && kind != SliceType::SI {
+            return Err(err("IDR picture with non intra slice"));
+        }
+
+        let pic_parameter_set_id = r.name("pic_parameter_set_id").ue8()?;
+        let pps = match parameter_sets.pps(pic_parameter_set_id) {
+            Some(pps) => pps,
+            None => return Err(err("Referenced PPS not found")),
+        };
+        let sps = match parameter_sets.sps(pps.seq_parameter_set_id) {
+            Some(sps) => sps,
+            None => return Err(err("Referenced SPS not found")),
+        };
+
+        let mut colour_plane_id = 0;
+        if sps.separate_colour_plane_flag {
+            colour_plane_id = r.name("colour_plane_id").u8(2)?;
+        }
+
+        let frame_num =
+            r.name("frame_num").u32(sps.log2_max_frame_num_minus4 as u8 + 4)?;
+
+        let mut field_pic_flag = false;
+        let mut bottom_field_flag = false;
+        if !sps.frame_mbs_only_flag {
+            field_pic_flag = r.name("field_pic_flag").flag()?;
+            if field_pic_flag {
+                bottom_field_flag = r.name("bottom_field_flag").flag()?;
+            }
+        }
+
+        let mut idr_pic_id = 0;
+        if idr_pic_flag {
+            /* Range 0 - 65535 */
+            idr_pic_id = r.name("idr_pic_id").ue32()?;
+            if idr_pic_id > 65535 {
+                return Err(err("idr_pic_id larger than 65535"));
+            }
+        }
+
+        let mut pic_order_cnt_lsb = 0;
+        let mut delta_pic_order_cnt_bottom = 0;
+        let mut delta_pic_order_cnt = [0; 2];
+        if sps.pic_order_cnt_type == 0 {
+            pic_order_cnt_lsb =
+                r.name("pic_order_cnt_lsb")
+                    .u32(sps.log2_max_pic_order_cnt_lsb_minus4 + 4)?;
+            if pps.bottom_field_pic_order_in_frame_present_flag &&
+               !field_pic_flag {
+                delta_pic_order_cnt_bottom =
+                    r.name("delta_pic_order_cnt_bottom").se64()?;
+            }
+        }
+        if sps.pic_order_cnt_type == 1 &&
+           !sps.delta_pic_order_always_zero_flag {
+            delta_pic_order_cnt[0] = r.name("delta_pic_order_cnt").se64()?;
+            if pps.bottom_field_pic_order_in_frame_present_flag &&
+               !field_pic_flag {
+                delta_pic_order_cnt[1] = r.name("delta_pic_order_cnt").se64()?;
+            }
+        }
+
+        let mut redundant_pic_cnt = 0;
+        if pps.redundant_pic_cnt_present_flag {
+            /* Range 0 - 127 */
+            redundant_pic_cnt = r.name("redundant_pic_cnt").ue8()?;
+            if redundant_pic_cnt > 127 {
+                return Err(err("redundant_pic_cnt larger than 127"));
+            }
+        }
+
+        let mut direct_spatial_mv_pred_flag = false;
+        if kind == SliceType::B {
+            direct_spatial_mv_pred_flag =
+                r.name("direct_spatial_mv_pred_flag").flag()?;
+        }
+
+        let mut num_ref_idx_active_override_flag = false;
+        let mut num_ref_idx_l0_active_minus1 =
+            pps.num_ref_idx_l0_default_active_minus1;
+        let mut num_ref_idx_l1_active_minus1 =
+            pps.num_ref_idx_l1_default_active_minus1;
+        if kind == SliceType::P || kind == SliceType::SP ||
+           kind == SliceType::B {
+            num_ref_idx_active_override_flag =
+                r.name("num_ref_idx_active_override_flag").flag()?;
+            if num_ref_idx_active_override_flag {
+                num_ref_idx_l0_active_minus1 =
+                    r.name("num_ref_idx_l0_active_minus1").ue8()?;
+                if kind == SliceType::B {
+                    num_ref_idx_l1_active_minus1 =
+                        r.name("num_ref_idx_l1_active_minus1").ue8()?;
+                }
+            }
+        }
+        /* Range 0 - 15 for frames, 0 - 31 for fields */
+        let max_num_ref_idx_active_minus1 = if field_pic_flag { 31 } else { 15 };
+        if num_ref_idx_l0_active_minus1 > max_num_ref_idx_active_minus1 ||
+           num_ref_idx_l1_active_minus1 > max_num_ref_idx_active_minus1 {
+            return Err(err("num_ref_idx_active_minus1 too large"));
+        }
+
+        if nal.nal_unit_type == 20 || nal.nal_unit_type == 21 {
+            return Err(not_impl("ref_pic_list_mvc_modification"));
+        }
+        let ref_pic_list_modification =
+            RefPicListModification::parse(r, kind)?;
+
+        let mut pred_weight_table = None;
+        if (pps.weighted_pred_flag &&
+            (kind == SliceType::P || kind == SliceType::SP)) ||
+           (pps.weighted_bipred_idc == 1 && kind == SliceType::B) {
+            pred_weight_table = Some(PredWeightTable::parse(
+                r, kind,
+                num_ref_idx_l0_active_minus1,
+                num_ref_idx_l1_active_minus1,
+                sps.chroma_array_type())?);
+        }
+
+        let mut dec_ref_pic_marking = None;
+        if nal.nal_ref_idc != 0 {
+            dec_ref_pic_marking = Some(DecRefPicMarking::parse(r,
+                                                               idr_pic_flag)?);
+        }
+
+        let mut cabac_init_idc = 0;
+        if pps.entropy_coding_mode_flag &&
+           kind != SliceType::I && kind != SliceType::SI {
+            /* Range 0 - 2 */
+            cabac_init_idc = r.name("cabac_init_idc").ue8()?;
+            if cabac_init_idc > 2 {
+                return Err(err("cabac_init_idc larger than 2"));
+            }
+        }
+
+        /* Range -(26 + QpBdOffset) to 25 */
+        let slice_qp_delta = r.name("slice_qp_delta").se8()?;
+
+        let mut sp_for_switch_flag = false;
+        let mut slice_qs_delta = 0;
+        if kind == SliceType::SP || kind == SliceType::SI {
+            if kind == SliceType::SP {
+                sp_for_switch_flag = r.name("sp_for_switch_flag").flag()?;
+            }
+            slice_qs_delta = r.name("slice_qs_delta").se8()?;
+        }
+
+        let mut disable_deblocking_filter_idc = 0;
+        let mut slice_alpha_c0_offset_div2 = 0;
+        let mut slice_beta_offset_div2 = 0;
+        if pps.deblocking_filter_control_present_flag {
+            /* Range 0 - 2 */
+            disable_deblocking_filter_idc =
+                r.name("disable_deblocking_filter_idc").ue8()?;
+            if disable_deblocking_filter_idc > 2 {
+                return Err(err("disable_deblocking_filter_idc larger than 2"));
+            }
+            if disable_deblocking_filter_idc != 1 {
+                /* Range -6 to 6 */
+                slice_alpha_c0_offset_div2 =
+                    r.name("slice_alpha_c0_offset_div2").se8()?;
+                slice_beta_offset_div2 =
+                    r.name("slice_beta_offset_div2").se8()?;
+            }
+        }
+
+        /* Slice groups are rejected when parsing the PPS, so there is
+         * no slice_group_change_cycle to read. */
+        if pps.num_slice_groups_minus1 > 0 {
+            return Err(not_impl("Slice groups not impl"));
+        }
+
+        Ok(SliceHeader {
+            first_mb_in_slice,
+            slice_type,
+            pic_parameter_set_id,
+            colour_plane_id,
+            frame_num,
+            field_pic_flag,
+            bottom_field_flag,
+            idr_pic_id,
+            pic_order_cnt_lsb,
+            delta_pic_order_cnt_bottom,
+            delta_pic_order_cnt,
+            redundant_pic_cnt,
+            direct_spatial_mv_pred_flag,
+            num_ref_idx_active_override_flag,
+            num_ref_idx_l0_active_minus1,
+            num_ref_idx_l1_active_minus1,
+            ref_pic_list_modification,
+            pred_weight_table,
+            dec_ref_pic_marking,
+            cabac_init_idc,
+            slice_qp_delta,
+            sp_for_switch_flag,
+            slice_qs_delta,
+            disable_deblocking_filter_idc,
+            slice_alpha_c0_offset_div2,
+            slice_beta_offset_div2,
+        })
+    }
+
+    pub fn slice_type(&self) -> SliceType {
+        SliceType::from_slice_type(self.slice_type)
+    }
+
+    /// The slice contains a memory_management_control_operation equal
+    /// to 5, marking all reference pictures as unused.
+    pub fn has_mmco5(&self) -> bool {
+        self.dec_ref_pic_marking.as_ref().is_some_and(|marking| {
+            marking.operations.iter()
+                .any(|op| op.memory_management_control_operation == 5)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use nalunit::NalPayload;
+    use sps::SequenceParameterSet;
+    use pps::PictureParameterSet;
+    use super::*;
+
+    /* Baseline 320x240, 4 bit frame_num and 6 bit pic_order_cnt_lsb */
+    const SPS: [u8; 7] = [
+        0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2,
+    ];
+
+    /* CAVLC, deblocking filter control present */
+    const PPS: [u8; 3] = [
+        0xce, 0x3c, 0x80,
+    ];
+
+    fn parameter_sets() -> ParameterSets {
+        let mut parameter_sets = ParameterSets::new();
+        let mut reader = BitReader::new(Cursor::new(SPS));
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        parameter_sets.update(&NalPayload::SequenceParameterSet(sps));
+        let mut reader = BitReader::from_rbsp(&PPS);
+        let pps = PictureParameterSet::parse(&mut reader,
+                                             &parameter_sets).unwrap();
+        parameter_sets.update(&NalPayload::PictureParameterSet(pps));
+
+        parameter_sets
+    }
+
+    fn nal(nal_ref_idc: u8, nal_unit_type: u8) -> NalUnit {
+        NalUnit {
+            offset: 0,
+            nal_ref_idc,
+            nal_unit_type,
+            svc_extension_flag: false,
+            avc_3d_extension_flag: false,
+        }
+    }
+
+    #[test]
+    fn parse_idr() {
+        let buf = [0x88, 0x82, 0x00, 0x2d, 0x3f, 0xff];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let header = SliceHeader::parse(&mut reader, &nal(3, 5),
+                                        &parameter_sets()).unwrap();
+        let marking = header.dec_ref_pic_marking.unwrap();
+
+        assert_eq!(header.slice_type, 7);
+        assert_eq!(SliceType::from_slice_type(header.slice_type),
+                   SliceType::I);
+        assert_eq!(header.idr_pic_id, 1);
+        assert!(!marking.no_output_of_prior_pics_flag);
+        assert!(!marking.long_term_reference_flag);
+        assert_eq!(header.slice_qp_delta, -2);
+        assert_eq!(header.disable_deblocking_filter_idc, 0);
+        assert_eq!(header.slice_alpha_c0_offset_div2, 1);
+        assert_eq!(header.slice_beta_offset_div2, -1);
+        /* Slice data is next */
+        assert_eq!(reader.u64(8).unwrap(), 0xff);
+    }
+
+    #[test]
+    fn parse_p() {
+        let buf = [
+            0x16, 0x69, 0x8d, 0x7d, 0xa2, 0x53, 0x34, 0xcb,
+            0xff,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let header = SliceHeader::parse(&mut reader, &nal(2, 1),
+                                        &parameter_sets()).unwrap();
+        let modification = &header.ref_pic_list_modification;
+        let marking = header.dec_ref_pic_marking.as_ref().unwrap();
+
+        assert_eq!(header.first_mb_in_slice, 10);
+        assert_eq!(header.slice_type(), SliceType::P);
+        assert_eq!(header.frame_num, 3);
+        assert_eq!(header.pic_order_cnt_lsb, 6);
+        assert!(header.num_ref_idx_active_override_flag);
+        assert_eq!(header.num_ref_idx_l0_active_minus1, 2);
+        assert!(modification.ref_pic_list_modification_flag_l0);
+        assert_eq!(modification.modifications_l0.len(), 2);
+        assert_eq!(modification.modifications_l0[1].long_term_pic_num, 1);
+        assert!(marking.adaptive_ref_pic_marking_mode_flag);
+        assert_eq!(marking.operations.len(), 2);
+        assert_eq!(marking.operations[0].difference_of_pic_nums_minus1, 2);
+        assert_eq!(marking.operations[1].memory_management_control_operation,
+                   5);
+        assert_eq!(header.slice_qp_delta, 3);
+        assert_eq!(header.disable_deblocking_filter_idc, 1);
+        assert_eq!(reader.u64(8).unwrap(), 0xff);
+    }
+
+    #[test]
+    fn parse_missing_pps() {
+        let buf = [0x88, 0x82, 0x00, 0x2d, 0x3f, 0xff];
+        let mut reader = BitReader::new(Cursor::new(buf));
+
+        let res = SliceHeader::parse(&mut reader, &nal(3, 5),
+                                     &ParameterSets::new());
+
+        assert!(res.is_err());
+    }
+}
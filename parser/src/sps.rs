@@ -1,96 +1,561 @@
 use std::io::prelude::*;
 
 use bitreader::BitReader;
+use bitwriter::BitWriter;
+use scaling::{ScalingLists, ScalingMatrix};
 use super::*;
 
-#[derive(Debug)]
-pub struct SequenceScalingMatrixEntry {
-    seq_scaling_list_present_flag: bool,
-    delta_scale: i32,
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct HrdParameters {
+    pub cpb_cnt_minus1: u8,
+    pub bit_rate_scale: u8,
+    pub cpb_size_scale: u8,
+    pub bit_rate_value_minus1: Vec<u32>,
+    pub cpb_size_value_minus1: Vec<u32>,
+    pub cbr_flag: Vec<bool>,
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    pub cpb_removal_delay_length_minus1: u8,
+    pub dpb_output_delay_length_minus1: u8,
+    pub time_offset_length: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct VideoUsabilityInformation {
-    aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_idc: u8,
+    pub sar_width: u16,
+    pub sar_height: u16,
+    pub overscan_info_present_flag: bool,
+    pub overscan_appropriate_flag: bool,
+    pub video_signal_type_present_flag: bool,
+    pub video_format: u8,
+    pub video_full_range_flag: bool,
+    pub colour_description_present_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub chroma_loc_info_present_flag: bool,
+    pub chroma_sample_loc_type_top_field: u8,
+    pub chroma_sample_loc_type_bottom_field: u8,
+    pub timing_info_present_flag: bool,
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate_flag: bool,
+    pub nal_hrd_parameters_present_flag: bool,
+    pub nal_hrd_parameters: Option<HrdParameters>,
+    pub vcl_hrd_parameters_present_flag: bool,
+    pub vcl_hrd_parameters: Option<HrdParameters>,
+    pub low_delay_hrd_flag: bool,
+    pub pic_struct_present_flag: bool,
+    pub bitstream_restriction_flag: bool,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub max_bytes_per_pic_denom: u8,
+    pub max_bits_per_mb_denom: u8,
+    pub log2_max_mv_length_horizontal: u8,
+    pub log2_max_mv_length_vertical: u8,
+    pub max_num_reorder_frames: u8,
+    pub max_dec_frame_buffering: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
 pub struct SequenceParameterSet {
-    profile_idc: u8,
-    constraint_set0_flag: bool,
-    constraint_set1_flag: bool,
-    constraint_set2_flag: bool,
-    constraint_set3_flag: bool,
-    constraint_set4_flag: bool,
-    constraint_set5_flag: bool,
-    level_idc: u8,
-    seq_parameter_set_id: u8,
-    chroma_format_idc: u8,
-    separate_colour_plane_flag: bool,
-    bit_depth_luma_minus8: u8,
-    bit_depth_chroma_minus8: u8,
-    qpprime_y_zero_transform_bypass_flag: bool,
-    seq_scaling_matrix_present_flag: bool,
-    //scaling_list: Vec<SequenceScalingMatrixEntry>,
-    log2_max_frame_num_minus4: u32,
-    pic_order_cnt_type: u8,
-    log2_max_pic_order_cnt_lsb_minus4: u8,
-    delta_pic_order_always_zero_flag: bool,
-    offset_for_non_ref_pic: i64,
-    offset_for_top_to_bottom_field: i64,
-    num_ref_frames_in_pic_order_cnt_cycle: u8,
-    offset_for_ref_frame: Vec<i64>,
-    max_num_ref_frames: u8,
-    gaps_in_frame_num_value_allowed_flag: bool,
-    pic_width_in_mbs_minus1: u32,
-    pic_height_in_map_units_minus1: u32,
-    frame_mbs_only_flag: bool,
-    mb_adaptive_frame_field_flag: bool,
-    direct_8x8_inference_flag: bool,
-    frame_cropping_flag: bool,
-    frame_crop_left_offset: u32,
-    frame_crop_right_offset: u32,
-    frame_crop_top_offset: u32,
-    frame_crop_bottom_offset: u32,
-    vui_parameters_present_flag: bool,
-    vui_parameters: Option<VideoUsabilityInformation>,
+    pub profile_idc: u8,
+    pub constraint_set0_flag: bool,
+    pub constraint_set1_flag: bool,
+    pub constraint_set2_flag: bool,
+    pub constraint_set3_flag: bool,
+    pub constraint_set4_flag: bool,
+    pub constraint_set5_flag: bool,
+    pub level_idc: u8,
+    pub seq_parameter_set_id: u8,
+    pub chroma_format_idc: u8,
+    pub separate_colour_plane_flag: bool,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub qpprime_y_zero_transform_bypass_flag: bool,
+    pub seq_scaling_matrix_present_flag: bool,
+    pub seq_scaling_lists: Option<ScalingLists>,
+    /* Effective lists, Flat_4x4_16 and Flat_8x8_16 when not present */
+    pub scaling_matrix: ScalingMatrix,
+    pub log2_max_frame_num_minus4: u32,
+    pub pic_order_cnt_type: u8,
+    pub log2_max_pic_order_cnt_lsb_minus4: u8,
+    pub delta_pic_order_always_zero_flag: bool,
+    pub offset_for_non_ref_pic: i64,
+    pub offset_for_top_to_bottom_field: i64,
+    pub num_ref_frames_in_pic_order_cnt_cycle: u8,
+    pub offset_for_ref_frame: Vec<i64>,
+    pub max_num_ref_frames: u8,
+    pub gaps_in_frame_num_value_allowed_flag: bool,
+    pub pic_width_in_mbs_minus1: u32,
+    pub pic_height_in_map_units_minus1: u32,
+    pub frame_mbs_only_flag: bool,
+    pub mb_adaptive_frame_field_flag: bool,
+    pub direct_8x8_inference_flag: bool,
+    pub frame_cropping_flag: bool,
+    pub frame_crop_left_offset: u32,
+    pub frame_crop_right_offset: u32,
+    pub frame_crop_top_offset: u32,
+    pub frame_crop_bottom_offset: u32,
+    pub vui_parameters_present_flag: bool,
+    pub vui_parameters: Option<VideoUsabilityInformation>,
 }
 
 fn err(text: &str) -> ParserError {
     let unit = ParserUnit::Sps();
     let description = String::from(text);
-    let error = ParserUnitError { unit, description };
+    let error = ParserUnitError { unit, description, location: None };
 
     ParserError::InvalidStream(error)
 }
 
+/*
 fn not_impl(text: &str) -> ParserError {
     let unit = ParserUnit::Sps();
     let description = String::from(text);
-    let error = ParserUnitError { unit, description };
+    let error = ParserUnitError { unit, description, location: None };
 
     ParserError::NotImplemented(error)
 }
+*/
 
+/* Table E-1, sample aspect ratio given by sar_width and sar_height */
+const EXTENDED_SAR: u8 = 255;
+
+/* Table E-1, sar_width and sar_height of aspect_ratio_idc 1 to 16 */
+const SAR: [(u16, u16); 16] = [
+    (1, 1), (12, 11), (10, 11), (16, 11), (40, 33), (24, 11), (20, 11),
+    (32, 11), (80, 33), (18, 11), (15, 11), (64, 33), (160, 99), (4, 3),
+    (3, 2), (2, 1),
+];
+
+impl HrdParameters {
+    /// Parses hrd_parameters() as specified in E.1.2.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) -> Result<HrdParameters> {
+        let cpb_cnt_minus1 = r.name("cpb_cnt_minus1").ue8()?;
+        if cpb_cnt_minus1 > 31 {
+            return Err(err("cpb_cnt_minus1 larger than 31"));
+        }
+        let bit_rate_scale = r.name("bit_rate_scale").u8(4)?;
+        let cpb_size_scale = r.name("cpb_size_scale").u8(4)?;
+
+        let mut bit_rate_value_minus1 = Vec::new();
+        let mut cpb_size_value_minus1 = Vec::new();
+        let mut cbr_flag = Vec::new();
+        for _ in 0..(cpb_cnt_minus1 + 1) {
+            /* Range 0 to 2^32 - 2 */
+            bit_rate_value_minus1.push(
+                r.name("bit_rate_value_minus1").ue32()?);
+            cpb_size_value_minus1.push(
+                r.name("cpb_size_value_minus1").ue32()?);
+            cbr_flag.push(r.name("cbr_flag").flag()?);
+        }
+
+        let initial_cpb_removal_delay_length_minus1 =
+            r.name("initial_cpb_removal_delay_length_minus1").u8(5)?;
+        let cpb_removal_delay_length_minus1 =
+            r.name("cpb_removal_delay_length_minus1").u8(5)?;
+        let dpb_output_delay_length_minus1 =
+            r.name("dpb_output_delay_length_minus1").u8(5)?;
+        let time_offset_length = r.name("time_offset_length").u8(5)?;
+
+        Ok(HrdParameters {
+            cpb_cnt_minus1,
+            bit_rate_scale,
+            cpb_size_scale,
+            bit_rate_value_minus1,
+            cpb_size_value_minus1,
+            cbr_flag,
+            initial_cpb_removal_delay_length_minus1,
+            cpb_removal_delay_length_minus1,
+            dpb_output_delay_length_minus1,
+            time_offset_length,
+        })
+    }
+
+    /// Writes hrd_parameters(), the inverse of `parse`.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        let count = self.cpb_cnt_minus1 as usize + 1;
+        if self.bit_rate_value_minus1.len() != count ||
+           self.cpb_size_value_minus1.len() != count ||
+           self.cbr_flag.len() != count {
+            return Err(err("HRD values do not match cpb_cnt_minus1"));
+        }
+
+        w.ue8(self.cpb_cnt_minus1)?;
+        w.u8(4, self.bit_rate_scale)?;
+        w.u8(4, self.cpb_size_scale)?;
+        for i in 0..count {
+            w.ue32(self.bit_rate_value_minus1[i])?;
+            w.ue32(self.cpb_size_value_minus1[i])?;
+            w.flag(self.cbr_flag[i])?;
+        }
+        w.u8(5, self.initial_cpb_removal_delay_length_minus1)?;
+        w.u8(5, self.cpb_removal_delay_length_minus1)?;
+        w.u8(5, self.dpb_output_delay_length_minus1)?;
+        w.u8(5, self.time_offset_length)?;
+
+        Ok(())
+    }
+}
+
+impl VideoUsabilityInformation {
+    /// Parses vui_parameters() as specified in E.1.1. Syntax elements
+    /// that are not present are set to the values inferred by E.2.1.
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) ->
+                             Result<VideoUsabilityInformation> {
+        let aspect_ratio_info_present_flag =
+            r.name("aspect_ratio_info_present_flag").flag()?;
+        /* 0 => Unspecified */
+        let mut aspect_ratio_idc = 0;
+        let mut sar_width = 0;
+        let mut sar_height = 0;
+        if aspect_ratio_info_present_flag {
+            aspect_ratio_idc = r.name("aspect_ratio_idc").u8(8)?;
+            if aspect_ratio_idc == EXTENDED_SAR {
+                sar_width = r.name("sar_width").u32(16)? as u16;
+                sar_height = r.name("sar_height").u32(16)? as u16;
+            }
+        }
+
+        let overscan_info_present_flag =
+            r.name("overscan_info_present_flag").flag()?;
+        let mut overscan_appropriate_flag = false;
+        if overscan_info_present_flag {
+            overscan_appropriate_flag =
+                r.name("overscan_appropriate_flag").flag()?;
+        }
+
+        let video_signal_type_present_flag =
+            r.name("video_signal_type_present_flag").flag()?;
+        /* 5 => Unspecified video format */
+        let mut video_format = 5;
+        let mut video_full_range_flag = false;
+        let mut colour_description_present_flag = false;
+        /* 2 => Unspecified */
+        let mut colour_primaries = 2;
+        let mut transfer_characteristics = 2;
+        let mut matrix_coefficients = 2;
+        if video_signal_type_present_flag {
+            video_format = r.name("video_format").u8(3)?;
+            video_full_range_flag = r.name("video_full_range_flag").flag()?;
+            colour_description_present_flag =
+                r.name("colour_description_present_flag").flag()?;
+            if colour_description_present_flag {
+                colour_primaries = r.name("colour_primaries").u8(8)?;
+                transfer_characteristics =
+                    r.name("transfer_characteristics").u8(8)?;
+                matrix_coefficients = r.name("matrix_coefficients").u8(8)?;
+            }
+        }
+
+        let chroma_loc_info_present_flag =
+            r.name("chroma_loc_info_present_flag").flag()?;
+        let mut chroma_sample_loc_type_top_field = 0;
+        let mut chroma_sample_loc_type_bottom_field = 0;
+        if chroma_loc_info_present_flag {
+            /* Range 0 - 5 */
+            chroma_sample_loc_type_top_field =
+                r.name("chroma_sample_loc_type_top_field").ue8()?;
+            chroma_sample_loc_type_bottom_field =
+                r.name("chroma_sample_loc_type_bottom_field").ue8()?;
+            if chroma_sample_loc_type_top_field > 5 ||
+               chroma_sample_loc_type_bottom_field > 5 {
+                return Err(err("chroma_sample_loc_type larger than 5"));
+            }
+        }
+
+        let timing_info_present_flag =
+            r.name("timing_info_present_flag").flag()?;
+        let mut num_units_in_tick = 0;
+        let mut time_scale = 0;
+        let mut fixed_frame_rate_flag = false;
+        if timing_info_present_flag {
+            num_units_in_tick = r.name("num_units_in_tick").u32(32)?;
+            if num_units_in_tick == 0 {
+                return Err(err("num_units_in_tick is 0"));
+            }
+            time_scale = r.name("time_scale").u32(32)?;
+            if time_scale == 0 {
+                return Err(err("time_scale is 0"));
+            }
+            fixed_frame_rate_flag = r.name("fixed_frame_rate_flag").flag()?;
+        }
+
+        let nal_hrd_parameters_present_flag =
+            r.name("nal_hrd_parameters_present_flag").flag()?;
+        let mut nal_hrd_parameters = None;
+        if nal_hrd_parameters_present_flag {
+            nal_hrd_parameters = Some(HrdParameters::parse(r)?);
+        }
+
+        let vcl_hrd_parameters_present_flag =
+            r.name("vcl_hrd_parameters_present_flag").flag()?;
+        let mut vcl_hrd_parameters = None;
+        if vcl_hrd_parameters_present_flag {
+            vcl_hrd_parameters = Some(HrdParameters::parse(r)?);
+        }
+
+        let mut low_delay_hrd_flag = false;
+        if nal_hrd_parameters_present_flag ||
+           vcl_hrd_parameters_present_flag {
+            low_delay_hrd_flag = r.name("low_delay_hrd_flag").flag()?;
+        }
+
+        let pic_struct_present_flag =
+            r.name("pic_struct_present_flag").flag()?;
+
+        let bitstream_restriction_flag =
+            r.name("bitstream_restriction_flag").flag()?;
+        let mut motion_vectors_over_pic_boundaries_flag = true;
+        let mut max_bytes_per_pic_denom = 2;
+        let mut max_bits_per_mb_denom = 1;
+        let mut log2_max_mv_length_horizontal = 15;
+        let mut log2_max_mv_length_vertical = 15;
+        /* Should be inferred to MaxDpbFrames, use the upper bound of
+         * MaxDpbFrames since level limits are not known here. */
+        let mut max_num_reorder_frames = 16;
+        let mut max_dec_frame_buffering = 16;
+        if bitstream_restriction_flag {
+            motion_vectors_over_pic_boundaries_flag =
+                r.name("motion_vectors_over_pic_boundaries_flag").flag()?;
+            /* Range 0 - 16 */
+            max_bytes_per_pic_denom = r.name("max_bytes_per_pic_denom").ue8()?;
+            if max_bytes_per_pic_denom > 16 {
+                return Err(err("max_bytes_per_pic_denom larger than 16"));
+            }
+            /* Range 0 - 16 */
+            max_bits_per_mb_denom = r.name("max_bits_per_mb_denom").ue8()?;
+            if max_bits_per_mb_denom > 16 {
+                return Err(err("max_bits_per_mb_denom larger than 16"));
+            }
+            /* Range 0 - 15 */
+            log2_max_mv_length_horizontal =
+                r.name("log2_max_mv_length_horizontal").ue8()?;
+            log2_max_mv_length_vertical =
+                r.name("log2_max_mv_length_vertical").ue8()?;
+            if log2_max_mv_length_horizontal > 15 ||
+               log2_max_mv_length_vertical > 15 {
+                return Err(err("log2_max_mv_length larger than 15"));
+            }
+            max_num_reorder_frames = r.name("max_num_reorder_frames").ue8()?;
+            max_dec_frame_buffering = r.name("max_dec_frame_buffering").ue8()?;
+            if max_num_reorder_frames > max_dec_frame_buffering {
+                return Err(err("max_num_reorder_frames larger than \
+                                max_dec_frame_buffering"));
+            }
+        }
+
+        Ok(VideoUsabilityInformation {
+            aspect_ratio_info_present_flag,
+            aspect_ratio_idc,
+            sar_width,
+            sar_height,
+            overscan_info_present_flag,
+            overscan_appropriate_flag,
+            video_signal_type_present_flag,
+            video_format,
+            video_full_range_flag,
+            colour_description_present_flag,
+            colour_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+            chroma_loc_info_present_flag,
+            chroma_sample_loc_type_top_field,
+            chroma_sample_loc_type_bottom_field,
+            timing_info_present_flag,
+            num_units_in_tick,
+            time_scale,
+            fixed_frame_rate_flag,
+            nal_hrd_parameters_present_flag,
+            nal_hrd_parameters,
+            vcl_hrd_parameters_present_flag,
+            vcl_hrd_parameters,
+            low_delay_hrd_flag,
+            pic_struct_present_flag,
+            bitstream_restriction_flag,
+            motion_vectors_over_pic_boundaries_flag,
+            max_bytes_per_pic_denom,
+            max_bits_per_mb_denom,
+            log2_max_mv_length_horizontal,
+            log2_max_mv_length_vertical,
+            max_num_reorder_frames,
+            max_dec_frame_buffering,
+        })
+    }
+
+    /// Writes vui_parameters(), the inverse of `parse`. Syntax elements
+    /// that are not present are not written.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        w.flag(self.aspect_ratio_info_present_flag)?;
+        if self.aspect_ratio_info_present_flag {
+            w.u8(8, self.aspect_ratio_idc)?;
+            if self.aspect_ratio_idc == EXTENDED_SAR {
+                w.u32(16, self.sar_width as u32)?;
+                w.u32(16, self.sar_height as u32)?;
+            }
+        }
+
+        w.flag(self.overscan_info_present_flag)?;
+        if self.overscan_info_present_flag {
+            w.flag(self.overscan_appropriate_flag)?;
+        }
+
+        w.flag(self.video_signal_type_present_flag)?;
+        if self.video_signal_type_present_flag {
+            w.u8(3, self.video_format)?;
+            w.flag(self.video_full_range_flag)?;
+            w.flag(self.colour_description_present_flag)?;
+            if self.colour_description_present_flag {
+                w.u8(8, self.colour_primaries)?;
+                w.u8(8, self.transfer_characteristics)?;
+                w.u8(8, self.matrix_coefficients)?;
+            }
+        }
+
+        w.flag(self.chroma_loc_info_present_flag)?;
+        if self.chroma_loc_info_present_flag {
+            w.ue8(self.chroma_sample_loc_type_top_field)?;
+            w.ue8(self.chroma_sample_loc_type_bottom_field)?;
+        }
+
+        w.flag(self.timing_info_present_flag)?;
+        if self.timing_info_present_flag {
+            w.u32(32, self.num_units_in_tick)?;
+            w.u32(32, self.time_scale)?;
+            w.flag(self.fixed_frame_rate_flag)?;
+        }
+
+        w.flag(self.nal_hrd_parameters_present_flag)?;
+        if self.nal_hrd_parameters_present_flag {
+            match self.nal_hrd_parameters {
+                Some(ref hrd) => hrd.write(w)?,
+                None => return Err(err("nal_hrd_parameters missing")),
+            }
+        }
+
+        w.flag(self.vcl_hrd_parameters_present_flag)?;
+        if self.vcl_hrd_parameters_present_flag {
+            match self.vcl_hrd_parameters {
+                Some(ref hrd) => hrd.write(w)?,
+                None => return Err(err("vcl_hrd_parameters missing")),
+            }
+        }
+
+        if self.nal_hrd_parameters_present_flag ||
+           self.vcl_hrd_parameters_present_flag {
+            w.flag(self.low_delay_hrd_flag)?;
+        }
+
+        w.flag(self.pic_struct_present_flag)?;
+
+        w.flag(self.bitstream_restriction_flag)?;
+        if self.bitstream_restriction_flag {
+            w.flag(self.motion_vectors_over_pic_boundaries_flag)?;
+            w.ue8(self.max_bytes_per_pic_denom)?;
+            w.ue8(self.max_bits_per_mb_denom)?;
+            w.ue8(self.log2_max_mv_length_horizontal)?;
+            w.ue8(self.log2_max_mv_length_vertical)?;
+            w.ue8(self.max_num_reorder_frames)?;
+            w.ue8(self.max_dec_frame_buffering)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sample aspect ratio as width and height, None when unspecified.
+    pub fn sar(&self) -> Option<(u16, u16)> {
+        match self.aspect_ratio_idc {
+            1..=16 => Some(SAR[self.aspect_ratio_idc as usize - 1]),
+            EXTENDED_SAR if self.sar_width != 0 && self.sar_height != 0 =>
+                Some((self.sar_width, self.sar_height)),
+            _ => None,
+        }
+    }
+
+    /// Signals the sample aspect ratio, by aspect_ratio_idc when it is
+    /// in Table E-1 and by sar_width and sar_height otherwise.
+    pub fn set_sar(&mut self, width: u16, height: u16) {
+        self.aspect_ratio_info_present_flag = true;
+        match SAR.iter().position(|sar| *sar == (width, height)) {
+            Some(i) => {
+                self.aspect_ratio_idc = i as u8 + 1;
+                self.sar_width = 0;
+                self.sar_height = 0;
+            },
+            None => {
+                self.aspect_ratio_idc = EXTENDED_SAR;
+                self.sar_width = width;
+                self.sar_height = height;
+            },
+        }
+    }
+}
+
+impl Default for VideoUsabilityInformation {
+    /// VUI without any optional syntax elements present, with the
+    /// values inferred by `parse`.
+    fn default() -> VideoUsabilityInformation {
+        VideoUsabilityInformation {
+            aspect_ratio_info_present_flag: false,
+            aspect_ratio_idc: 0,
+            sar_width: 0,
+            sar_height: 0,
+            overscan_info_present_flag: false,
+            overscan_appropriate_flag: false,
+            video_signal_type_present_flag: false,
+            video_format: 5,
+            video_full_range_flag: false,
+            colour_description_present_flag: false,
+            colour_primaries: 2,
+            transfer_characteristics: 2,
+            matrix_coefficients: 2,
+            chroma_loc_info_present_flag: false,
+            chroma_sample_loc_type_top_field: 0,
+            chroma_sample_loc_type_bottom_field: 0,
+            timing_info_present_flag: false,
+            num_units_in_tick: 0,
+            time_scale: 0,
+            fixed_frame_rate_flag: false,
+            nal_hrd_parameters_present_flag: false,
+            nal_hrd_parameters: None,
+            vcl_hrd_parameters_present_flag: false,
+            vcl_hrd_parameters: None,
+            low_delay_hrd_flag: false,
+            pic_struct_present_flag: false,
+            bitstream_restriction_flag: false,
+            motion_vectors_over_pic_boundaries_flag: true,
+            max_bytes_per_pic_denom: 2,
+            max_bits_per_mb_denom: 1,
+            log2_max_mv_length_horizontal: 15,
+            log2_max_mv_length_vertical: 15,
+            max_num_reorder_frames: 16,
+            max_dec_frame_buffering: 16,
+        }
+    }
+}
 
 impl SequenceParameterSet {
-    pub fn parse<R: Read>(r: &mut BitReader<R>) ->
-                          Result<SequenceParameterSet> {
-        let profile_idc = r.b()?;
-        let constraint_set0_flag = r.flag()?;
-        let constraint_set1_flag = r.flag()?;
-        let constraint_set2_flag = r.flag()?;
-        let constraint_set3_flag = r.flag()?;
-        let constraint_set4_flag = r.flag()?;
-        let constraint_set5_flag = r.flag()?;
-
-        let reserved_zero_2bit = r.u64(2)?;
+    pub fn parse<R: BufRead>(r: &mut BitReader<R>) ->
+                             Result<SequenceParameterSet> {
+        let profile_idc = r.name("profile_idc").b()?;
+        let constraint_set0_flag = r.name("constraint_set0_flag").flag()?;
+        let constraint_set1_flag = r.name("constraint_set1_flag").flag()?;
+        let constraint_set2_flag = r.name("constraint_set2_flag").flag()?;
+        let constraint_set3_flag = r.name("constraint_set3_flag").flag()?;
+        let constraint_set4_flag = r.name("constraint_set4_flag").flag()?;
+        let constraint_set5_flag = r.name("constraint_set5_flag").flag()?;
+
+        let reserved_zero_2bit = r.name("reserved_zero_2bit").u64(2)?;
         if reserved_zero_2bit != 0 {
             return Err(err("Reserved zero 2 bit is not zero"));
         }
 
-        let level_idc = r.u8(8)?;
-        let seq_parameter_set_id = r.ue8()?;
+        let level_idc = r.name("level_idc").u8(8)?;
+        let seq_parameter_set_id = r.name("seq_parameter_set_id").ue8()?;
         if seq_parameter_set_id > 31 {
             return Err(err("seq_parameter_set_id too large"));
         }
@@ -102,6 +567,8 @@ impl SequenceParameterSet {
         let mut bit_depth_chroma_minus8 = 0;
         let mut qpprime_y_zero_transform_bypass_flag = false;
         let mut seq_scaling_matrix_present_flag = false;
+        let mut seq_scaling_lists = None;
+        let mut scaling_matrix = ScalingMatrix::flat();
         let mut log2_max_pic_order_cnt_lsb_minus4 = 0;
         let mut delta_pic_order_always_zero_flag = false;
         let mut offset_for_non_ref_pic = 0;
@@ -113,49 +580,68 @@ impl SequenceParameterSet {
             100 | 110 | 122 | 244 |  44 |
              83 |  86 | 118 | 128 | 138 |
             139 | 134 | 135 => {
-                chroma_format_idc = r.ue8()?;
+                chroma_format_idc = r.name("chroma_format_idc").ue8()?;
                 if chroma_format_idc > 3 {
                     return Err(err("chroma_format_idc too large"));
                 }
                 if chroma_format_idc == 3 {
-                    separate_colour_plane_flag = r.flag()?;
+                    separate_colour_plane_flag =
+                        r.name("separate_colour_plane_flag").flag()?;
                 }
-                bit_depth_luma_minus8 = r.ue8()?;
+                bit_depth_luma_minus8 = r.name("bit_depth_luma_minus8").ue8()?;
                 if bit_depth_luma_minus8 > 6 {
                     return Err(err("bit_depth_luma_minus8 too large"));
                 }
-                bit_depth_chroma_minus8 = r.ue8()?;
+                bit_depth_chroma_minus8 =
+                    r.name("bit_depth_chroma_minus8").ue8()?;
                 if bit_depth_chroma_minus8 > 6 {
                     return Err(err("bit_depth_chroma_minus8 too large"));
                 }
-                qpprime_y_zero_transform_bypass_flag = r.flag()?;
-                seq_scaling_matrix_present_flag = r.flag()?;
+                qpprime_y_zero_transform_bypass_flag =
+                    r.name("qpprime_y_zero_transform_bypass_flag").flag()?;
+                seq_scaling_matrix_present_flag =
+                    r.name("seq_scaling_matrix_present_flag").flag()?;
 
                 if seq_scaling_matrix_present_flag {
-                    return Err(not_impl("scaling matrix not implemented"));
+                    let count = if chroma_format_idc != 3 { 8 } else { 12 };
+                    let lists = ScalingLists::parse(r, count)?;
+                    /* Fall-back rule A */
+                    scaling_matrix = ScalingMatrix::new(&lists, None);
+                    seq_scaling_lists = Some(lists);
                 }
             },
             _ => {},
         };
 
-        let log2_max_frame_num_minus4 = r.ue32()?;
+        let log2_max_frame_num_minus4 =
+            r.name("log2_max_frame_num_minus4").ue32()?;
         if log2_max_frame_num_minus4 > 12 {
             return Err(err("log2_max_frame_num_minus4 larger than 12"));
         }
 
-        let pic_order_cnt_type = r.ue8()?;
+        let pic_order_cnt_type = r.name("pic_order_cnt_type").ue8()?;
         match pic_order_cnt_type {
             0 => {
-                log2_max_pic_order_cnt_lsb_minus4 = r.ue8()?;
+                log2_max_pic_order_cnt_lsb_minus4 =
+                    r.name("log2_max_pic_order_cnt_lsb_minus4").ue8()?;
+                if log2_max_pic_order_cnt_lsb_minus4 > 12 {
+                    return Err(err("log2_max_pic_order_cnt_lsb_minus4 \
+                                    larger than 12"));
+                }
             },
             1 => {
-                delta_pic_order_always_zero_flag = r.flag()?;
-                offset_for_non_ref_pic = r.se64()?;
-                offset_for_top_to_bottom_field = r.se64()?;
-                num_ref_frames_in_pic_order_cnt_cycle = r.ue8()?;
+                delta_pic_order_always_zero_flag =
+                    r.name("delta_pic_order_always_zero_flag").flag()?;
+                offset_for_non_ref_pic =
+                    r.name("offset_for_non_ref_pic").se64()?;
+                offset_for_top_to_bottom_field =
+                    r.name("offset_for_top_to_bottom_field").se64()?;
+                num_ref_frames_in_pic_order_cnt_cycle =
+                    r.name("num_ref_frames_in_pic_order_cnt_cycle").ue8()?;
                 /* Read offsets */
                 for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
-                    offset_for_ref_frame.push(r.se64()?);
+                    offset_for_ref_frame.push(
+                        r.name("offset_for_ref_frame").se64()?);
                 }
             },
             2 => {},
@@ -167,33 +653,41 @@ impl SequenceParameterSet {
          * min(mvScaleFactor * MaxDpbMbs / (PicWidthInMbs * FrameHeightInMbs),
                max(1, ceil(log2(NumViews))) * 16)
          */
-        let max_num_ref_frames = r.ue8()?;
-        let gaps_in_frame_num_value_allowed_flag = r.flag()?;
-        let pic_width_in_mbs_minus1 = r.ue32()?;
-        let pic_height_in_map_units_minus1 = r.ue32()?;
-        let frame_mbs_only_flag = r.flag()?;
+        let max_num_ref_frames = r.name("max_num_ref_frames").ue8()?;
+        let gaps_in_frame_num_value_allowed_flag =
+            r.name("gaps_in_frame_num_value_allowed_flag").flag()?;
+        let pic_width_in_mbs_minus1 =
+            r.name("pic_width_in_mbs_minus1").ue32()?;
+        let pic_height_in_map_units_minus1 =
+            r.name("pic_height_in_map_units_minus1").ue32()?;
+        let frame_mbs_only_flag = r.name("frame_mbs_only_flag").flag()?;
         let mut mb_adaptive_frame_field_flag = false;
-        if frame_mbs_only_flag {
-            mb_adaptive_frame_field_flag = r.flag()?;
+        if !frame_mbs_only_flag {
+            mb_adaptive_frame_field_flag =
+                r.name("mb_adaptive_frame_field_flag").flag()?;
         }
-        let direct_8x8_inference_flag = r.flag()?;
-        let frame_cropping_flag = r.flag()?;
+        let direct_8x8_inference_flag =
+            r.name("direct_8x8_inference_flag").flag()?;
+        let frame_cropping_flag = r.name("frame_cropping_flag").flag()?;
 
         let mut frame_crop_left_offset = 0;
         let mut frame_crop_right_offset = 0;
         let mut frame_crop_top_offset = 0;
         let mut frame_crop_bottom_offset = 0;
         if frame_cropping_flag {
-            frame_crop_left_offset = r.ue32()?;
-            frame_crop_right_offset = r.ue32()?;
-            frame_crop_top_offset = r.ue32()?;
-            frame_crop_bottom_offset = r.ue32()?;
+            frame_crop_left_offset = r.name("frame_crop_left_offset").ue32()?;
+            frame_crop_right_offset =
+                r.name("frame_crop_right_offset").ue32()?;
+            frame_crop_top_offset = r.name("frame_crop_top_offset").ue32()?;
+            frame_crop_bottom_offset =
+                r.name("frame_crop_bottom_offset").ue32()?;
         }
-        let vui_parameters_present_flag = r.flag()?;
+        let vui_parameters_present_flag =
+            r.name("vui_parameters_present_flag").flag()?;
 
-        let vui_parameters = None;
+        let mut vui_parameters = None;
         if vui_parameters_present_flag {
-            return Err(not_impl("vui parameters not implemented"));
+            vui_parameters = Some(VideoUsabilityInformation::parse(r)?);
         }
 
         r.rbsp_trailing_bits()?;
@@ -220,6 +714,8 @@ impl SequenceParameterSet {
             bit_depth_chroma_minus8,
             qpprime_y_zero_transform_bypass_flag,
             seq_scaling_matrix_present_flag,
+            seq_scaling_lists,
+            scaling_matrix,
             log2_max_frame_num_minus4,
             pic_order_cnt_type,
             log2_max_pic_order_cnt_lsb_minus4,
@@ -244,4 +740,652 @@ impl SequenceParameterSet {
             vui_parameters,
         })
     }
+
+    /// Writes seq_parameter_set_rbsp(), the inverse of `parse`,
+    /// including the rbsp trailing bits. The scaling matrix is derived
+    /// from the lists and is not written.
+    pub fn write(&self, w: &mut BitWriter) -> Result<()> {
+        w.b(self.profile_idc)?;
+        w.flag(self.constraint_set0_flag)?;
+        w.flag(self.constraint_set1_flag)?;
+        w.flag(self.constraint_set2_flag)?;
+        w.flag(self.constraint_set3_flag)?;
+        w.flag(self.constraint_set4_flag)?;
+        w.flag(self.constraint_set5_flag)?;
+        /* reserved_zero_2bit */
+        w.u8(2, 0)?;
+        w.u8(8, self.level_idc)?;
+        w.ue8(self.seq_parameter_set_id)?;
+
+        match self.profile_idc {
+            100 | 110 | 122 | 244 |  44 |
+             83 |  86 | 118 | 128 | 138 |
+            139 | 134 | 135 => {
+                w.ue8(self.chroma_format_idc)?;
+                if self.chroma_format_idc == 3 {
+                    w.flag(self.separate_colour_plane_flag)?;
+                }
+                w.ue8(self.bit_depth_luma_minus8)?;
+                w.ue8(self.bit_depth_chroma_minus8)?;
+                w.flag(self.qpprime_y_zero_transform_bypass_flag)?;
+                w.flag(self.seq_scaling_matrix_present_flag)?;
+
+                if self.seq_scaling_matrix_present_flag {
+                    let count =
+                        if self.chroma_format_idc != 3 { 8 } else { 12 };
+                    match self.seq_scaling_lists {
+                        Some(ref lists) if lists.lists.len() == count =>
+                            lists.write(w)?,
+                        Some(_) => return Err(err("seq_scaling_lists do not \
+                                                   match chroma_format_idc")),
+                        None => return Err(err("seq_scaling_lists missing")),
+                    }
+                }
+            },
+            _ => {},
+        };
+
+        w.ue32(self.log2_max_frame_num_minus4)?;
+        w.ue8(self.pic_order_cnt_type)?;
+        match self.pic_order_cnt_type {
+            0 => w.ue8(self.log2_max_pic_order_cnt_lsb_minus4)?,
+            1 => {
+                let count = self.num_ref_frames_in_pic_order_cnt_cycle;
+                if self.offset_for_ref_frame.len() != count as usize {
+                    return Err(err("offset_for_ref_frame does not match \
+                                    num_ref_frames_in_pic_order_cnt_cycle"));
+                }
+                w.flag(self.delta_pic_order_always_zero_flag)?;
+                w.se64(self.offset_for_non_ref_pic)?;
+                w.se64(self.offset_for_top_to_bottom_field)?;
+                w.ue8(count)?;
+                for offset in &self.offset_for_ref_frame {
+                    w.se64(*offset)?;
+                }
+            },
+            2 => {},
+            _ => return Err(err("pic_order_cnt_type larger than 2")),
+        }
+
+        w.ue8(self.max_num_ref_frames)?;
+        w.flag(self.gaps_in_frame_num_value_allowed_flag)?;
+        w.ue32(self.pic_width_in_mbs_minus1)?;
+        w.ue32(self.pic_height_in_map_units_minus1)?;
+        w.flag(self.frame_mbs_only_flag)?;
+        if !self.frame_mbs_only_flag {
+            w.flag(self.mb_adaptive_frame_field_flag)?;
+        }
+        w.flag(self.direct_8x8_inference_flag)?;
+        w.flag(self.frame_cropping_flag)?;
+        if self.frame_cropping_flag {
+            w.ue32(self.frame_crop_left_offset)?;
+            w.ue32(self.frame_crop_right_offset)?;
+            w.ue32(self.frame_crop_top_offset)?;
+            w.ue32(self.frame_crop_bottom_offset)?;
+        }
+
+        w.flag(self.vui_parameters_present_flag)?;
+        if self.vui_parameters_present_flag {
+            match self.vui_parameters {
+                Some(ref vui) => vui.write(w)?,
+                None => return Err(err("vui_parameters missing")),
+            }
+        }
+
+        w.rbsp_trailing_bits()
+    }
+
+    /// ChromaArrayType, 0 when colour planes are coded separately.
+    pub fn chroma_array_type(&self) -> u8 {
+        if self.separate_colour_plane_flag { 0 } else { self.chroma_format_idc }
+    }
+
+    /// CropUnitX and CropUnitY, equations 7-19 to 7-22.
+    pub fn crop_units(&self) -> (u32, u32) {
+        let field_factor = if self.frame_mbs_only_flag { 1 } else { 2 };
+        /* SubWidthC and SubHeightC, Table 6-1 */
+        match self.chroma_array_type() {
+            0 => (1, field_factor),
+            1 => (2, 2 * field_factor),
+            2 => (2, field_factor),
+            _ => (1, field_factor),
+        }
+    }
+
+    /// Width in luma samples of the decoded frame. The syntax elements
+    /// are not bounded, sizes saturate at u32::MAX.
+    pub fn uncropped_width(&self) -> u32 {
+        self.pic_width_in_mbs_minus1.saturating_add(1).saturating_mul(16)
+    }
+
+    /// Height in luma samples of the decoded frame, FrameHeightInMbs
+    /// is twice the number of map units when fields might be coded.
+    pub fn uncropped_height(&self) -> u32 {
+        let field_factor = if self.frame_mbs_only_flag { 1 } else { 2 };
+
+        self.pic_height_in_map_units_minus1.saturating_add(1)
+            .saturating_mul(field_factor * 16)
+    }
+
+    /// Width in luma samples after applying the frame cropping
+    /// rectangle.
+    pub fn width(&self) -> u32 {
+        let (crop_unit_x, _) = self.crop_units();
+        let crop = crop_unit_x.saturating_mul(
+            self.frame_crop_left_offset
+                .saturating_add(self.frame_crop_right_offset));
+
+        self.uncropped_width().saturating_sub(crop)
+    }
+
+    /// Height in luma samples after applying the frame cropping
+    /// rectangle.
+    pub fn height(&self) -> u32 {
+        let (_, crop_unit_y) = self.crop_units();
+        let crop = crop_unit_y.saturating_mul(
+            self.frame_crop_top_offset
+                .saturating_add(self.frame_crop_bottom_offset));
+
+        self.uncropped_height().saturating_sub(crop)
+    }
+
+    /// MaxFrameNum, equation 7-10.
+    pub fn max_frame_num(&self) -> u32 {
+        1 << (self.log2_max_frame_num_minus4 + 4)
+    }
+
+    /// MaxPicOrderCntLsb, equation 7-11.
+    pub fn max_pic_order_cnt_lsb(&self) -> u32 {
+        1 << (self.log2_max_pic_order_cnt_lsb_minus4 + 4)
+    }
+
+    /// Name of the profile as in Annex A and Annex G/H, including
+    /// the profiles signalled by constraint flags like Constrained
+    /// Baseline.
+    pub fn profile_name(&self) -> &'static str {
+        match self.profile_idc {
+            66 if self.constraint_set1_flag => "Constrained Baseline",
+            66 => "Baseline",
+            77 => "Main",
+            88 => "Extended",
+            100 if self.constraint_set4_flag && self.constraint_set5_flag =>
+                "Constrained High",
+            100 if self.constraint_set4_flag => "Progressive High",
+            100 => "High",
+            110 if self.constraint_set3_flag => "High 10 Intra",
+            110 if self.constraint_set4_flag => "Progressive High 10",
+            110 => "High 10",
+            122 if self.constraint_set3_flag => "High 4:2:2 Intra",
+            122 => "High 4:2:2",
+            244 if self.constraint_set3_flag => "High 4:4:4 Intra",
+            244 => "High 4:4:4 Predictive",
+            44 => "CAVLC 4:4:4 Intra",
+            83 if self.constraint_set5_flag => "Scalable Constrained Baseline",
+            83 => "Scalable Baseline",
+            86 if self.constraint_set3_flag => "Scalable High Intra",
+            86 if self.constraint_set5_flag => "Scalable Constrained High",
+            86 => "Scalable High",
+            118 => "Multiview High",
+            128 => "Stereo High",
+            134 => "MFC High",
+            135 => "MFC Depth High",
+            138 => "Multiview Depth High",
+            139 => "Enhanced Multiview Depth High",
+            _ => "Unknown",
+        }
+    }
+
+    /// Level as written in Table A-1, e.g. "3.1". Level 1b is signalled
+    /// either by level_idc 9 or, for Baseline, Main and Extended, by
+    /// level_idc 11 together with constraint_set3_flag.
+    pub fn level(&self) -> String {
+        let level_1b = match self.profile_idc {
+            66 | 77 | 88 =>
+                self.level_idc == 11 && self.constraint_set3_flag,
+            _ => false,
+        };
+        if level_1b || self.level_idc == 9 {
+            return String::from("1b");
+        }
+
+        format!("{}.{}", self.level_idc / 10, self.level_idc % 10)
+    }
+
+    /// Descriptions of the constraints signalled by the set
+    /// constraint_setX_flags, see A.2.
+    pub fn constraints(&self) -> Vec<&'static str> {
+        let mut constraints = Vec::new();
+
+        if self.constraint_set0_flag {
+            constraints.push("Obeys Baseline constraints");
+        }
+        if self.constraint_set1_flag {
+            constraints.push("Obeys Main constraints");
+        }
+        if self.constraint_set2_flag {
+            constraints.push("Obeys Extended constraints");
+        }
+        if self.constraint_set3_flag {
+            match self.profile_idc {
+                66 | 77 | 88 if self.level_idc == 11 =>
+                    constraints.push("Level 1b"),
+                110 | 122 | 244 | 44 | 86 =>
+                    constraints.push("Intra profile"),
+                _ => (),
+            }
+        }
+        if self.constraint_set4_flag {
+            constraints.push("Frame coding only");
+        }
+        if self.constraint_set5_flag {
+            constraints.push("No B slices");
+        }
+
+        constraints
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use proptest::prelude::*;
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::sample::select;
+    use scaling::*;
+    use scaling::tests::scaling_lists;
+    use super::*;
+
+    /* Baseline 320x240 with VUI containing extended SAR, colour
+     * description, timing info, NAL HRD and bitstream restriction. */
+    const SPS_WITH_VUI: [u8; 38] = [
+        0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf7, 0xfe,
+        0x00, 0x08, 0x00, 0x06, 0xd4, 0x04, 0x04, 0x05,
+        0x00, 0x00, 0x03, 0x03, 0xe9, 0x00, 0x00, 0xea,
+        0x60, 0xe8, 0x60, 0x07, 0xd1, 0x00, 0x09, 0xc4,
+        0xaf, 0x7b, 0xe0, 0xf8, 0xb1, 0x75,
+    ];
+
+    /* High profile with scaling matrix, Intra Y 4x4 uses the default
+     * list and Intra Y 8x8 is all 10 */
+    const SPS_WITH_SCALING_MATRIX: [u8; 12] = [
+        0x64, 0x00, 0x28, 0xad, 0x84, 0x41, 0x20, 0x55,
+        0xb4, 0x0a, 0x0f, 0xc8,
+    ];
+
+    /* High 1920x1088 cropped to 1920x1080, level 4.0 */
+    const SPS_WITH_CROPPING: [u8; 11] = [
+        0x64, 0x00, 0x28, 0xac, 0x2d, 0x94, 0x07, 0x80,
+        0x22, 0x7e, 0x54,
+    ];
+
+    #[test]
+    fn parse_scaling_matrix() {
+        let cursor = Cursor::new(SPS_WITH_SCALING_MATRIX);
+        let mut reader = BitReader::new(cursor);
+
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        let matrix = sps.scaling_matrix;
+
+        assert!(sps.seq_scaling_matrix_present_flag);
+        assert_eq!(sps.seq_scaling_lists.unwrap().lists.len(), 8);
+        assert_eq!(matrix.scaling_list_4x4[0], DEFAULT_4X4_INTRA);
+        assert_eq!(matrix.scaling_list_4x4[2], DEFAULT_4X4_INTRA);
+        assert_eq!(matrix.scaling_list_4x4[3], DEFAULT_4X4_INTER);
+        assert_eq!(matrix.scaling_list_8x8[0], vec![10; 64]);
+        assert_eq!(matrix.scaling_list_8x8[1], DEFAULT_8X8_INTER.to_vec());
+        assert_eq!(matrix.scaling_list_8x8[2], vec![10; 64]);
+    }
+
+    #[test]
+    fn parse_vui() {
+        let cursor = Cursor::new(SPS_WITH_VUI);
+        let mut reader = BitReader::new(cursor);
+
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        let vui = sps.vui_parameters.unwrap();
+
+        assert_eq!(sps.profile_idc, 66);
+        assert_eq!(sps.pic_width_in_mbs_minus1, 19);
+        assert_eq!(sps.pic_height_in_map_units_minus1, 14);
+        assert_eq!(vui.aspect_ratio_idc, EXTENDED_SAR);
+        assert_eq!(vui.sar_width, 4);
+        assert_eq!(vui.sar_height, 3);
+        assert!(!vui.overscan_info_present_flag);
+        assert_eq!(vui.video_format, 5);
+        assert_eq!(vui.colour_primaries, 1);
+        assert_eq!(vui.transfer_characteristics, 1);
+        assert_eq!(vui.matrix_coefficients, 1);
+        assert_eq!(vui.num_units_in_tick, 1001);
+        assert_eq!(vui.time_scale, 60000);
+        assert!(vui.fixed_frame_rate_flag);
+        assert!(vui.vcl_hrd_parameters.is_none());
+        assert!(vui.pic_struct_present_flag);
+        assert_eq!(vui.log2_max_mv_length_horizontal, 10);
+        assert_eq!(vui.max_num_reorder_frames, 0);
+        assert_eq!(vui.max_dec_frame_buffering, 1);
+    }
+
+    #[test]
+    fn parse_vui_nal_hrd() {
+        let cursor = Cursor::new(SPS_WITH_VUI);
+        let mut reader = BitReader::new(cursor);
+
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        let hrd = sps.vui_parameters.unwrap().nal_hrd_parameters.unwrap();
+
+        assert_eq!(hrd.cpb_cnt_minus1, 0);
+        assert_eq!(hrd.bit_rate_scale, 4);
+        assert_eq!(hrd.cpb_size_scale, 3);
+        assert_eq!(hrd.bit_rate_value_minus1, [2000]);
+        assert_eq!(hrd.cpb_size_value_minus1, [5000]);
+        assert_eq!(hrd.cbr_flag, [false]);
+        assert_eq!(hrd.initial_cpb_removal_delay_length_minus1, 23);
+        assert_eq!(hrd.time_offset_length, 24);
+    }
+
+    #[test]
+    fn derived_values() {
+        let cursor = Cursor::new(SPS_WITH_VUI);
+        let mut reader = BitReader::new(cursor);
+
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+
+        assert_eq!(sps.width(), 320);
+        assert_eq!(sps.height(), 240);
+        assert_eq!(sps.profile_name(), "Constrained Baseline");
+        assert_eq!(sps.level(), "3.0");
+        assert_eq!(sps.constraints(),
+                   ["Obeys Baseline constraints", "Obeys Main constraints"]);
+    }
+
+    #[test]
+    fn cropped_size() {
+        let cursor = Cursor::new(SPS_WITH_CROPPING);
+        let mut reader = BitReader::new(cursor);
+
+        let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+
+        assert_eq!(sps.uncropped_width(), 1920);
+        assert_eq!(sps.uncropped_height(), 1088);
+        assert_eq!(sps.width(), 1920);
+        assert_eq!(sps.height(), 1080);
+        assert_eq!(sps.max_frame_num(), 1 << 8);
+        assert_eq!(sps.max_pic_order_cnt_lsb(), 1 << 6);
+        assert_eq!(sps.profile_name(), "High");
+        assert_eq!(sps.level(), "4.0");
+    }
+
+    #[test]
+    fn size_saturates() {
+        let cursor = Cursor::new(SPS_WITH_CROPPING);
+        let mut reader = BitReader::new(cursor);
+        let mut sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        sps.pic_width_in_mbs_minus1 = u32::MAX;
+        sps.pic_height_in_map_units_minus1 = u32::MAX - 1;
+        sps.frame_crop_left_offset = u32::MAX;
+        sps.frame_crop_right_offset = u32::MAX;
+
+        assert_eq!(sps.uncropped_width(), u32::MAX);
+        assert_eq!(sps.uncropped_height(), u32::MAX);
+        assert_eq!(sps.width(), 0);
+        assert_eq!(sps.height(), u32::MAX - 8);
+    }
+
+    fn write(sps: &SequenceParameterSet) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        sps.write(&mut writer).unwrap();
+
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn write_byte_exact() {
+        for buf in &[&SPS_WITH_VUI[..], &SPS_WITH_SCALING_MATRIX[..],
+                     &SPS_WITH_CROPPING[..]] {
+            let mut reader = BitReader::new(Cursor::new(buf));
+            let sps = SequenceParameterSet::parse(&mut reader).unwrap();
+
+            assert_eq!(write(&sps), *buf);
+        }
+    }
+
+    #[test]
+    fn write_vui_missing() {
+        let mut reader = BitReader::new(Cursor::new(SPS_WITH_VUI));
+        let mut sps = SequenceParameterSet::parse(&mut reader).unwrap();
+        sps.vui_parameters = None;
+        let mut writer = BitWriter::new();
+
+        assert!(sps.write(&mut writer).is_err());
+    }
+
+    prop_compose! {
+        fn hrd_parameters()(scales in (0u8..16, 0u8..16),
+                            values in vec((0..u32::MAX,
+                                           0..u32::MAX,
+                                           any::<bool>()), 1..33),
+                            lengths in (0u8..32, 0u8..32, 0u8..32, 0u8..32))
+                            -> HrdParameters {
+            HrdParameters {
+                cpb_cnt_minus1: values.len() as u8 - 1,
+                bit_rate_scale: scales.0,
+                cpb_size_scale: scales.1,
+                bit_rate_value_minus1: values.iter().map(|v| v.0).collect(),
+                cpb_size_value_minus1: values.iter().map(|v| v.1).collect(),
+                cbr_flag: values.iter().map(|v| v.2).collect(),
+                initial_cpb_removal_delay_length_minus1: lengths.0,
+                cpb_removal_delay_length_minus1: lengths.1,
+                dpb_output_delay_length_minus1: lengths.2,
+                time_offset_length: lengths.3,
+            }
+        }
+    }
+
+    prop_compose! {
+        /* Syntax elements that are not present have inferred values */
+        fn vui_parameters()(aspect in option::of((any::<u8>(),
+                                                  any::<u16>(),
+                                                  any::<u16>())),
+                            overscan in option::of(any::<bool>()),
+                            signal in option::of((0u8..8, any::<bool>(),
+                                option::of(any::<[u8; 3]>()))),
+                            chroma_loc in option::of((0u8..6, 0u8..6)),
+                            timing in option::of((1..=u32::MAX,
+                                                  1..=u32::MAX,
+                                                  any::<bool>())),
+                            nal_hrd in option::of(hrd_parameters()),
+                            vcl_hrd in option::of(hrd_parameters()),
+                            flags in any::<(bool, bool)>(),
+                            restriction in option::of((any::<bool>(),
+                                0u8..17, 0u8..17, 0u8..16, 0u8..16,
+                                any::<u8>(), any::<u8>())))
+                            -> VideoUsabilityInformation {
+            let (aspect_ratio_idc, sar_width, sar_height) = match aspect {
+                Some((EXTENDED_SAR, width, height)) =>
+                    (EXTENDED_SAR, width, height),
+                Some((idc, _, _)) => (idc, 0, 0),
+                None => (0, 0, 0),
+            };
+            let (video_format, video_full_range_flag, colour) =
+                signal.unwrap_or((5, false, None));
+            let colour_description = colour.unwrap_or([2, 2, 2]);
+            let chroma_sample_loc_type = chroma_loc.unwrap_or((0, 0));
+            let (num_units_in_tick, time_scale, fixed_frame_rate_flag) =
+                timing.unwrap_or((0, 0, false));
+            let hrd_present = nal_hrd.is_some() || vcl_hrd.is_some();
+            let bitstream_restriction =
+                restriction.unwrap_or((true, 2, 1, 15, 15, 16, 16));
+
+            VideoUsabilityInformation {
+                aspect_ratio_info_present_flag: aspect.is_some(),
+                aspect_ratio_idc,
+                sar_width,
+                sar_height,
+                overscan_info_present_flag: overscan.is_some(),
+                overscan_appropriate_flag: overscan.unwrap_or(false),
+                video_signal_type_present_flag: signal.is_some(),
+                video_format,
+                video_full_range_flag,
+                colour_description_present_flag: colour.is_some(),
+                colour_primaries: colour_description[0],
+                transfer_characteristics: colour_description[1],
+                matrix_coefficients: colour_description[2],
+                chroma_loc_info_present_flag: chroma_loc.is_some(),
+                chroma_sample_loc_type_top_field: chroma_sample_loc_type.0,
+                chroma_sample_loc_type_bottom_field: chroma_sample_loc_type.1,
+                timing_info_present_flag: timing.is_some(),
+                num_units_in_tick,
+                time_scale,
+                fixed_frame_rate_flag,
+                nal_hrd_parameters_present_flag: nal_hrd.is_some(),
+                nal_hrd_parameters: nal_hrd,
+                vcl_hrd_parameters_present_flag: vcl_hrd.is_some(),
+                vcl_hrd_parameters: vcl_hrd,
+                low_delay_hrd_flag: hrd_present && flags.0,
+                pic_struct_present_flag: flags.1,
+                bitstream_restriction_flag: restriction.is_some(),
+                motion_vectors_over_pic_boundaries_flag:
+                    bitstream_restriction.0,
+                max_bytes_per_pic_denom: bitstream_restriction.1,
+                max_bits_per_mb_denom: bitstream_restriction.2,
+                log2_max_mv_length_horizontal: bitstream_restriction.3,
+                log2_max_mv_length_vertical: bitstream_restriction.4,
+                max_num_reorder_frames:
+                    bitstream_restriction.5.min(bitstream_restriction.6),
+                max_dec_frame_buffering: bitstream_restriction.6,
+            }
+        }
+    }
+
+    prop_compose! {
+        fn sequence_parameter_set()(
+                profile_idc in select(vec![66u8, 77, 88, 100, 110, 122,
+                                           244, 44, 83, 86, 118, 128]),
+                constraint_flags in any::<[bool; 6]>(),
+                ids in (any::<u8>(), 0u8..32),
+                high in (0u8..4, any::<bool>(), 0u8..7, 0u8..7,
+                         any::<bool>(), option::of(scaling_lists(12))),
+                poc in (0u8..3, 0u8..13, any::<bool>(), any::<i32>(),
+                        any::<i32>(), vec(any::<i32>(), 0..256)),
+                frame_num in (0u32..13, any::<u8>(), any::<bool>()),
+                size in any::<(u32, u32)>(),
+                frame in any::<(bool, bool, bool)>(),
+                crop in option::of(any::<(u32, u32, u32, u32)>()),
+                vui in option::of(vui_parameters()))
+                -> SequenceParameterSet {
+            let high_profile = !matches!(profile_idc, 66 | 77 | 88);
+            let (chroma_format_idc, separate_colour_plane_flag,
+                 bit_depth_luma_minus8, bit_depth_chroma_minus8,
+                 qpprime_y_zero_transform_bypass_flag, mut lists) =
+                if high_profile { high } else { (1, false, 0, 0, false, None) };
+            if let Some(ref mut lists) = lists {
+                if chroma_format_idc != 3 {
+                    lists.lists.truncate(8);
+                }
+            }
+            let scaling_matrix = match lists {
+                Some(ref lists) => ScalingMatrix::new(lists, None),
+                None => ScalingMatrix::flat(),
+            };
+            let (pic_order_cnt_type, lsb, always_zero, non_ref,
+                 top_to_bottom, offsets) = poc;
+            let offsets: Vec<i64> = offsets.iter().map(|o| *o as i64)
+                .collect();
+            let (frame_mbs_only_flag, mbaff, direct) = frame;
+            let frame_crop = crop.unwrap_or((0, 0, 0, 0));
+
+            SequenceParameterSet {
+                profile_idc,
+                constraint_set0_flag: constraint_flags[0],
+                constraint_set1_flag: constraint_flags[1],
+                constraint_set2_flag: constraint_flags[2],
+                constraint_set3_flag: constraint_flags[3],
+                constraint_set4_flag: constraint_flags[4],
+                constraint_set5_flag: constraint_flags[5],
+                level_idc: ids.0,
+                seq_parameter_set_id: ids.1,
+                chroma_format_idc,
+                separate_colour_plane_flag:
+                    chroma_format_idc == 3 && separate_colour_plane_flag,
+                bit_depth_luma_minus8,
+                bit_depth_chroma_minus8,
+                qpprime_y_zero_transform_bypass_flag,
+                seq_scaling_matrix_present_flag: lists.is_some(),
+                seq_scaling_lists: lists,
+                scaling_matrix,
+                log2_max_frame_num_minus4: frame_num.0,
+                pic_order_cnt_type,
+                log2_max_pic_order_cnt_lsb_minus4:
+                    if pic_order_cnt_type == 0 { lsb } else { 0 },
+                delta_pic_order_always_zero_flag:
+                    pic_order_cnt_type == 1 && always_zero,
+                offset_for_non_ref_pic:
+                    if pic_order_cnt_type == 1 { non_ref as i64 } else { 0 },
+                offset_for_top_to_bottom_field:
+                    if pic_order_cnt_type == 1 { top_to_bottom as i64 }
+                    else { 0 },
+                num_ref_frames_in_pic_order_cnt_cycle:
+                    if pic_order_cnt_type == 1 { offsets.len() as u8 }
+                    else { 0 },
+                offset_for_ref_frame:
+                    if pic_order_cnt_type == 1 { offsets } else { vec![] },
+                max_num_ref_frames: frame_num.1,
+                gaps_in_frame_num_value_allowed_flag: frame_num.2,
+                pic_width_in_mbs_minus1: size.0,
+                pic_height_in_map_units_minus1: size.1,
+                frame_mbs_only_flag,
+                mb_adaptive_frame_field_flag: !frame_mbs_only_flag && mbaff,
+                direct_8x8_inference_flag: !frame_mbs_only_flag || direct,
+                frame_cropping_flag: crop.is_some(),
+                frame_crop_left_offset: frame_crop.0,
+                frame_crop_right_offset: frame_crop.1,
+                frame_crop_top_offset: frame_crop.2,
+                frame_crop_bottom_offset: frame_crop.3,
+                vui_parameters_present_flag: vui.is_some(),
+                vui_parameters: vui,
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn write_read_back(sps in sequence_parameter_set()) {
+            let bytes = write(&sps);
+            let mut reader = BitReader::new(&bytes[..]);
+
+            let parsed = SequenceParameterSet::parse(&mut reader).unwrap();
+
+            prop_assert_eq!(write(&parsed), bytes);
+            prop_assert_eq!(parsed, sps);
+        }
+    }
+
+    #[test]
+    fn default_vui() {
+        let mut writer = BitWriter::new_rbsp();
+        VideoUsabilityInformation::default().write(&mut writer).unwrap();
+        writer.rbsp_trailing_bits().unwrap();
+        let bytes = writer.into_bytes();
+        let mut reader = BitReader::new_rbsp(&bytes[..]);
+
+        let vui = VideoUsabilityInformation::parse(&mut reader).unwrap();
+
+        assert_eq!(vui, VideoUsabilityInformation::default());
+    }
+
+    #[test]
+    fn set_sar() {
+        let mut vui = VideoUsabilityInformation::default();
+        assert_eq!(vui.sar(), None);
+
+        vui.set_sar(4, 3);
+        assert_eq!(vui.aspect_ratio_idc, 14);
+        assert_eq!(vui.sar(), Some((4, 3)));
+
+        vui.set_sar(5, 4);
+        assert_eq!(vui.aspect_ratio_idc, EXTENDED_SAR);
+        assert_eq!(vui.sar(), Some((5, 4)));
+    }
 }
@@ -0,0 +1,183 @@
+use std::io;
+use std::io::prelude::*;
+use std::fmt;
+
+use nalunit::NalUnit;
+
+/// Descriptor of a syntax element as specified in 7.2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum Descriptor {
+    /// Unsigned integer of n bits, u(n)
+    U(u8),
+    /// Unsigned Exp-Golomb code, ue(v)
+    Ue,
+    /// Signed Exp-Golomb code, se(v)
+    Se,
+}
+
+/// Syntax element as read by the bit reader when tracing.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct TraceElement {
+    pub name: Option<&'static str>,
+    /// Bit position of the first bit, within the RBSP
+    pub bit_pos: usize,
+    pub descriptor: Descriptor,
+    pub value: i64,
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Descriptor::U(n) => write!(f, "u({})", n),
+            Descriptor::Ue => write!(f, "ue(v)"),
+            Descriptor::Se => write!(f, "se(v)"),
+        }
+    }
+}
+
+impl TraceElement {
+    /// The coded bits as a string of '0' and '1'.
+    pub fn bits(&self) -> String {
+        let code_num = match self.descriptor {
+            Descriptor::U(0) => return String::new(),
+            Descriptor::U(n) => {
+                return format!("{:01$b}", self.value as u64, n as usize);
+            },
+            Descriptor::Ue => self.value as u64 as u128,
+            /* Table 9-3 */
+            Descriptor::Se if self.value > 0 => self.value as u128 * 2 - 1,
+            Descriptor::Se => self.value.unsigned_abs() as u128 * 2,
+        };
+        /* Leading zero bits, a one bit and the info bits */
+        let code = format!("{:b}", code_num + 1);
+
+        format!("{:0>1$}", code, code.len() * 2 - 1)
+    }
+}
+
+/// Writes syntax element traces in the layout of the trace_dec.txt
+/// written by the JM reference decoder.
+///
+/// Bit positions are counted over all traced elements, like JM does.
+/// Slice data is not parsed, so positions differ from JM after the
+/// first slice header.
+pub struct JmTraceWriter<W> {
+    w: W,
+    bitcounter: usize,
+}
+
+impl<W: Write> JmTraceWriter<W> {
+    pub fn new(w: W) -> JmTraceWriter<W> {
+        JmTraceWriter { w, bitcounter: 0 }
+    }
+
+    /// Writes the line starting a NAL unit. `len` is the length of the
+    /// NAL unit, the header and the RBSP.
+    pub fn nal(&mut self, nal: &NalUnit, long_start_code: bool,
+               len: usize) -> io::Result<()> {
+        write!(self.w, "\n\nAnnex B NALU w/ {} startcode, len {}, \
+                        forbidden_bit 0, nal_reference_idc {}, \
+                        nal_unit_type {}\n\n",
+               if long_start_code { "long" } else { "short" }, len,
+               nal.nal_ref_idc, nal.nal_unit_type)
+    }
+
+    /// Writes the syntax elements of a NAL unit of type
+    /// `nal_unit_type`. Only SPS, PPS and slice header elements are
+    /// written, as by JM.
+    pub fn elements(&mut self, nal_unit_type: u8,
+                    elements: &[TraceElement]) -> io::Result<()> {
+        let mut prefix = match nal_unit_type {
+            1 | 5 => "SH",
+            7 => "SPS",
+            8 => "PPS",
+            _ => return Ok(()),
+        };
+
+        for element in elements {
+            let name = element.name.unwrap_or("");
+            let bits = element.bits();
+            let label = format!("@{:<5} {}: {}", self.bitcounter, prefix,
+                                name);
+            writeln!(self.w, "{:<55}{:>15} ({:3}) ", label, bits,
+                     element.value)?;
+            self.bitcounter += bits.len();
+            if name == "vui_parameters_present_flag" {
+                prefix = "VUI";
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use bitreader::BitReader;
+    use paramsets::ParameterSets;
+
+    #[test]
+    fn bits() {
+        let element = |descriptor, value| TraceElement {
+            name: None,
+            bit_pos: 0,
+            descriptor,
+            value,
+        };
+
+        assert_eq!(element(Descriptor::U(8), 66).bits(), "01000010");
+        assert_eq!(element(Descriptor::U(1), 1).bits(), "1");
+        assert_eq!(element(Descriptor::U(0), 0).bits(), "");
+        assert_eq!(element(Descriptor::Ue, 0).bits(), "1");
+        assert_eq!(element(Descriptor::Ue, 3).bits(), "00100");
+        assert_eq!(element(Descriptor::Se, 1).bits(), "010");
+        assert_eq!(element(Descriptor::Se, -1).bits(), "011");
+        assert_eq!(element(Descriptor::Se, -2).bits(), "00101");
+    }
+
+    #[test]
+    fn trace_sps() {
+        let buf = vec![
+            0x00, 0x00, 0x00, 0x01,
+          /* Baseline 320x240 */
+            0x67, 0x42, 0xc0, 0x1e, 0xed, 0x02, 0x83, 0xf2,
+        ];
+        let mut reader = BitReader::new(Cursor::new(buf));
+        reader.skip_to_start_code().unwrap();
+        let long_start_code = reader.long_start_code();
+        let (mut nal, rbsp) = NalUnit::parse(&mut reader).unwrap();
+        let mut elements = Vec::new();
+        nal.parse_payload_traced(&rbsp, &ParameterSets::new(),
+                                 &mut elements).unwrap();
+
+        let mut out = Vec::new();
+        {
+            let mut writer = JmTraceWriter::new(&mut out);
+            writer.nal(&nal, long_start_code, rbsp.len() + 1).unwrap();
+            writer.elements(nal.nal_unit_type, &elements).unwrap();
+        }
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[2], "Annex B NALU w/ long startcode, len 8, \
+                              forbidden_bit 0, nal_reference_idc 3, \
+                              nal_unit_type 7");
+        assert_eq!(lines[4], concat!("@0     SPS: profile_idc",
+                                     "                                ",
+                                     "       01000010 ( 66) "));
+        assert_eq!(lines[5].len(), 77);
+        assert!(lines[5].starts_with("@8     SPS: constraint_set0_flag "));
+        assert!(lines[5].ends_with(" 1 (  1) "));
+        assert!(lines[13].starts_with("@24    SPS: seq_parameter_set_id "));
+        assert!(lines[13].ends_with(" 1 (  0) "));
+        assert!(lines[19].starts_with("@34    SPS: pic_width_in_mbs_minus1 "));
+        assert!(lines[19].ends_with(" 000010100 ( 19) "));
+        assert!(lines[24].starts_with("@53    SPS: vui_parameters_present_"));
+        assert_eq!(lines.len(), 25);
+    }
+}
@@ -0,0 +1,491 @@
+use std::io::prelude::*;
+use std::collections::HashMap;
+
+use demux::Demuxer;
+use super::*;
+
+/// Size of a transport stream packet.
+pub const PACKET_SIZE: usize = 188;
+
+const SYNC_BYTE: u8 = 0x47;
+
+/* Table 2-34, ITU-T H.264 | ISO/IEC 14496-10 video */
+const STREAM_TYPE_H264: u8 = 0x1b;
+
+/// A PES packet of the H.264 elementary stream, usually one access
+/// unit.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct Pes {
+    /// Byte offset of the transport packet starting the PES packet
+    pub offset: u64,
+    pub stream_id: u8,
+    /// Presentation time stamp, 90 kHz
+    pub pts: Option<u64>,
+    /// Decoding time stamp, 90 kHz, equal to the PTS when not present
+    pub dts: Option<u64>,
+    /// PES packet data, an Annex B byte stream
+    pub data: Vec<u8>,
+}
+
+/// Discontinuity of continuity_counter, packets were lost or
+/// reordered. Duplicate packets are dropped.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub struct ContinuityError {
+    pub pid: u16,
+    /// Byte offset of the packet
+    pub offset: u64,
+    pub expected: u8,
+    pub found: u8,
+}
+
+/// Reads the H.264 elementary stream of the first program of an
+/// MPEG-2 transport stream, as specified in ITU-T H.222.0.
+///
+/// Also reads as an Annex B byte stream with `AnnexBReader`, the data
+/// of the PES packets, so the stream can be parsed like a raw H.264
+/// stream.
+pub struct TsReader<R> {
+    reader: R,
+    /* Byte offset of the next packet */
+    offset: u64,
+    pmt_pid: Option<u16>,
+    es_pid: Option<u16>,
+    /* Last continuity_counter per PID */
+    continuity: HashMap<u16, u8>,
+    continuity_errors: Vec<ContinuityError>,
+    /* PSI sections being reassembled per PID */
+    sections: HashMap<u16, Vec<u8>>,
+    /* PES packet being reassembled */
+    pes: Option<Pes>,
+}
+
+fn err(text: &str) -> ParserError {
+    let unit = ParserUnit::Ts();
+    let description = String::from(text);
+    let error = ParserUnitError { unit, description, location: None };
+
+    ParserError::InvalidStream(error)
+}
+
+
+/* 33 bit time stamp of 5 bytes with marker bits, 2.4.3.7 */
+fn timestamp(b: &[u8]) -> u64 {
+    (b[0] as u64 >> 1 & 0x07) << 30 | (b[1] as u64) << 22 |
+        (b[2] as u64 >> 1) << 15 | (b[3] as u64) << 7 | b[4] as u64 >> 1
+}
+
+/* Parses the PES packet header, 2.4.3.6. Returns the stream_id, PTS,
+ * DTS and the size of the header. */
+fn pes_header(data: &[u8]) -> Result<(u8, Option<u64>, Option<u64>, usize)> {
+    if data.len() < 6 || data[..3] != [0x00, 0x00, 0x01] {
+        return Err(err("PES packet_start_code_prefix missing"));
+    }
+    let stream_id = data[3];
+    /* Streams without the optional header fields, Table 2-22 */
+    match stream_id {
+        0xbc | 0xbe | 0xbf | 0xf0 | 0xf1 | 0xff | 0xf2 | 0xf8 =>
+            return Ok((stream_id, None, None, 6)),
+        _ => {},
+    }
+    if data.len() < 9 {
+        return Err(err("PES header past end of packet"));
+    }
+    let pts_dts_flags = data[7] >> 6;
+    let header_size = 9 + data[8] as usize;
+    if data.len() < header_size {
+        return Err(err("PES header past end of packet"));
+    }
+
+    let mut pts = None;
+    let mut dts = None;
+    if pts_dts_flags & 0x2 != 0 && header_size >= 14 {
+        pts = Some(timestamp(&data[9..14]));
+    }
+    if pts_dts_flags == 0x3 && header_size >= 19 {
+        dts = Some(timestamp(&data[14..19]));
+    }
+
+    Ok((stream_id, pts, dts, header_size))
+}
+
+impl<R: Read> TsReader<R> {
+    pub fn new(reader: R) -> TsReader<R> {
+        TsReader {
+            reader,
+            offset: 0,
+            pmt_pid: None,
+            es_pid: None,
+            continuity: HashMap::new(),
+            continuity_errors: Vec::new(),
+            sections: HashMap::new(),
+            pes: None,
+        }
+    }
+
+    /// PID of the H.264 elementary stream, once the PMT was read.
+    pub fn es_pid(&self) -> Option<u16> {
+        self.es_pid
+    }
+
+    /// Continuity counter errors found so far.
+    pub fn continuity_errors(&self) -> &[ContinuityError] {
+        &self.continuity_errors
+    }
+
+    /* Reads the next packet, skipping bytes up to the sync byte.
+     * Returns false at end of data. */
+    fn read_packet(&mut self, packet: &mut [u8; PACKET_SIZE])
+                   -> Result<bool> {
+        loop {
+            let n = read_all(&mut self.reader, &mut packet[..1])
+                .map_err(|e| io_err(ParserUnit::Ts(), e))?;
+            if n == 0 {
+                return Ok(false);
+            }
+            self.offset += 1;
+            if packet[0] == SYNC_BYTE {
+                break;
+            }
+        }
+        let n = read_all(&mut self.reader, &mut packet[1..])
+            .map_err(|e| io_err(ParserUnit::Ts(), e))?;
+        /* Truncated last packet */
+        if n < PACKET_SIZE - 1 {
+            return Ok(false);
+        }
+        self.offset += PACKET_SIZE as u64 - 1;
+
+        Ok(true)
+    }
+
+    /* Checks the continuity_counter of a packet with payload, true
+     * for a duplicate packet, which has the same counter */
+    fn check_continuity(&mut self, pid: u16, counter: u8, offset: u64,
+                        discontinuity: bool) -> bool {
+        let last = self.continuity.insert(pid, counter);
+        match last {
+            Some(last) if !discontinuity && counter == last => true,
+            Some(last) if !discontinuity &&
+                          counter != (last + 1) & 0x0f => {
+                self.continuity_errors.push(ContinuityError {
+                    pid,
+                    offset,
+                    expected: (last + 1) & 0x0f,
+                    found: counter,
+                });
+                false
+            },
+            _ => false,
+        }
+    }
+
+    /* Appends PSI data and parses the PAT or PMT once a section is
+     * complete */
+    fn section(&mut self, pid: u16, start: bool, payload: &[u8])
+               -> Result<()> {
+        let mut data = if start {
+            /* pointer_field */
+            let pointer = *payload.first().unwrap_or(&0) as usize;
+            match payload.get(1 + pointer..) {
+                Some(data) => data.to_vec(),
+                None => return Err(err("pointer_field past end of packet")),
+            }
+        }
+        else {
+            match self.sections.remove(&pid) {
+                Some(mut data) => {
+                    data.extend_from_slice(payload);
+                    data
+                },
+                None => return Ok(()),
+            }
+        };
+        if data.len() < 3 {
+            self.sections.insert(pid, data);
+            return Ok(());
+        }
+        let section_length = ((data[1] as usize & 0x0f) << 8) |
+            data[2] as usize;
+        if data.len() < 3 + section_length {
+            self.sections.insert(pid, data);
+            return Ok(());
+        }
+        data.truncate(3 + section_length);
+        if section_length < 9 {
+            return Err(err("section too short"));
+        }
+        /* Without the CRC_32 */
+        let end = data.len() - 4;
+
+        match data[0] {
+            /* program_association_section, 2.4.4.3 */
+            0x00 if pid == 0 => {
+                for program in data[8..end].chunks_exact(4) {
+                    let program_number = (program[0] as u16) << 8 |
+                        program[1] as u16;
+                    if program_number != 0 {
+                        let pmt_pid = (program[2] as u16 & 0x1f) << 8 |
+                            program[3] as u16;
+                        self.pmt_pid = Some(pmt_pid);
+                        break;
+                    }
+                }
+            },
+            /* TS_program_map_section, 2.4.4.8 */
+            0x02 if Some(pid) == self.pmt_pid => {
+                if section_length < 13 {
+                    return Err(err("PMT too short"));
+                }
+                let program_info_length = ((data[10] as usize & 0x0f) << 8) |
+                    data[11] as usize;
+                let mut pos = 12 + program_info_length;
+                while pos + 5 <= end {
+                    let stream_type = data[pos];
+                    let es_pid = (data[pos + 1] as u16 & 0x1f) << 8 |
+                        data[pos + 2] as u16;
+                    let es_info_length =
+                        ((data[pos + 3] as usize & 0x0f) << 8) |
+                        data[pos + 4] as usize;
+                    if stream_type == STREAM_TYPE_H264 {
+                        self.es_pid = Some(es_pid);
+                        break;
+                    }
+                    pos += 5 + es_info_length;
+                }
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    /* Completes the PES packet being reassembled */
+    fn finish_pes(&mut self) -> Result<Option<Pes>> {
+        let mut pes = match self.pes.take() {
+            Some(pes) => pes,
+            None => return Ok(None),
+        };
+        let (stream_id, pts, dts, header_size) = pes_header(&pes.data)?;
+        pes.stream_id = stream_id;
+        pes.pts = pts;
+        pes.dts = dts.or(pts);
+        /* PES_packet_length is 0 for unbounded video packets */
+        let length = (pes.data[4] as usize) << 8 | pes.data[5] as usize;
+        if length != 0 && 6 + length < pes.data.len() {
+            pes.data.truncate(6 + length);
+        }
+        pes.data.drain(..header_size);
+
+        Ok(Some(pes))
+    }
+
+    /// Reads the next PES packet of the H.264 elementary stream, None
+    /// at end of data.
+    pub fn next_pes(&mut self) -> Result<Option<Pes>> {
+        let mut packet = [0; PACKET_SIZE];
+
+        loop {
+            let offset = self.offset;
+            if !self.read_packet(&mut packet)? {
+                return self.finish_pes();
+            }
+            let transport_error = packet[1] & 0x80 != 0;
+            let start = packet[1] & 0x40 != 0;
+            let pid = (packet[1] as u16 & 0x1f) << 8 | packet[2] as u16;
+            let adaptation_field_control = packet[3] >> 4 & 0x03;
+            let counter = packet[3] & 0x0f;
+            if transport_error || pid == 0x1fff {
+                continue;
+            }
+
+            let mut payload_start = 4;
+            let mut discontinuity = false;
+            if adaptation_field_control & 0x2 != 0 {
+                let length = packet[4] as usize;
+                discontinuity = length > 0 && packet[5] & 0x80 != 0;
+                payload_start = 5 + length;
+            }
+            if adaptation_field_control & 0x1 == 0 ||
+               payload_start >= PACKET_SIZE {
+                continue;
+            }
+            if self.check_continuity(pid, counter, offset, discontinuity) {
+                continue;
+            }
+            let payload = &packet[payload_start..];
+
+            if pid == 0 || Some(pid) == self.pmt_pid {
+                self.section(pid, start, payload)?;
+            }
+            else if Some(pid) == self.es_pid {
+                let pes = if start { self.finish_pes()? } else { None };
+                if start {
+                    self.pes = Some(Pes {
+                        offset,
+                        stream_id: 0,
+                        pts: None,
+                        dts: None,
+                        data: Vec::new(),
+                    });
+                }
+                /* Data before the first start is dropped */
+                if let Some(ref mut current) = self.pes {
+                    current.data.extend_from_slice(payload);
+                }
+                if pes.is_some() {
+                    return Ok(pes);
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Demuxer for TsReader<R> {
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.next_pes()?.map(|pes| pes.data))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use demux::tests::nal_types;
+    use super::*;
+
+    /* Transport packet with payload, stuffed by the adaptation field */
+    fn packet(pid: u16, start: bool, counter: u8, payload: &[u8])
+              -> Vec<u8> {
+        let mut packet = vec![
+            SYNC_BYTE,
+            (start as u8) << 6 | (pid >> 8) as u8,
+            pid as u8,
+            0x10 | counter,
+        ];
+        let stuffing = PACKET_SIZE - 4 - payload.len();
+        if stuffing > 0 {
+            packet[3] |= 0x20;
+            packet.push(stuffing as u8 - 1);
+            if stuffing > 1 {
+                packet.push(0x00);
+                packet.extend(vec![0xff; stuffing - 2]);
+            }
+        }
+        packet.extend_from_slice(payload);
+
+        packet
+    }
+
+    /* Section with pointer_field and a dummy CRC_32 */
+    fn section(table_id: u8, content: &[u8]) -> Vec<u8> {
+        let length = content.len() + 5 + 4;
+        let mut section = vec![0x00, table_id, 0xb0 | (length >> 8) as u8,
+                               length as u8, 0x00, 0x01, 0xc1, 0x00, 0x00];
+        section.extend_from_slice(content);
+        section.extend_from_slice(&[0x00; 4]);
+
+        section
+    }
+
+    fn stream() -> Vec<u8> {
+        /* Program 1 with PMT on PID 0x100 */
+        let pat = section(0x00, &[0x00, 0x01, 0xe1, 0x00]);
+        /* PCR PID 0x101, AAC on 0x102 and H.264 on 0x101 */
+        let pmt = section(0x02, &[0xe1, 0x01, 0xf0, 0x00,
+                                  0x0f, 0xe1, 0x02, 0xf0, 0x00,
+                                  0x1b, 0xe1, 0x01, 0xf0, 0x00]);
+        /* PES with PTS 180000 and DTS 176400, AUD and SPS */
+        let pes1 = [
+            0x00, 0x00, 0x01, 0xe0, 0x00, 0x00, 0x80, 0xc0, 0x0a,
+            0x31, 0x00, 0x0b, 0x7e, 0x41, 0x11, 0x00, 0x0b, 0x62, 0x21,
+            0x00, 0x00, 0x00, 0x01, 0x09, 0xf0,
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0xc0, 0x1e,
+        ];
+        let pes1_rest = [0xed, 0x02, 0x83, 0xf2];
+        /* PES with only PTS, AUD */
+        let pes2 = [
+            0x00, 0x00, 0x01, 0xe0, 0x00, 0x00, 0x80, 0x80, 0x05,
+            0x21, 0x00, 0x01, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x01, 0x09, 0xf0,
+        ];
+
+        let mut stream = Vec::new();
+        stream.extend(packet(0x000, true, 0, &pat));
+        stream.extend(packet(0x100, true, 0, &pmt));
+        stream.extend(packet(0x101, true, 0, &pes1));
+        stream.extend(packet(0x101, false, 1, &pes1_rest));
+        /* Counter 3 instead of 2 */
+        stream.extend(packet(0x101, true, 3, &pes2));
+
+        stream
+    }
+
+    #[test]
+    fn read_pes() {
+        let mut reader = TsReader::new(Cursor::new(stream()));
+
+        let pes1 = reader.next_pes().unwrap().unwrap();
+        let pes2 = reader.next_pes().unwrap().unwrap();
+
+        assert_eq!(reader.es_pid(), Some(0x101));
+        assert_eq!(pes1.offset, 2 * PACKET_SIZE as u64);
+        assert_eq!(pes1.stream_id, 0xe0);
+        assert_eq!(pes1.pts, Some(180000));
+        assert_eq!(pes1.dts, Some(176400));
+        assert_eq!(pes1.data.len(), 18);
+        assert_eq!(pes2.pts, Some(0));
+        assert_eq!(pes2.dts, Some(0));
+        assert!(reader.next_pes().unwrap().is_none());
+    }
+
+    #[test]
+    fn continuity_error() {
+        let mut reader = TsReader::new(Cursor::new(stream()));
+
+        while reader.next_pes().unwrap().is_some() {}
+
+        assert_eq!(reader.continuity_errors(), [ContinuityError {
+            pid: 0x101,
+            offset: 4 * PACKET_SIZE as u64,
+            expected: 2,
+            found: 3,
+        }]);
+    }
+
+    #[test]
+    fn duplicate_packet() {
+        let mut data = stream();
+        let duplicate = data[3 * PACKET_SIZE..4 * PACKET_SIZE].to_vec();
+        data.splice(4 * PACKET_SIZE..4 * PACKET_SIZE, duplicate);
+        let mut reader = TsReader::new(Cursor::new(data));
+
+        let pes1 = reader.next_pes().unwrap().unwrap();
+        while reader.next_pes().unwrap().is_some() {}
+
+        assert_eq!(pes1.data.len(), 18);
+        assert_eq!(reader.continuity_errors().len(), 1);
+        assert_eq!(reader.continuity_errors()[0].found, 3);
+    }
+
+    /* section_length of 10 leaves a single byte for the programs */
+    #[test]
+    fn truncated_program() {
+        let pat = section(0x00, &[0x00]);
+        let data = packet(0x000, true, 0, &pat);
+        let mut reader = TsReader::new(Cursor::new(data));
+
+        assert!(reader.next_pes().unwrap().is_none());
+        assert_eq!(reader.es_pid(), None);
+    }
+
+    #[test]
+    fn read_annex_b() {
+        let reader = TsReader::new(Cursor::new(stream()));
+
+        let types = nal_types(reader);
+
+        assert_eq!(types, [9, 7, 9]);
+    }
+}
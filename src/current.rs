@@ -3,25 +3,43 @@ use std::io::prelude::*;
 use parser::bitreader::BitReader;
 use parser::nalunit::NalUnit;
 use parser::nalunit::NalPayload;
+use parser::paramsets::{ParameterSets, ParameterSetUpdate};
+use parser::accessunit::{AccessUnit, AccessUnitAggregator};
+use parser::trace::TraceElement;
 use parser::ParserError;
 
 pub struct Current {
+    /* Byte offset of the NAL in the stream, after the start code */
+    pub offset: usize,
+    /* Start code of the NAL was preceded by a zero_byte */
+    pub long_start_code: bool,
     pub nal: Option<NalUnit>,
     pub payload: Option<NalPayload>,
     pub parser_error: Option<ParserError>,
     pub rbsp: Option<Vec<u8>>,
+    pub parameter_sets: ParameterSets,
+    pub parameter_set_update: Option<ParameterSetUpdate>,
+    pub access_units: AccessUnitAggregator,
+    /* Set when the current NAL started a new access unit, or when
+     * reaching end of data, to the access unit before it */
+    pub access_unit: Option<AccessUnit>,
+    /* Number of NALs that failed to parse so far, units that are not
+     * implemented are not counted */
+    pub errors: usize,
+    /* Syntax elements of the current payload, when set */
+    pub trace: Option<Vec<TraceElement>>,
 }
 
-fn reposition<R: Read>(r: &mut BitReader<R>) -> bool {
+fn reposition<R: BufRead>(r: &mut BitReader<R>) -> bool {
     match NalUnit::next(r) {
         /* Non recoverable */
         Err(s) => {
-            println!("Error: {:?}", s);
-            return false;
+            eprintln!("Error: {:?}", s);
+            false
         },
         Ok(false) => {
-            println!("No start of nal found!");
-            return false;
+            eprintln!("No start of nal found!");
+            false
         },
         Ok(true) => true,
     }
@@ -30,43 +48,86 @@ fn reposition<R: Read>(r: &mut BitReader<R>) -> bool {
 impl Current {
     pub fn new() -> Current {
         Current {
+            offset: 0,
+            long_start_code: false,
             nal: None,
             payload: None,
             parser_error: None,
             rbsp: None,
+            parameter_sets: ParameterSets::new(),
+            parameter_set_update: None,
+            access_units: AccessUnitAggregator::new(),
+            access_unit: None,
+            errors: 0,
+            trace: None,
         }
     }
 
     /* Return false when no more data */
-    pub fn next<R: Read>(&mut self, bitreader: &mut BitReader<R>) -> bool {
+    pub fn next<R: BufRead>(&mut self, bitreader: &mut BitReader<R>) -> bool {
         self.payload = None;
         self.parser_error = None;
         self.rbsp = None;
+        self.parameter_set_update = None;
+        self.access_unit = None;
+        if let Some(ref mut trace) = self.trace {
+            trace.clear();
+        }
 
-        if self.nal.is_none() {
-            if !reposition(bitreader) {
-                return false;
-            }
+        /* Previous NAL ended at end of data */
+        if bitreader.reached_end_of_data() ||
+           (self.nal.is_none() && !reposition(bitreader)) {
+            self.nal = None;
+            self.access_unit = self.access_units.flush();
+            return false;
         }
 
+        self.offset = bitreader.pos;
+        self.long_start_code = bitreader.long_start_code();
         let parsed_nal = NalUnit::parse(bitreader);
         if parsed_nal.is_err() {
             self.parser_error = parsed_nal.err();
+            self.count_error();
             self.nal = None;
             return true;
         }
 
         let (mut nal, rbsp) = parsed_nal.unwrap();
-        let parsed_payload = nal.parse_payload(&rbsp);
+        let parsed_payload = match self.trace {
+            Some(ref mut trace) =>
+                nal.parse_payload_traced(&rbsp, &self.parameter_sets, trace),
+            None => nal.parse_payload(&rbsp, &self.parameter_sets),
+        };
         self.nal = Some(nal);
         self.rbsp = Some(rbsp);
         if parsed_payload.is_err() {
             self.parser_error = parsed_payload.err();
+            self.count_error();
+            self.push_access_unit_nal();
             return true;
         }
 
-        self.payload = Some(parsed_payload.unwrap());
+        let payload = parsed_payload.unwrap();
+        self.parameter_set_update = self.parameter_sets.update(&payload);
+        self.payload = Some(payload);
+        self.push_access_unit_nal();
 
-        return true;
+        true
+    }
+
+    fn count_error(&mut self) {
+        match self.parser_error {
+            Some(ParserError::InvalidStream(_)) |
+            Some(ParserError::BitReaderError(_)) => self.errors += 1,
+            _ => {},
+        }
+    }
+
+    fn push_access_unit_nal(&mut self) {
+        if let Some(ref nal) = self.nal {
+            self.access_unit = self.access_units.push(nal.clone(),
+                                                      self.payload.clone(),
+                                                      &self.parameter_sets);
+        }
     }
 }
@@ -0,0 +1,48 @@
+use std::io::BufRead;
+
+use serde_json::Value;
+
+use parser::bitreader::BitReader;
+use current::Current;
+
+/* One record per NAL, nal and payload are null when they failed to
+ * parse and error is null when parsing succeeded */
+fn record(current: &Current) -> Value {
+    json!({
+        "offset": current.offset,
+        "nal": current.nal,
+        "payload": current.payload,
+        "error": current.parser_error,
+    })
+}
+
+/// Prints all units of the stream as JSON, an array of records or,
+/// when `lines` is set, one record per line.
+/// Returns the number of units that failed to parse.
+pub fn dump<R: BufRead>(input: R, lines: bool) -> usize {
+    let mut bitreader = BitReader::new(input);
+    let mut current = Current::new();
+    let mut first = true;
+
+    if !lines {
+        println!("[");
+    }
+    while current.next(&mut bitreader) {
+        let record = record(&current);
+        if lines {
+            println!("{}", record);
+            continue;
+        }
+        if !first {
+            println!(",");
+        }
+        print!("{}", serde_json::to_string_pretty(&record).unwrap());
+        first = false;
+    }
+    if !lines {
+        println!();
+        println!("]");
+    }
+
+    current.errors
+}
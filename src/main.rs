@@ -1,19 +1,439 @@
 use std::fs::File;
 use std::env;
+use std::process;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, Cursor};
 
 extern crate parser;
+#[macro_use]
+extern crate serde_json;
 
 mod current;
 mod shell;
+mod json;
+mod trace;
+mod rewrite;
+
+use parser::mp4::Mp4Reader;
+use parser::ts::TsReader;
+use parser::rtp::{RtpReader, SequenceError};
+use parser::mkv::MkvReader;
+use parser::flv::FlvReader;
+use parser::demux::{AnnexBReader, Demuxer};
+use parser::probe::{self, Format};
+use parser::ParserError;
+use rewrite::SpsEdits;
+use shell::{PesTime, PesTimes};
+
+enum Mode {
+    Interactive,
+    Dump,
+    Json,
+    JsonLines,
+    Trace,
+    Commands(Vec<String>),
+    Rewrite(String),
+}
+
+fn print_usage() {
+    println!("Usage: vidpar [options] [h264 file]");
+    println!("The h264 file - reads stdin, which is not possible in the interactive shell.");
+    println!("The file format is detected: raw H.264, MP4, MPEG-TS, Matroska, FLV or RTP in a pcap capture.");
+    println!("  -d | --dump - Prints all units and exits.");
+    println!("  --json - Prints all units as a JSON array and exits.");
+    println!("  --jsonl - Prints all units as JSON Lines and exits.");
+    println!("  --trace - Prints a JM decoder compatible syntax element trace and exits.");
+    println!("  -c <commands> - Runs shell commands separated by ';' and exits.");
+    println!("  -f <file> - Runs shell commands from file, one per line, and exits.");
+    println!("  --rewrite <file> - Writes the stream to file, - for stdout, with the SPS fields below");
+    println!("      changed, other units are copied unchanged, and exits.");
+    println!("  --level <level> - Sets level_idc, e.g. 41 or 4.1.");
+    println!("  --sar <w>:<h> - Sets the VUI sample aspect ratio.");
+    println!("  --colour <primaries>,<transfer>,<matrix> - Sets the VUI colour description.");
+    println!("  --timing <num_units_in_tick>:<time_scale>[:fixed] - Sets the VUI timing info.");
+    println!("  --reorder <max_num_reorder_frames>:<max_dec_frame_buffering> - Sets the");
+    println!("      VUI bitstream restriction.");
+    println!("  --format <{}> - Reads the file as this format instead of detecting it.",
+             Format::names().join("|"));
+    println!("  -h | --help - Shows this text.");
+    println!("Exit status is 1 when units failed to parse in dump, trace, command and");
+    println!("rewrite mode, units not supported by the parser do not count, 2 on usage");
+    println!("or file errors.");
+}
+
+fn read_commands(path: &str) -> Option<Vec<String>> {
+    let mut content = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            if file.read_to_string(&mut content).is_err() {
+                println!("Unable to read command file: {}", path);
+                return None;
+            }
+        },
+        Err(_) => {
+            println!("Unable to open command file: {}", path);
+            return None;
+        },
+    }
+
+    Some(content.lines().map(String::from).collect())
+}
+
+/* Values separated by `separator`, None unless there are `min` to
+ * `max` values that all parse */
+fn parse_values<T: std::str::FromStr>(arg: &str, separator: char,
+                                      min: usize, max: usize)
+                                      -> Option<Vec<T>> {
+    let values: Vec<&str> = arg.split(separator).collect();
+    if values.len() < min || values.len() > max {
+        println!("Invalid value: {}", arg);
+        return None;
+    }
+
+    let parsed: Vec<T> = values.iter().filter_map(|v| v.parse().ok())
+        .collect();
+    if parsed.len() != values.len() {
+        println!("Invalid value: {}", arg);
+        return None;
+    }
+
+    Some(parsed)
+}
+
+/* level_idc as a number or as the level, e.g. 4.1 */
+fn parse_level(arg: &str) -> Option<u8> {
+    let level = match arg.find('.') {
+        None => arg.parse().ok(),
+        Some(_) => match parse_values::<u8>(arg, '.', 2, 2) {
+            Some(ref v) if v[0] < 25 && v[1] < 10 => Some(v[0] * 10 + v[1]),
+            _ => None,
+        },
+    };
+    if level.is_none() {
+        println!("Invalid level: {}", arg);
+    }
+
+    level
+}
+
+fn parse_timing(arg: &str) -> Option<(u32, u32, bool)> {
+    let fixed = arg.ends_with(":fixed");
+    let arg = arg.trim_end_matches(":fixed");
+    let values = parse_values::<u32>(arg, ':', 2, 2)?;
+    if values[0] == 0 || values[1] == 0 {
+        println!("Invalid timing: {}", arg);
+        return None;
+    }
+
+    Some((values[0], values[1], fixed))
+}
+
+fn parse_reorder(arg: &str) -> Option<(u8, u8)> {
+    let values = parse_values::<u8>(arg, ':', 2, 2)?;
+    if values[0] > values[1] {
+        println!("max_num_reorder_frames larger than \
+                  max_dec_frame_buffering: {}", arg);
+        return None;
+    }
+
+    Some((values[0], values[1]))
+}
+
+fn parse_format(arg: &str) -> Option<Format> {
+    let format = Format::from_name(arg);
+    if format.is_none() {
+        println!("Unknown format: {}", arg);
+    }
+
+    format
+}
+
+/* Returns the mode, the SPS edits, the format when given and the path
+ * to the h264 file, None on usage error */
+fn parse_args() -> Option<(Mode, SpsEdits, Option<Format>, String)> {
+    let mut mode = Mode::Interactive;
+    let mut edits = SpsEdits::default();
+    let mut format = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-d" | "--dump" => mode = Mode::Dump,
+            "--json" => mode = Mode::Json,
+            "--jsonl" => mode = Mode::JsonLines,
+            "--trace" => mode = Mode::Trace,
+            "-c" => {
+                let commands = args.next()?;
+                mode = Mode::Commands(commands.split(';')
+                                      .map(|c| c.trim().to_string())
+                                      .collect());
+            },
+            "-f" => mode = Mode::Commands(read_commands(&args.next()?)?),
+            "--rewrite" => mode = Mode::Rewrite(args.next()?),
+            "--level" => edits.level_idc = Some(parse_level(&args.next()?)?),
+            "--sar" => {
+                let sar = parse_values::<u16>(&args.next()?, ':', 2, 2)?;
+                edits.sar = Some((sar[0], sar[1]));
+            },
+            "--colour" => {
+                let colour = parse_values::<u8>(&args.next()?, ',', 3, 3)?;
+                edits.colour = Some((colour[0], colour[1], colour[2]));
+            },
+            "--timing" => edits.timing = Some(parse_timing(&args.next()?)?),
+            "--reorder" => edits.reorder = Some(parse_reorder(&args.next()?)?),
+            "--format" => format = Some(parse_format(&args.next()?)?),
+            "-h" | "--help" => return None,
+            "-" if path.is_none() => path = Some(arg),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                println!("Unknown argument: {}", arg);
+                return None;
+            },
+        }
+    }
+
+    if matches!(mode, Mode::Interactive) && path.as_deref() == Some("-") {
+        println!("The shell reads commands from stdin, use -d, -c or -f");
+        return None;
+    }
+    let rewrite = matches!(mode, Mode::Rewrite(_));
+    if rewrite == edits.is_empty() {
+        println!("--rewrite requires SPS fields to change and vice versa");
+        return None;
+    }
+
+    Some((mode, edits, format, path.unwrap_or("sw.h264".to_string())))
+}
+
+fn demuxer<D: Demuxer + 'static>(demuxer: Result<D, ParserError>,
+                                 format: Format) -> Option<Box<dyn BufRead>> {
+    match demuxer {
+        Ok(demuxer) => Some(Box::new(AnnexBReader::new(demuxer))),
+        Err(e) => {
+            println!("Unable to read {} file: {}", format, e);
+            None
+        },
+    }
+}
+
+/* RtpReader printing the sequence number errors and skipped packets
+ * to stderr at end of stream */
+struct RtpReport<R> {
+    reader: RtpReader<R>,
+    reported: bool,
+}
+
+impl<R: Read> RtpReport<R> {
+    fn new(reader: RtpReader<R>) -> RtpReport<R> {
+        RtpReport { reader, reported: false }
+    }
+
+    fn report(&self) {
+        for error in self.reader.sequence_errors() {
+            match *error {
+                SequenceError::Gap { expected, found }
+                    if found == expected.wrapping_add(1) =>
+                    eprintln!("RTP packet {} missing", expected),
+                SequenceError::Gap { expected, found } =>
+                    eprintln!("RTP packets {} to {} missing", expected,
+                              found.wrapping_sub(1)),
+                SequenceError::Reordered { expected, found } =>
+                    eprintln!("RTP packet {} late, expected {}", found,
+                              expected),
+            }
+        }
+        if self.reader.skipped() > 0 {
+            eprintln!("{} RTP packets without NAL unit skipped",
+                      self.reader.skipped());
+        }
+    }
+}
+
+impl<R: Read> Demuxer for RtpReport<R> {
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>, ParserError> {
+        let annex_b = self.reader.next_annex_b()?;
+        if annex_b.is_none() && !self.reported {
+            self.reported = true;
+            self.report();
+        }
+
+        Ok(annex_b)
+    }
+}
+
+/* TsReader recording the PES timestamps for the shell and printing
+ * the continuity errors to stderr at end of stream */
+struct TsReport<R> {
+    reader: TsReader<R>,
+    times: PesTimes,
+    /* Annex B bytes returned so far */
+    offset: usize,
+    reported: bool,
+}
+
+impl<R: Read> TsReport<R> {
+    fn new(reader: TsReader<R>, times: PesTimes) -> TsReport<R> {
+        TsReport { reader, times, offset: 0, reported: false }
+    }
+
+    fn report(&self) {
+        for error in self.reader.continuity_errors() {
+            eprintln!("Transport packet of PID {:#x} at offset {} has \
+                       continuity_counter {}, expected {}", error.pid,
+                      error.offset, error.found, error.expected);
+        }
+    }
+}
+
+impl<R: Read> Demuxer for TsReport<R> {
+    fn next_annex_b(&mut self) -> Result<Option<Vec<u8>>, ParserError> {
+        match self.reader.next_pes()? {
+            Some(pes) => {
+                self.times.borrow_mut().push(PesTime {
+                    offset: self.offset,
+                    pts: pes.pts,
+                    dts: pes.dts,
+                });
+                self.offset += pes.data.len();
+                Ok(Some(pes.data))
+            },
+            None => {
+                if !self.reported {
+                    self.reported = true;
+                    self.report();
+                }
+                Ok(None)
+            },
+        }
+    }
+}
+
+/* Mp4Reader seeking in a regular file */
+fn mp4_file(reader: BufReader<File>) -> Option<Box<dyn BufRead>> {
+    demuxer(Mp4Reader::new(reader), Format::Mp4)
+}
+
+/* Mp4Reader of a stream that can not seek, read into memory first
+ * since samples are located by offset */
+fn mp4_stream<R: Read>(mut reader: BufReader<R>)
+                       -> Option<Box<dyn BufRead>> {
+    let mut data = Vec::new();
+    if reader.read_to_end(&mut data).is_err() {
+        println!("Unable to read MP4 file");
+        return None;
+    }
+
+    demuxer(Mp4Reader::new(Cursor::new(data)), Format::Mp4)
+}
+
+/* The H.264 stream of `reader` as Annex B bytes, read with the
+ * demuxer of `format` or of the format detected from the first bytes,
+ * and the format. Only `mp4` needs to seek. The PES timestamps of a
+ * transport stream are added to `times`. */
+fn open<R, F>(mut reader: BufReader<R>, format: Option<Format>, mp4: F,
+              times: &PesTimes) -> Option<(Box<dyn BufRead>, Format)>
+    where R: Read + 'static,
+          F: FnOnce(BufReader<R>) -> Option<Box<dyn BufRead>> {
+    let format = match format {
+        Some(format) => format,
+        None => {
+            let head = match reader.fill_buf() {
+                Ok(head) => head,
+                Err(_) => {
+                    println!("Unable to read h264 file");
+                    return None;
+                },
+            };
+            probe::probe(head).unwrap_or_else(|| {
+                eprintln!("Unknown format, reading as Annex B");
+                Format::AnnexB
+            })
+        },
+    };
+
+    let input = match format {
+        Format::AnnexB => Some(Box::new(reader) as Box<dyn BufRead>),
+        Format::Mp4 => mp4(reader),
+        Format::MpegTs => {
+            let reader = TsReader::new(reader);
+            demuxer(Ok(TsReport::new(reader, times.clone())), format)
+        },
+        Format::Matroska => demuxer(MkvReader::new(reader), format),
+        Format::Flv => demuxer(FlvReader::new(reader), format),
+        Format::Pcap => demuxer(RtpReader::new(reader, None)
+                                .map(RtpReport::new), format),
+    };
+
+    input.map(|input| (input, format))
+}
 
 fn main() {
-    /* Retrieve path to h264 file */
-    let path = env::args().nth(1).unwrap_or("sw.h264".to_string());
-    let file = File::open(&path);
-    if file.is_err() {
-        println!("Unable to open h264 file: {}", path);
-        return;
+    let (mode, edits, format, path) = match parse_args() {
+        Some(args) => args,
+        None => {
+            print_usage();
+            process::exit(2);
+        },
+    };
+
+    /* Retrieve path to h264 file, None for stdin */
+    let file = match path.as_str() {
+        "-" => None,
+        _ => match File::open(&path) {
+            Ok(file) => Some(file),
+            Err(_) => {
+                println!("Unable to open h264 file: {}", path);
+                process::exit(2);
+            },
+        },
+    };
+    if let Mode::Rewrite(ref output) = mode {
+        let errors = match file {
+            Some(file) => rewrite::rewrite(file, output, &edits, format),
+            None => rewrite::rewrite(io::stdin(), output, &edits, format),
+        };
+        match errors {
+            Some(0) => return,
+            Some(errors) => {
+                eprintln!("{} units failed to parse", errors);
+                process::exit(1);
+            },
+            None => process::exit(2),
+        }
     }
+    let times = PesTimes::default();
+    let opened = match file {
+        /* Named pipes and devices can not seek */
+        Some(file) => match file.metadata() {
+            Ok(ref metadata) if metadata.is_file() =>
+                open(BufReader::new(file), format, mp4_file, &times),
+            _ => open(BufReader::new(file), format, mp4_stream, &times),
+        },
+        None => open(BufReader::new(io::stdin()), format, mp4_stream,
+                     &times),
+    };
+    let (input, format) = match opened {
+        Some(opened) => opened,
+        None => process::exit(2),
+    };
 
-    shell::eval_loop(file.unwrap());
+    let errors = match mode {
+        Mode::Interactive => {
+            shell::eval_loop(input, format, &times);
+            0
+        },
+        Mode::Dump => shell::dump(input, &times),
+        Mode::Json => json::dump(input, false),
+        Mode::JsonLines => json::dump(input, true),
+        Mode::Trace => trace::dump(input),
+        Mode::Commands(commands) =>
+            shell::eval_commands(input, &commands, &times),
+        Mode::Rewrite(_) => 0,
+    };
+    if errors > 0 {
+        eprintln!("{} units failed to parse", errors);
+        process::exit(1);
+    }
 }
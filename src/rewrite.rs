@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use parser::bitreader::BitReader;
+use parser::nalunit::{NalUnit, NalPayload};
+use parser::paramsets::ParameterSets;
+use parser::sps::{SequenceParameterSet, VideoUsabilityInformation};
+use parser::probe::{self, Format};
+use parser::ParserError;
+
+/// SPS fields to change, None leaves the field as it is.
+#[derive(Default)]
+pub struct SpsEdits {
+    pub level_idc: Option<u8>,
+    /* Sample aspect ratio width and height */
+    pub sar: Option<(u16, u16)>,
+    /* colour_primaries, transfer_characteristics, matrix_coefficients */
+    pub colour: Option<(u8, u8, u8)>,
+    /* num_units_in_tick, time_scale, fixed_frame_rate_flag */
+    pub timing: Option<(u32, u32, bool)>,
+    /* max_num_reorder_frames, max_dec_frame_buffering */
+    pub reorder: Option<(u8, u8)>,
+}
+
+impl SpsEdits {
+    pub fn is_empty(&self) -> bool {
+        self.level_idc.is_none() && self.sar.is_none() &&
+            self.colour.is_none() && self.timing.is_none() &&
+            self.reorder.is_none()
+    }
+
+    /* Changes the fields of `sps`, VUI is added when not present */
+    fn apply(&self, sps: &mut SequenceParameterSet) {
+        if let Some(level_idc) = self.level_idc {
+            sps.level_idc = level_idc;
+        }
+        if self.sar.is_none() && self.colour.is_none() &&
+           self.timing.is_none() && self.reorder.is_none() {
+            return;
+        }
+
+        sps.vui_parameters_present_flag = true;
+        let vui = sps.vui_parameters
+            .get_or_insert_with(VideoUsabilityInformation::default);
+        if let Some((width, height)) = self.sar {
+            vui.set_sar(width, height);
+        }
+        if let Some((primaries, transfer, matrix)) = self.colour {
+            vui.video_signal_type_present_flag = true;
+            vui.colour_description_present_flag = true;
+            vui.colour_primaries = primaries;
+            vui.transfer_characteristics = transfer;
+            vui.matrix_coefficients = matrix;
+        }
+        if let Some((num_units_in_tick, time_scale, fixed)) = self.timing {
+            vui.timing_info_present_flag = true;
+            vui.num_units_in_tick = num_units_in_tick;
+            vui.time_scale = time_scale;
+            vui.fixed_frame_rate_flag = fixed;
+        }
+        if let Some((reorder, buffering)) = self.reorder {
+            /* Elements not present before keep their inferred values */
+            vui.bitstream_restriction_flag = true;
+            vui.max_num_reorder_frames = reorder;
+            vui.max_dec_frame_buffering = buffering;
+        }
+    }
+}
+
+/* Start of the next start code prefix 0x000001 at or after `from` */
+fn find_start_code(bytes: &[u8], from: usize) -> Option<usize> {
+    bytes[from..].windows(3)
+        .position(|w| w == [0x00, 0x00, 0x01])
+        .map(|i| from + i)
+}
+
+/* The NAL unit `nal`, without start code and trailing zero bytes,
+ * with the SPS edits applied */
+fn rewrite_sps(nal: &[u8], edits: &SpsEdits)
+               -> Result<Vec<u8>, ParserError> {
+    let mut reader = BitReader::new(nal);
+    let (mut nal, rbsp) = NalUnit::parse(&mut reader)?;
+    let mut payload = nal.parse_payload(&rbsp, &ParameterSets::new())?;
+    if let NalPayload::SequenceParameterSet(ref mut sps) = payload {
+        edits.apply(sps);
+    }
+    let bytes = nal.write(&payload)?;
+
+    /* Without the start code */
+    Ok(bytes[4..].to_vec())
+}
+
+/// Writes the stream in `input` to `output` with the edits applied to
+/// all SPS. Other NAL units, start codes and zero bytes are copied as
+/// they are. The stream is read into memory before writing, so
+/// `output` might be the same file, - writes to stdout.
+/// Only Annex B streams are rewritten, `format` is detected when None.
+/// Returns the number of SPS that failed to parse and were copied
+/// unchanged, None when the output could not be written.
+pub fn rewrite<R: Read>(mut reader: R, output: &str, edits: &SpsEdits,
+                        format: Option<Format>) -> Option<usize> {
+    let mut input = Vec::new();
+    if reader.read_to_end(&mut input).is_err() {
+        eprintln!("Unable to read h264 file");
+        return None;
+    }
+    match format.or_else(|| probe::probe(&input)) {
+        Some(Format::AnnexB) | None => {},
+        Some(format) => {
+            eprintln!("Unable to rewrite {} file, only Annex B streams",
+                      format);
+            return None;
+        },
+    }
+
+    let mut out = Vec::with_capacity(input.len());
+    let mut errors = 0;
+    let mut pos = match find_start_code(&input, 0) {
+        Some(start) => start,
+        None => input.len(),
+    };
+    out.extend_from_slice(&input[..pos]);
+    while pos < input.len() {
+        let start = pos + 3;
+        let next = find_start_code(&input, start).unwrap_or(input.len());
+        /* Zero bytes before the next start code are not part of the
+         * NAL unit */
+        let mut end = next;
+        while end > start && input[end - 1] == 0x00 {
+            end -= 1;
+        }
+        let nal = &input[start..end];
+
+        out.extend_from_slice(&input[pos..start]);
+        if !nal.is_empty() && nal[0] & 0x1f == 7 {
+            match rewrite_sps(nal, edits) {
+                Ok(sps) => out.extend_from_slice(&sps),
+                Err(e) => {
+                    eprintln!("SPS at byte {} not rewritten: {}", start, e);
+                    out.extend_from_slice(nal);
+                    errors += 1;
+                },
+            }
+        }
+        else {
+            out.extend_from_slice(nal);
+        }
+        out.extend_from_slice(&input[end..next]);
+        pos = next;
+    }
+
+    let written = match output {
+        "-" => io::stdout().write_all(&out),
+        _ => File::create(output).and_then(|mut file| file.write_all(&out)),
+    };
+    if written.is_err() {
+        eprintln!("Unable to write output file: {}", output);
+        return None;
+    }
+
+    Some(errors)
+}
@@ -1,71 +1,201 @@
 use std::io::prelude::*;
-use std::fs::File;
 use std::io;
 use std::io::stdout;
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use parser::bitreader::BitReader;
+use parser::paramsets::ParameterSetUpdate;
+use parser::accessunit::AccessUnit;
+use parser::aud::AccessUnitDelimiter;
+use parser::probe::Format;
 use current::Current;
 
+/// Timestamps of a PES packet of a transport stream.
+pub struct PesTime {
+    /// Byte offset of the PES packet data in the Annex B stream
+    pub offset: usize,
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+}
+
+/// Timestamps of the PES packets read so far, shared with the
+/// demuxer. Empty for other formats.
+pub type PesTimes = Rc<RefCell<Vec<PesTime>>>;
 
 fn print_help() {
     println!("n | next - Decodes next unit.");
+    println!("au - Decodes units until the end of the next access unit.");
     println!("q | quit - Quits program.");
     println!("? | help - Shows this text.");
     println!("nal - prints current nal.");
     println!("payload - prints current payload.");
     println!("bytes - prints payload raw bytes.");
+    println!("sps [id] - prints SPS with id, or the active SPS.");
+    println!("pps [id] - prints PPS with id, or the active PPS.");
+    println!("pes - prints PTS and DTS of the PES packet of current nal.");
 }
 
 fn print_curr_slim(curr: &Current) {
     match curr.nal {
         None => {
-            println!("Failed to parse NAL: {:?}",
+            println!("Failed to parse NAL: {}",
                      curr.parser_error.as_ref().unwrap());
         },
         Some(ref nal) => {
             print!("Parsed NAL of type {}. ", nal.nal_unit_type);
             match curr.payload {
-                None => println!("Failed to parse payload: {:?}",
+                None => println!("Failed to parse payload: {}",
                                  curr.parser_error.as_ref().unwrap()),
-                Some(ref _payload) => println!("Parsed {}",
-                                     curr.payload.as_ref().unwrap()),
+                Some(ref payload) => {
+                    print!("Parsed {}", payload);
+                    match curr.parameter_set_update {
+                        Some(ParameterSetUpdate::Redefined) =>
+                            println!(", redefined with different content"),
+                        _ => println!(),
+                    }
+                },
             }
         },
     }
 }
 
+fn print_access_unit(access_unit: &AccessUnit) {
+    let slice_types = AccessUnitDelimiter {
+        primary_pic_type: access_unit.primary_pic_type,
+    }.slice_types();
+    print!("Access unit with {} NALs, primary_pic_type {} {:?}",
+           access_unit.nals.len(), access_unit.primary_pic_type,
+           slice_types);
+    if access_unit.idr {
+        print!(", IDR");
+    }
+    if access_unit.recovery_point {
+        print!(", recovery point");
+    }
+    if let Some(ref poc) = access_unit.pic_order_cnt {
+        print!(", POC {}", poc.pic_order_cnt());
+        if let Some(top) = poc.top_field_order_cnt {
+            print!(" top {}", top);
+        }
+        if let Some(bottom) = poc.bottom_field_order_cnt {
+            print!(" bottom {}", bottom);
+        }
+    }
+    println!();
+    for (nal, payload) in &access_unit.nals {
+        match payload {
+            None => println!("  NAL of type {}", nal.nal_unit_type),
+            Some(payload) => println!("  NAL of type {}, {}",
+                                          nal.nal_unit_type, payload),
+        }
+    }
+}
+
 fn print_payload_bytes(curr: &Current) {
     if curr.rbsp.is_none() {
         println!("No rbsp");
         return;
     }
 
-    let mut i = 0;
     let num = 10;
-    for x in curr.rbsp.as_ref().unwrap() {
+    for (i, x) in curr.rbsp.as_ref().unwrap().iter().enumerate() {
         if i % num == 0 {
             if i > 0 {
-                println!("");
+                println!();
             }
             print!("{:08} ", i);
         }
         print!("{:02x} ", x);
-        i += 1;
     }
-    println!("");
+    println!();
+}
+
+/* Index of the PES packet containing the current NAL */
+fn pes_index(curr: &Current, times: &PesTimes) -> Option<usize> {
+    times.borrow().iter().rposition(|time| time.offset <= curr.offset)
+}
+
+fn print_pes_time(time: &PesTime) {
+    print!("PES packet");
+    if let Some(pts) = time.pts {
+        print!(", PTS {}", pts);
+    }
+    if let Some(dts) = time.dts {
+        print!(", DTS {}", dts);
+    }
+    println!();
+}
+
+fn parse_id(arg: Option<&str>) -> Option<Option<u8>> {
+    match arg {
+        None => Some(None),
+        Some(arg) => match arg.parse::<u8>() {
+            Ok(id) => Some(Some(id)),
+            Err(_) => {
+                println!("Invalid id: {}", arg);
+                None
+            },
+        },
+    }
+}
+
+fn print_sps(curr: &Current, arg: Option<&str>) {
+    let sps = match parse_id(arg) {
+        None => return,
+        Some(None) => curr.parameter_sets.active_sps(),
+        Some(Some(id)) => curr.parameter_sets.sps(id),
+    };
+    match sps {
+        None => println!("No SPS."),
+        Some(sps) => {
+            println!("{} profile, level {}, {}x{}",
+                     sps.profile_name(), sps.level(),
+                     sps.width(), sps.height());
+            println!("{:#?}", sps);
+        },
+    }
 }
 
-pub fn eval<R: Read>(command: String,
-                     current: &mut Current,
-                     bitreader: &mut BitReader<R>) -> bool {
-    match command.as_ref() {
+fn print_pps(curr: &Current, arg: Option<&str>) {
+    let pps = match parse_id(arg) {
+        None => return,
+        Some(None) => curr.parameter_sets.active_pps(),
+        Some(Some(id)) => curr.parameter_sets.pps(id),
+    };
+    match pps {
+        None => println!("No PPS."),
+        Some(pps) => println!("{:#?}", pps),
+    }
+}
+
+pub fn eval<R: BufRead>(command: String,
+                        current: &mut Current,
+                        bitreader: &mut BitReader<R>,
+                        times: &PesTimes) -> bool {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or("");
+    match name {
         "" => {},
         "q" | "quit" => return false,
         "?" | "help" => print_help(),
         "n" | "next" => {
             match current.next(bitreader) {
                 false => println!("Reached end of data"),
-                true => print_curr_slim(&current)
+                true => print_curr_slim(current)
+            }
+        },
+        "au" => {
+            loop {
+                let more = current.next(bitreader);
+                if let Some(ref access_unit) = current.access_unit {
+                    print_access_unit(access_unit);
+                    break;
+                }
+                if !more {
+                    println!("Reached end of data");
+                    break;
+                }
             }
         },
         "nal" => {
@@ -83,18 +213,66 @@ pub fn eval<R: Read>(command: String,
         "bytes" => {
             print_payload_bytes(current);
         },
+        "sps" => print_sps(current, words.next()),
+        "pps" => print_pps(current, words.next()),
+        "pes" => {
+            match pes_index(current, times) {
+                None => println!("No PES packet."),
+                Some(index) => print_pes_time(&times.borrow()[index]),
+            }
+        },
         _ => {
             println!("Unknown command: {}", command);
         },
     };
 
-    return true;
+    true
+}
+
+/// Runs the commands without prompting, stops at quit.
+/// Returns the number of units that failed to parse.
+pub fn eval_commands<R: BufRead>(input: R, commands: &[String],
+                                 times: &PesTimes) -> usize {
+    let mut bitreader = BitReader::new(input);
+    let mut current = Current::new();
+
+    for command in commands {
+        println!(">{}", command);
+        if !eval(command.clone(), &mut current, &mut bitreader, times) {
+            break;
+        }
+    }
+
+    current.errors
+}
+
+/// Prints all units of the stream, and the timestamps of each PES
+/// packet before its first unit.
+/// Returns the number of units that failed to parse.
+pub fn dump<R: BufRead>(input: R, times: &PesTimes) -> usize {
+    let mut bitreader = BitReader::new(input);
+    let mut current = Current::new();
+    let mut pes = None;
+
+    while current.next(&mut bitreader) {
+        let index = pes_index(&current, times);
+        if index != pes {
+            if let Some(index) = index {
+                print_pes_time(&times.borrow()[index]);
+            }
+            pes = index;
+        }
+        print_curr_slim(&current);
+    }
+
+    current.errors
 }
 
-pub fn eval_loop(file: File) {
-    let mut bitreader = BitReader::new(file);
+pub fn eval_loop<R: BufRead>(input: R, format: Format, times: &PesTimes) {
+    let mut bitreader = BitReader::new(input);
     let mut current = Current::new();
 
+    println!("Reading {} stream, ? for help.", format);
     loop {
         let mut input = String::new();
         /* Read next command */
@@ -106,7 +284,7 @@ pub fn eval_loop(file: File) {
         }
         /* Remove newline */
         input.pop();
-        if !eval(input, &mut current, &mut bitreader) {
+        if !eval(input, &mut current, &mut bitreader, times) {
             break;
         }
     }
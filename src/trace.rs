@@ -0,0 +1,34 @@
+use std::io::BufRead;
+use std::io::stdout;
+
+use parser::bitreader::BitReader;
+use parser::trace::JmTraceWriter;
+use current::Current;
+
+/// Prints the syntax elements of all units in the layout of the JM
+/// reference decoder trace.
+/// Returns the number of units that failed to parse.
+pub fn dump<R: BufRead>(input: R) -> usize {
+    let mut bitreader = BitReader::new(input);
+    let mut current = Current::new();
+    let stdout = stdout();
+    let mut writer = JmTraceWriter::new(stdout.lock());
+
+    current.trace = Some(Vec::new());
+    while current.next(&mut bitreader) {
+        let nal = match current.nal {
+            Some(ref nal) => nal,
+            None => continue,
+        };
+        /* NAL unit header and RBSP */
+        let len = current.rbsp.as_ref().map_or(0, |rbsp| rbsp.len()) + 1;
+        let trace = current.trace.as_ref().unwrap();
+        let written = writer.nal(nal, current.long_start_code, len)
+            .and_then(|_| writer.elements(nal.nal_unit_type, trace));
+        if written.is_err() {
+            break;
+        }
+    }
+
+    current.errors
+}